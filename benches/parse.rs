@@ -0,0 +1,39 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use hyper::header::{ContentType, Headers};
+use mime::{Attr, Mime, SubLevel, TopLevel, Value};
+use mime_multipart::read_multipart_body;
+
+// A multipart/form-data body made up of many small text fields
+fn many_small_fields(count: usize) -> Vec<u8> {
+    let mut body: Vec<u8> = Vec::new();
+    for i in 0..count {
+        body.extend(b"--AaB03x\r\n");
+        body.extend(format!("Content-Disposition: form-data; name=\"field{}\"\r\n", i).bytes());
+        body.extend(b"\r\n");
+        body.extend(format!("value of field number {}\r\n", i).bytes());
+    }
+    body.extend(b"--AaB03x--");
+    body
+}
+
+fn parse_many_small_parts(c: &mut Criterion) {
+    let body = many_small_fields(500);
+    let mut headers = Headers::new();
+    headers.set(ContentType(Mime(TopLevel::Multipart, SubLevel::FormData,
+                                 vec![(Attr::Boundary, Value::Ext("AaB03x".to_owned()))])));
+
+    c.bench_function("parse 500 small fields", |b| b.iter(|| {
+        let nodes = read_multipart_body(&mut &body[..], &headers, false).unwrap();
+        assert_eq!(nodes.len(), 500);
+    }));
+}
+
+criterion_group!(benches, parse_many_small_parts);
+criterion_main!(benches);
@@ -0,0 +1,134 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Writing of multipart bodies to an `AsyncWrite`, for async servers which must not block
+//! the executor on file I/O.  Only available with the `async` feature.
+
+use hyper::header::Headers;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use super::{get_multipart_boundary, write_headers, Error, FilePart, Node};
+
+/// Stream a multipart body to the async output `stream` given, made up of the `nodes`
+/// given, as `write_multipart()` does.  File parts are read with async file I/O.
+/// Top-level headers are NOT included in this stream; the caller must send those first.
+/// Returns the number of bytes written, or an error.
+pub async fn write_multipart_async<S>(stream: &mut S, boundary: &[u8], nodes: &[Node])
+    -> Result<usize, Error>
+    where S: AsyncWrite + Unpin + ?Sized
+{
+    let mut writer = AsyncMultipartWriter::new(stream, boundary);
+    for node in nodes {
+        writer.write_node(node).await?;
+    }
+    writer.finish().await
+}
+
+/// Writes a multipart body to an `AsyncWrite` a part at a time, as `MultipartWriter` does
+/// for a `Write`.  As well as `Node`s, it can write parts whose body is streamed from any
+/// `AsyncRead`, such as a `tokio::fs::File`.
+///
+/// Top-level headers are NOT included in this stream; the caller must send those first.
+/// `finish()` must be called to write the final boundary.
+pub struct AsyncMultipartWriter<'a, S: AsyncWrite + Unpin + ?Sized> {
+    stream: &'a mut S,
+    boundary: Vec<u8>,
+    count: usize,
+}
+
+impl<'a, S: AsyncWrite + Unpin + ?Sized> AsyncMultipartWriter<'a, S> {
+    /// A writer of a multipart body with the given `boundary` to `stream`
+    pub fn new(stream: &'a mut S, boundary: &[u8]) -> AsyncMultipartWriter<'a, S> {
+        AsyncMultipartWriter {
+            stream,
+            boundary: boundary.to_vec(),
+            count: 0,
+        }
+    }
+
+    /// Write a node
+    pub async fn write_node(&mut self, node: &Node) -> Result<(), Error> {
+        self.write_start(node.headers()).await?;
+        match node {
+            Node::Part(part) => {
+                self.stream.write_all(&part.body).await?;
+                self.count += part.body.len();
+            },
+            Node::File(filepart) => {
+                self.count += write_file_body(self.stream, filepart).await?;
+            },
+            Node::Multipart((headers, subnodes)) => {
+                let boundary = get_multipart_boundary(headers)?;
+                let nested = Box::pin(write_multipart_async(self.stream, &boundary[2..], subnodes));
+                self.count += nested.await?;
+            },
+        }
+        self.write_end().await
+    }
+
+    /// Write a part with the given `headers`, whose body is streamed from `body` until it
+    /// ends.
+    pub async fn write_part_from<R>(&mut self, headers: &Headers, body: &mut R)
+        -> Result<(), Error>
+        where R: AsyncRead + Unpin + ?Sized
+    {
+        self.write_start(headers).await?;
+        self.count += tokio::io::copy(body, self.stream).await? as usize;
+        self.write_end().await
+    }
+
+    /// Write the final boundary, ending the body, and flush the stream.  Returns the number
+    /// of bytes written in total.
+    pub async fn finish(self) -> Result<usize, Error> {
+        let mut end: Vec<u8> = b"--".to_vec();
+        end.extend_from_slice(&self.boundary);
+        end.extend_from_slice(b"--");
+        self.stream.write_all(&end).await?;
+        self.stream.flush().await?;
+        Ok(self.count + end.len())
+    }
+
+    // Write a boundary, the part's headers, and the blank line which ends them
+    async fn write_start(&mut self, headers: &Headers) -> Result<(), Error> {
+        let mut start: Vec<u8> = b"--".to_vec();
+        start.extend_from_slice(&self.boundary);
+        start.extend_from_slice(b"\r\n");
+        write_headers(&mut start, headers, b"\r\n")?;
+        start.extend_from_slice(b"\r\n");
+        self.stream.write_all(&start).await?;
+        self.count += start.len();
+        Ok(())
+    }
+
+    // Write the line terminator which ends a part
+    async fn write_end(&mut self) -> Result<(), Error> {
+        self.stream.write_all(b"\r\n").await?;
+        self.count += 2;
+        Ok(())
+    }
+}
+
+// Stream the content of a file part, wherever it is held.  Returns the number of bytes
+// written.
+async fn write_file_body<S>(stream: &mut S, filepart: &FilePart) -> Result<usize, Error>
+    where S: AsyncWrite + Unpin + ?Sized
+{
+    let written = match (&filepart.content, &filepart.file) {
+        (Some(content), _) => {
+            stream.write_all(content).await?;
+            content.len() as u64
+        },
+        (None, Some(file)) => {
+            let mut file = tokio::fs::File::from_std(file.rewound()?);
+            tokio::io::copy(&mut file, stream).await?
+        },
+        (None, None) => {
+            let mut file = tokio::fs::File::open(&filepart.path).await?;
+            tokio::io::copy(&mut file, stream).await?
+        },
+    };
+    Ok(written as usize)
+}
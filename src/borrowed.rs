@@ -0,0 +1,76 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Zero-copy parsing of a multipart body which is already in memory, into parts whose
+//! bodies borrow from it.
+
+use buf_read_ext::BufReadExt;
+use hyper::header::{ContentType, Headers};
+use mime::{Mime, TopLevel};
+use super::{get_multipart_boundary, parse_part_headers, read_first_boundary, read_header_lines,
+            skip_transport_padding, Error, ParseOptions};
+
+/// A part parsed from an in-memory body, whose body is a slice of that body rather than a
+/// copy of it
+#[derive(Clone, Debug, PartialEq)]
+pub struct BorrowedPart<'a> {
+    /// The headers of the part
+    pub headers: Headers,
+    /// The body of the part, exactly as it appears in the input
+    pub body: &'a [u8],
+}
+
+impl<'a> BorrowedPart<'a> {
+    /// Whether this part is itself a `multipart/*`, whose body can be parsed in turn with
+    /// `read_multipart_slice()` given this part's headers
+    pub fn is_multipart(&self) -> bool {
+        matches!(self.headers.get::<ContentType>(),
+                 Some(&ContentType(Mime(TopLevel::Multipart, _, _))))
+    }
+}
+
+/// Parse a MIME `multipart/*` body held in memory into its parts, as
+/// `read_multipart_body()` does, but without copying: each part's body borrows from
+/// `input`.  Nothing is streamed to files, and nested `multipart/*` parts are returned as
+/// parts like any other (see `BorrowedPart::is_multipart()`).
+///
+/// The `headers` must carry the `Content-Type` which gives the boundary.
+pub fn read_multipart_slice<'a>(input: &'a [u8], headers: &Headers)
+    -> Result<Vec<BorrowedPart<'a>>, Error>
+{
+    let boundary = get_multipart_boundary(headers)?;
+    let options = ParseOptions::default();
+    let mut reader: &'a [u8] = input;
+    let (lt, lt_boundary) = read_first_boundary(&mut reader, &boundary)?;
+    let mut parts: Vec<BorrowedPart<'a>> = Vec::new();
+    let mut buf: Vec<u8> = Vec::new();
+
+    loop {
+        // If the next two characters are '--', parsing is finished.
+        skip_transport_padding(&mut reader)?;
+        if reader.starts_with(b"--") {
+            return Ok(parts);
+        }
+        if reader.is_empty() {
+            return Err(Error::EofAfterBoundary);
+        }
+
+        // Read the line terminator after the boundary
+        let (_, found) = reader.stream_until_token(&lt, &mut buf)?;
+        if ! found { return Err(Error::NoCrLfAfterBoundary); }
+
+        buf.truncate(0);
+        read_header_lines(&mut reader, &lt, &mut buf, &options)?;
+        let (headers, _) = parse_part_headers(&buf, &options, false)?;
+
+        // Find the end of the body, which is then sliced from the input
+        let body: &'a [u8] = reader;
+        let (read, found) = reader.stream_until_token(&lt_boundary, &mut ::std::io::sink())?;
+        if ! found { return Err(Error::EofInPart); }
+        parts.push(BorrowedPart { headers, body: &body[..read] });
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::io::{self, Write};
+
+// Wraps the destination of a part body, noting whether the boundary appears anywhere within
+// the bytes written through it, including across the edges of separate writes.  With no
+// boundary given, this passes everything through without looking at it.
+pub(crate) struct CollisionWriter<'b, W> {
+    inner: W,
+    boundary: Option<&'b [u8]>,
+    // The last bytes written, which may hold the start of the boundary
+    tail: Vec<u8>,
+    found: bool,
+}
+
+impl<'b, W: Write> CollisionWriter<'b, W> {
+    pub(crate) fn new(inner: W, boundary: Option<&'b [u8]>) -> CollisionWriter<'b, W> {
+        CollisionWriter {
+            inner,
+            boundary,
+            tail: Vec::new(),
+            found: false,
+        }
+    }
+
+    // Whether the boundary was found within what was written
+    pub(crate) fn found(&self) -> bool {
+        self.found
+    }
+
+    fn scan(&mut self, bytes: &[u8]) {
+        let boundary = match self.boundary {
+            Some(boundary) if ! self.found && ! boundary.is_empty() => boundary,
+            _ => return,
+        };
+        self.tail.extend_from_slice(bytes);
+        if self.tail.windows(boundary.len()).any(|w| w == boundary) {
+            self.found = true;
+            self.tail = Vec::new();
+            return;
+        }
+        let keep = boundary.len() - 1;
+        if self.tail.len() > keep {
+            let excess = self.tail.len() - keep;
+            self.tail.drain(..excess);
+        }
+    }
+}
+
+impl<'b, W: Write> Write for CollisionWriter<'b, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.scan(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
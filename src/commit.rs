@@ -0,0 +1,115 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Committing all the uploaded files of a parse to their final place, all or nothing.
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use super::{Error, FilePart, Node};
+
+/// Commit every `FilePart` in the tree of `nodes`, including those within nested
+/// `multipart/*` nodes, to a file in `dest_dir` named by `name_fn`, all or nothing.
+///
+/// Temporary files are moved into place (or copied, if they cannot be moved, as across
+/// filesystems); content held in memory or in anonymous files, and files which are not
+/// temporary (see `FilePart::new()`), are copied.  `name_fn` must give a plain file name,
+/// not a path, and no file of that name may already exist in `dest_dir`.  If committing any
+/// file fails, those already committed are moved back or removed, and the error returned,
+/// leaving nothing committed.
+///
+/// On success each `FilePart` refers to its committed file, which is no longer deleted when
+/// it drops, and the committed paths are returned in the order the file parts appear.
+pub fn commit_files<F>(nodes: &mut [Node], dest_dir: &Path, mut name_fn: F)
+    -> Result<Vec<PathBuf>, Error>
+    where F: FnMut(&FilePart) -> PathBuf
+{
+    let mut fileparts: Vec<&mut FilePart> = Vec::new();
+    collect_files(nodes, &mut fileparts);
+
+    // Commit each file, undoing them all if one fails
+    let mut committed: Vec<Committed> = Vec::with_capacity(fileparts.len());
+    for filepart in &fileparts {
+        let result = commit_file(filepart, dest_dir, &mut name_fn);
+        match result {
+            Ok(done) => committed.push(done),
+            Err(e) => {
+                for (filepart, done) in fileparts.iter().zip(committed).rev() {
+                    if done.moved {
+                        let _ = fs::rename(&done.path, &filepart.path);
+                    } else {
+                        let _ = fs::remove_file(&done.path);
+                    }
+                }
+                return Err(e);
+            },
+        }
+    }
+
+    // Point each file part at its committed file, removing what the file was copied from
+    // if it was temporary
+    for (filepart, done) in fileparts.into_iter().zip(&committed) {
+        if let Some(tempdir) = filepart.tempdir.take() {
+            if ! done.moved {
+                let _ = fs::remove_file(&filepart.path);
+            }
+            let _ = fs::remove_dir(tempdir);
+        }
+        filepart.path = done.path.clone();
+        filepart.content = None;
+        filepart.file = None;
+    }
+    Ok(committed.into_iter().map(|done| done.path).collect())
+}
+
+// A file committed by `commit_file()`
+struct Committed {
+    path: PathBuf,
+    // Whether the file was moved there (so that undoing it moves it back), rather than copied
+    moved: bool,
+}
+
+// Move or copy a file part's content to its file in `dest_dir`
+fn commit_file<F>(filepart: &FilePart, dest_dir: &Path, name_fn: &mut F)
+    -> Result<Committed, Error>
+    where F: FnMut(&FilePart) -> PathBuf
+{
+    let name = name_fn(filepart);
+    let mut components = name.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => {},
+        _ => return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{:?} is not a plain file name", name)))),
+    }
+    let path = dest_dir.join(name);
+
+    // Claim the name, so that an existing file is never replaced
+    let mut file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+
+    let is_temporary = filepart.tempdir.is_some() && filepart.content.is_none() &&
+        filepart.file.is_none();
+    if is_temporary && fs::rename(&filepart.path, &path).is_ok() {
+        return Ok(Committed { path, moved: true });
+    }
+    let copied = filepart.reader().and_then(|mut reader| Ok(io::copy(&mut reader, &mut file)?));
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&path);
+        return Err(e);
+    }
+    Ok(Committed { path, moved: false })
+}
+
+// Every `FilePart` in the tree of `nodes`, depth first and in order
+fn collect_files<'a>(nodes: &'a mut [Node], fileparts: &mut Vec<&'a mut FilePart>) {
+    for node in nodes {
+        match *node {
+            Node::File(ref mut filepart) => fileparts.push(filepart),
+            Node::Multipart((_, ref mut subnodes)) => collect_files(subnodes, fileparts),
+            Node::Part(_) => {},
+        }
+    }
+}
@@ -15,6 +15,10 @@ use httparse;
 use hyper;
 
 /// An error type for the `mime-multipart` crate.
+///
+/// Variants may be added in future (and some exist only with certain features), so matches
+/// on it must have a wildcard arm; `kind()` gives a stable category.
+#[non_exhaustive]
 pub enum Error {
     /// The Hyper request did not have a Content-Type header.
     NoRequestContentType,
@@ -22,14 +26,76 @@ pub enum Error {
     NotMultipart,
     /// The Content-Type header failed to specify boundary token.
     BoundaryNotSpecified,
+    /// A nested `multipart/*` part failed to specify a boundary token in its Content-Type.
+    NestedMultipartMissingBoundary,
     /// A multipart section contained only partial headers.
     PartialHeaders,
     EofInMainHeaders,
     EofBeforeFirstBoundary,
+    /// The stream ended immediately after a boundary (e.g. a truncated upload).
+    EofAfterBoundary,
     NoCrLfAfterBoundary,
     EofInPartHeaders,
     EofInFile,
     EofInPart,
+    /// A part's content was recognisably of another type than its `Content-Type` declared.
+    /// Holds the declared and the recognised types.
+    ContentTypeMismatch(String, String),
+    /// A part's `Content-Type` could not be parsed as a MIME type.  Holds the header value.
+    InvalidContentType(String),
+    /// A part carried a header its `HeaderPolicy` does not allow.  Holds the header name.
+    DisallowedHeader(String),
+    /// A part's body, read by its `Content-Length`, was not followed by the boundary.
+    ContentLengthMismatch,
+    /// A part exceeded the configured size limit.
+    SizeLimitExceeded,
+    /// A line of a part's headers exceeded the configured maximum length.
+    HeaderLineTooLong,
+    /// The stream exceeded the `ParseBudget`'s maximum number of bytes.
+    BodyTooLarge,
+    /// The body had more parts than the `ParseBudget` allows.
+    TooManyParts,
+    /// The body had more fields kept in memory than the `ParseBudget` allows.
+    TooManyFields,
+    /// The body had more file parts than the `ParseBudget` allows.
+    TooManyFiles,
+    /// The parse took longer than the `ParseBudget` allows.
+    TimedOut,
+    /// The parse was cancelled through its cancel flag.
+    Cancelled,
+    /// The stream repeatedly made no progress (e.g. its reads kept being interrupted).
+    NoProgress,
+    /// Several errors occurred during a best-effort parse.
+    Multiple(Vec<Error>),
+    /// A boundary was requested which is too short to be safely unique.
+    BoundaryTooShort,
+    /// A random nonce (for a boundary or a temporary file name) could not be generated.
+    NonceGeneration(String),
+    /// The receiving end of the channel which parsed nodes were being sent down was dropped.
+    Disconnected,
+    /// A decoded filename exceeded the configured maximum length.  Holds the filename.
+    InvalidFilename(String),
+    /// A header to be written contained a CR or LF (or its name was invalid), which would
+    /// allow it to inject further headers.  Holds the header name.
+    HeaderInjection(String),
+    /// A `multipart/form-data` part lacked a `Content-Disposition` of type `form-data` with
+    /// a `name`, as RFC 7578 requires.
+    MissingName,
+    /// A `multipart/form-data` part had a `Content-Transfer-Encoding`, which RFC 7578
+    /// deprecates.  Holds the name of the field.
+    UnexpectedTransferEncoding(String),
+    /// A form lacked a field or file its `FormSchema` requires.  Holds the name.
+    MissingField(String),
+    /// A form had a field or file its `FormSchema` does not declare.  Holds the name.
+    UnexpectedField(String),
+    /// A form field or file was stored, or had a content type, other than its `FormSchema`
+    /// allows.  Holds the name.
+    WrongFieldType(String),
+    /// A part to be written as `multipart/form-data` had no `Content-Disposition` with a
+    /// `name` parameter.
+    MissingDisposition,
+    /// A file to be written with `WriteOptions::consume_once` appeared more than once.
+    SourceReused,
     /// An HTTP parsing error from a multipart section.
     Httparse(httparse::Error),
     /// An I/O error.
@@ -40,6 +106,96 @@ pub enum Error {
     Utf8(FromUtf8Error),
     /// An error occurred during character decoding
     Decoding(Cow<'static, str>),
+    /// A part to be deserialized as JSON did not have a JSON content-type.
+    #[cfg(feature = "json")]
+    NotJson,
+    /// An error occurred deserializing JSON.
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+}
+
+/// A broad category of `Error`, for callers which need to tell kinds of failure apart
+/// (e.g. retryable or fatal, the client's fault or the server's) without matching on every
+/// specific variant.  Errors added in future will fall into these categories where they
+/// fit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An I/O failure, including a stalled stream or a dropped receiver
+    Io,
+    /// The input did not conform to MIME or HTTP
+    Protocol,
+    /// A configured limit was exceeded
+    Limit,
+    /// Text or data could not be decoded
+    Encoding,
+    /// The input ended prematurely
+    Truncation,
+    /// Anything else: a cancelled parse, an invalid request of this crate, or several
+    /// errors of different kinds
+    Other,
+}
+
+impl Error {
+    /// The category of this error.  `Error::Multiple` is of the kind of its errors if
+    /// they are all of the same kind, otherwise `ErrorKind::Other`.
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Error::NoRequestContentType |
+            Error::NotMultipart |
+            Error::BoundaryNotSpecified |
+            Error::NestedMultipartMissingBoundary |
+            Error::PartialHeaders |
+            Error::NoCrLfAfterBoundary |
+            Error::ContentLengthMismatch |
+            Error::ContentTypeMismatch(..) |
+            Error::InvalidContentType(_) |
+            Error::DisallowedHeader(_) |
+            Error::MissingName |
+            Error::UnexpectedTransferEncoding(_) |
+            Error::MissingField(_) |
+            Error::UnexpectedField(_) |
+            Error::WrongFieldType(_) |
+            Error::Httparse(_) |
+            Error::Hyper(_) => ErrorKind::Protocol,
+            #[cfg(feature = "json")]
+            Error::NotJson => ErrorKind::Protocol,
+            Error::EofInMainHeaders |
+            Error::EofBeforeFirstBoundary |
+            Error::EofAfterBoundary |
+            Error::EofInPartHeaders |
+            Error::EofInFile |
+            Error::EofInPart => ErrorKind::Truncation,
+            Error::SizeLimitExceeded |
+            Error::HeaderLineTooLong |
+            Error::BodyTooLarge |
+            Error::TooManyParts |
+            Error::TooManyFields |
+            Error::TooManyFiles |
+            Error::TimedOut |
+            Error::InvalidFilename(_) => ErrorKind::Limit,
+            Error::Utf8(_) |
+            Error::Decoding(_) => ErrorKind::Encoding,
+            #[cfg(feature = "json")]
+            Error::Json(_) => ErrorKind::Encoding,
+            Error::Io(_) |
+            Error::NoProgress |
+            Error::Disconnected |
+            Error::NonceGeneration(_) => ErrorKind::Io,
+            Error::Cancelled |
+            Error::BoundaryTooShort |
+            Error::HeaderInjection(_) |
+            Error::MissingDisposition |
+            Error::SourceReused => ErrorKind::Other,
+            Error::Multiple(ref errors) => {
+                let mut kinds = errors.iter().map(Error::kind);
+                match kinds.next() {
+                    Some(kind) if kinds.all(|k| k == kind) => kind,
+                    _ => ErrorKind::Other,
+                }
+            },
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -66,9 +222,45 @@ impl From<FromUtf8Error> for Error {
     }
 }
 
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Json(err)
+    }
+}
+
 impl Display for Error {
+    #[allow(deprecated)]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::Multiple(ref errors) => {
+                write!(f, "{} errors: ", errors.len())?;
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str("; ")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                Ok(())
+            },
+            Error::NonceGeneration(ref e) =>
+                format!("Nonce generation: {}", e).fmt(f),
+            Error::InvalidFilename(ref name) =>
+                format!("Invalid filename {:?}", name).fmt(f),
+            Error::HeaderInjection(ref name) =>
+                format!("Header injection in header {:?}", name).fmt(f),
+            Error::DisallowedHeader(ref name) =>
+                format!("Disallowed header {:?}", name).fmt(f),
+            Error::ContentTypeMismatch(ref declared, ref sniffed) =>
+                format!("Content type mismatch: {} declared, {} found", declared, sniffed).fmt(f),
+            Error::UnexpectedTransferEncoding(ref name) =>
+                format!("Unexpected Content-Transfer-Encoding in form field {:?}", name).fmt(f),
+            Error::MissingField(ref name) =>
+                format!("Missing form field {:?}", name).fmt(f),
+            Error::UnexpectedField(ref name) =>
+                format!("Unexpected form field {:?}", name).fmt(f),
+            Error::WrongFieldType(ref name) =>
+                format!("Wrong type of form field {:?}", name).fmt(f),
             Error::Httparse(ref e) =>
                 format!("Httparse: {:?}", e).fmt(f),
             Error::Io(ref e) =>
@@ -79,7 +271,10 @@ impl Display for Error {
                 format!("Utf8: {}", e).fmt(f),
             Error::Decoding(ref e) =>
                 format!("Decoding: {}", e).fmt(f),
-            _ => format!("{}", self).fmt(f),
+            #[cfg(feature = "json")]
+            Error::Json(ref e) =>
+                format!("Json: {}", e).fmt(f),
+            _ => f.write_str(self.description()),
         }
     }
 }
@@ -102,12 +297,16 @@ impl StdError for Error {
                 "The Hyper request Content-Type top-level Mime was not multipart.",
             Error::BoundaryNotSpecified =>
                 "The Content-Type header failed to specify a boundary token.",
+            Error::NestedMultipartMissingBoundary =>
+                "A nested multipart part failed to specify a boundary token.",
             Error::PartialHeaders =>
                 "A multipart section contained only partial headers.",
             Error::EofInMainHeaders =>
                 "The request headers ended pre-maturely.",
             Error::EofBeforeFirstBoundary =>
                 "The request body ended prior to reaching the expected starting boundary.",
+            Error::EofAfterBoundary =>
+                "The request body ended immediately after a boundary.",
             Error::NoCrLfAfterBoundary =>
                 "Missing CRLF after boundary.",
             Error::EofInPartHeaders =>
@@ -116,12 +315,68 @@ impl StdError for Error {
                 "The request body ended prematurely while streaming a file part.",
             Error::EofInPart =>
                 "The request body ended prematurely while reading a multipart part.",
+            Error::InvalidContentType(_) =>
+                "A part's Content-Type could not be parsed.",
+            Error::DisallowedHeader(_) =>
+                "A part carried a header which is not allowed.",
+            Error::ContentLengthMismatch =>
+                "A part's body was not followed by the boundary where its Content-Length ended.",
+            Error::SizeLimitExceeded =>
+                "A multipart part exceeded the configured size limit.",
+            Error::HeaderLineTooLong =>
+                "A header line of a multipart part exceeded the configured maximum length.",
+            Error::BodyTooLarge =>
+                "The request body exceeded the configured maximum size.",
+            Error::TooManyParts =>
+                "The request body had more parts than the configured maximum.",
+            Error::TooManyFields =>
+                "The request body had more fields than the configured maximum.",
+            Error::TooManyFiles =>
+                "The request body had more files than the configured maximum.",
+            Error::TimedOut =>
+                "Parsing took longer than the configured maximum duration.",
+            Error::Cancelled =>
+                "Parsing was cancelled.",
+            Error::NoProgress =>
+                "The request body stream repeatedly made no progress.",
+            Error::Multiple(_) =>
+                "Several errors occurred.",
+            Error::BoundaryTooShort =>
+                "The requested boundary length is too short.",
+            Error::NonceGeneration(_) =>
+                "A random nonce could not be generated.",
+            Error::Disconnected =>
+                "The receiver of the parsed nodes was dropped.",
+            Error::InvalidFilename(_) =>
+                "A filename exceeded the configured maximum length.",
+            Error::HeaderInjection(_) =>
+                "A header to be written contained a line break or an invalid name.",
+            Error::ContentTypeMismatch(..) =>
+                "A part's content was not of the type its Content-Type declared.",
+            Error::MissingName =>
+                "A form-data part lacked a Content-Disposition name.",
+            Error::UnexpectedTransferEncoding(_) =>
+                "A form-data part had a Content-Transfer-Encoding.",
+            Error::MissingField(_) =>
+                "A form lacked a required field.",
+            Error::UnexpectedField(_) =>
+                "A form had a field its schema does not declare.",
+            Error::WrongFieldType(_) =>
+                "A form field was of a type its schema does not allow.",
+            Error::MissingDisposition =>
+                "A form-data part to be written had no Content-Disposition name.",
+            Error::SourceReused =>
+                "A file to be written once appeared more than once.",
             Error::Httparse(_) =>
                 "A parse error occurred while parsing the headers of a multipart section.",
             Error::Io(_) => "An I/O error occurred.",
             Error::Hyper(_) => "A Hyper error occurred.",
             Error::Utf8(_) => "A UTF-8 error occurred.",
             Error::Decoding(_) => "A decoding error occurred.",
+            #[cfg(feature = "json")]
+            Error::NotJson => "The part does not have a JSON content-type.",
+            #[cfg(feature = "json")]
+            Error::Json(_) => "A JSON deserialization error occurred.",
         }
     }
 }
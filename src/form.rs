@@ -0,0 +1,275 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A higher-level view of a parsed `multipart/form-data` body, with the parts keyed by
+//! their `Content-Disposition` `name` and text fields separated from files.
+
+use std::collections::HashMap;
+use std::io::Read;
+use hyper::header::{ContentDisposition, ContentType, DispositionParam, Headers};
+use mime::{Mime, SubLevel, TopLevel};
+use super::{decode_extended_value, mime_matches, params, read_multipart_body_with_options,
+            Error, FilePart, Node, ParseOptions, Part};
+
+/// The parts of a `multipart/form-data` body, separated into in-memory fields and files,
+/// each keyed by the `name` parameter of its `Content-Disposition` header.
+///
+/// Parts are kept in the order they were received, and a name may occur more than once.
+/// Parts without a `name` are keyed by the empty string.
+///
+/// Nested `multipart/*` parts (as used by older clients to upload several files under one
+/// field) are flattened: each of their parts is added to `fields` or `files` like a
+/// top-level part, but keyed by the `name` of the enclosing nested multipart if it has one.
+#[derive(Clone, Debug, Default)]
+pub struct ParsedForm {
+    /// Parts kept in memory, usually text fields
+    pub fields: Vec<(String, Part)>,
+    /// Parts streamed to files, usually uploads
+    pub files: Vec<(String, FilePart)>,
+}
+
+impl ParsedForm {
+    /// Build a `ParsedForm` from already parsed `Node`s.
+    pub fn from_nodes(nodes: Vec<Node>) -> ParsedForm {
+        let mut form = ParsedForm::default();
+        form.add_nodes(nodes, None);
+        form
+    }
+
+    fn add_nodes(&mut self, nodes: Vec<Node>, outer_name: Option<&str>) {
+        for node in nodes {
+            match node {
+                Node::Part(part) => {
+                    let name = key(outer_name, &part.headers);
+                    self.fields.push((name, part));
+                },
+                Node::File(filepart) => {
+                    let name = key(outer_name, &filepart.headers);
+                    self.files.push((name, filepart));
+                },
+                Node::Multipart((headers, subnodes)) => {
+                    let name = key(outer_name, &headers);
+                    let outer = if name.is_empty() { None } else { Some(&*name) };
+                    self.add_nodes(subnodes, outer);
+                },
+            }
+        }
+    }
+
+    /// The first field with the given name
+    pub fn field(&self, name: &str) -> Option<&Part> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, part)| part)
+    }
+
+    /// The first file with the given name
+    pub fn file(&self, name: &str) -> Option<&FilePart> {
+        self.files.iter().find(|(n, _)| n == name).map(|(_, filepart)| filepart)
+    }
+
+    /// All the files with the given name, in order.  This includes the files of a nested
+    /// `multipart/mixed` part given that name, as older clients send several files under
+    /// one field; each keeps its own filename (see `FilePart::filename()`).
+    pub fn files_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a FilePart> + 'a {
+        self.files.iter().filter(move |(n, _)| n == name).map(|(_, filepart)| filepart)
+    }
+}
+
+/// Parse a `multipart/form-data` body from a `Read`able stream into a `ParsedForm`.
+///
+/// As with `read_multipart_body()`, it is presumed that you have the `Headers` already and
+/// the stream starts at the body.
+pub fn parse_form<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions)
+    -> Result<ParsedForm, Error>
+{
+    let nodes = read_multipart_body_with_options(stream, headers, options)?;
+    Ok(ParsedForm::from_nodes(nodes))
+}
+
+/// Parse a `multipart/form-data` body from a `Read`able stream into a `ParsedForm`, as
+/// `parse_form()` does, but enforcing the constraints of RFC 7578 rather than accepting
+/// non-conforming parts: every top-level part must have a `Content-Disposition` of type
+/// `form-data` with a `name`, or parsing fails with `Error::MissingName`, and no part may
+/// have a `Content-Transfer-Encoding`, or parsing fails with
+/// `Error::UnexpectedTransferEncoding`.
+pub fn parse_form_data_strict<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions)
+    -> Result<ParsedForm, Error>
+{
+    let nodes = read_multipart_body_with_options(stream, headers, options)?;
+    for node in &nodes {
+        let is_form_data = disposition_type(node.headers())
+            .is_some_and(|disposition| disposition.eq_ignore_ascii_case("form-data"));
+        let name = match disposition_name(node.headers()) {
+            Some(name) if is_form_data => name,
+            _ => return Err(Error::MissingName),
+        };
+        check_transfer_encoding(node, &name)?;
+    }
+    Ok(ParsedForm::from_nodes(nodes))
+}
+
+// Fail with `Error::UnexpectedTransferEncoding` if the node, or any node within it, has a
+// `Content-Transfer-Encoding`
+fn check_transfer_encoding(node: &Node, name: &str) -> Result<(), Error> {
+    if node.headers().get_raw("Content-Transfer-Encoding").is_some() {
+        return Err(Error::UnexpectedTransferEncoding(name.to_owned()));
+    }
+    if let Node::Multipart((_, ref subnodes)) = *node {
+        for subnode in subnodes {
+            check_transfer_encoding(subnode, name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Group `Node`s by the `name` parameter of their `Content-Disposition` header, keeping
+/// them in their original order within each name.  This suits fields which may be repeated,
+/// such as those from `<select multiple>` or repeated checkboxes.
+///
+/// Nodes without a `name` are grouped under the empty string.  Nested `multipart/*` nodes
+/// are grouped as a whole, by their own `name`.
+pub fn group_by_name(nodes: Vec<Node>) -> HashMap<String, Vec<Node>> {
+    let mut groups: HashMap<String, Vec<Node>> = HashMap::new();
+    for node in nodes {
+        let name = disposition_name(node.headers()).unwrap_or_default();
+        groups.entry(name).or_default().push(node);
+    }
+    groups
+}
+
+/// A declaration of the fields a `multipart/form-data` body must or may have, for
+/// `validate_form()`.  Names are matched exactly.
+#[derive(Clone, Debug, Default)]
+pub struct FormSchema {
+    /// Names of fields which must be present, kept in memory (as `Node::Part`s)
+    pub required_fields: Vec<String>,
+    /// Names of files which must be present, streamed to files (as `Node::File`s)
+    pub required_files: Vec<String>,
+    /// Names of fields or files which may be present
+    pub optional: Vec<String>,
+    /// The content types acceptable for the parts with a name, for the names which are
+    /// restricted.  A `*` type or subtype matches any; parameters are ignored.  A part
+    /// without a `Content-Type` is taken to be `text/plain`.
+    pub content_types: Vec<(String, Vec<Mime>)>,
+    /// If true, parts with names not declared above are allowed.  If false (the default),
+    /// they fail validation with `Error::UnexpectedField`.
+    pub allow_unknown: bool,
+}
+
+impl FormSchema {
+    fn is_declared(&self, name: &str) -> bool {
+        self.required_fields.iter()
+            .chain(&self.required_files)
+            .chain(&self.optional)
+            .any(|n| n == name)
+    }
+}
+
+/// Check parsed `multipart/form-data` nodes against a `FormSchema`, failing with the first
+/// problem found: `Error::UnexpectedField` for a name the schema does not declare,
+/// `Error::WrongFieldType` for a required field streamed to a file (or a required file kept
+/// in memory) or a part whose content type is not acceptable, and `Error::MissingField`
+/// for a required name which is absent.  Each holds the name concerned.
+///
+/// The parts of nested `multipart/*` nodes are checked under the name of the enclosing
+/// node, as `ParsedForm` keys them.
+pub fn validate_form(nodes: &[Node], schema: &FormSchema) -> Result<(), Error> {
+    let mut seen: Vec<String> = Vec::new();
+    validate_nodes(nodes, schema, None, &mut seen)?;
+    for name in schema.required_fields.iter().chain(&schema.required_files) {
+        if ! seen.contains(name) {
+            return Err(Error::MissingField(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+fn validate_nodes(nodes: &[Node], schema: &FormSchema, outer_name: Option<&str>,
+                  seen: &mut Vec<String>)
+    -> Result<(), Error>
+{
+    for node in nodes {
+        let name = key(outer_name, node.headers());
+        let is_file = match *node {
+            Node::Part(_) => false,
+            Node::File(_) => true,
+            Node::Multipart((_, ref subnodes)) => {
+                let outer = if name.is_empty() { None } else { Some(&*name) };
+                validate_nodes(subnodes, schema, outer, seen)?;
+                continue;
+            },
+        };
+        if ! schema.allow_unknown && ! schema.is_declared(&name) {
+            return Err(Error::UnexpectedField(name));
+        }
+        let wrong_storage = if is_file {
+            schema.required_fields.contains(&name)
+        } else {
+            schema.required_files.contains(&name)
+        };
+        if wrong_storage || ! content_type_allowed(schema, &name, node.headers()) {
+            return Err(Error::WrongFieldType(name));
+        }
+        seen.push(name);
+    }
+    Ok(())
+}
+
+fn content_type_allowed(schema: &FormSchema, name: &str, headers: &Headers) -> bool {
+    let allowed = match schema.content_types.iter().find(|(n, _)| n == name) {
+        Some((_, allowed)) => allowed,
+        None => return true,
+    };
+    let ct: Option<&ContentType> = headers.get();
+    allowed.iter().any(|preference| match ct {
+        Some(&ContentType(Mime(ref top, ref sub, _))) => mime_matches(preference, top, sub),
+        None => mime_matches(preference, &TopLevel::Text, &SubLevel::Plain),
+    })
+}
+
+// The key of a part: the name of its enclosing nested multipart if there is one, otherwise
+// its own name, otherwise the empty string.
+fn key(outer_name: Option<&str>, headers: &Headers) -> String {
+    match outer_name {
+        Some(name) => name.to_owned(),
+        None => disposition_name(headers).unwrap_or_default(),
+    }
+}
+
+// The type of a `Content-Disposition` header, such as `form-data`
+fn disposition_type(headers: &Headers) -> Option<String> {
+    let raw = headers.get_raw("Content-Disposition")?.first()?;
+    let (disposition, _) = params::split_header_params(raw);
+    Some(String::from_utf8_lossy(&disposition).into_owned())
+}
+
+// The `name` parameter of a `Content-Disposition` header.  This is taken from the raw header
+// where possible, as hyper does not undo escapes within quoted values, nor join RFC 2231
+// continuations, which give an extended `name*` if they are encoded.
+pub(crate) fn disposition_name(headers: &Headers) -> Option<String> {
+    if let Some(name) = params::find_header_param(headers, "Content-Disposition", "name") {
+        return Some(String::from_utf8_lossy(&name).into_owned());
+    }
+    let extended = params::find_header_param(headers, "Content-Disposition", "name*")
+        .and_then(|name| decode_extended_value(&name))
+        .and_then(Result::ok);
+    if extended.is_some() {
+        return extended;
+    }
+    let cd: &ContentDisposition = headers.get()?;
+    cd.parameters.iter().find_map(|param| match *param {
+        DispositionParam::Ext(ref attr, ref value) if attr.eq_ignore_ascii_case("name") => {
+            Some(value.clone())
+        },
+        _ => None,
+    })
+}
@@ -5,26 +5,77 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+#[cfg(feature = "async")]
+pub mod async_write;
+pub mod borrowed;
+pub mod commit;
 pub mod error;
+pub mod form;
+pub mod options;
+mod collision;
+mod limit;
+mod params;
+mod progress;
+pub mod report;
+mod sniff;
+pub mod scanner;
+mod spill;
+mod transfer;
+pub mod tee;
 
 #[cfg(test)]
 mod mock;
+// The original tests predate these lints, and are kept as they were written
 #[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::explicit_auto_deref, clippy::borrow_deref_ref,
+        clippy::needless_borrowed_reference, clippy::vec_init_then_push)]
 mod tests;
 
-pub use error::Error;
+#[cfg(feature = "async")]
+pub use async_write::{write_multipart_async, AsyncMultipartWriter};
+pub use borrowed::{read_multipart_slice, BorrowedPart};
+pub use commit::commit_files;
+pub use error::{Error, ErrorKind};
+pub use form::{group_by_name, parse_form, parse_form_data_strict, validate_form, FormSchema,
+               ParsedForm};
+pub use options::{ContentTypeCheck, DispositionPolicy, DispositionStorage, HeaderLineEndings,
+                  HeaderPolicy, InvalidContentType, LineEnding, ParseBudget, ParseOptions,
+                  PartStorage, SpillPolicy, WriteOptions};
+pub use report::{debug_dump, size_report, PartSize, SizeReport};
+pub use scanner::BoundaryScanner;
+pub use tee::TeeWriter;
+
+// The crates, and the types from them, which appear in this crate's API, so that callers can
+// build `Headers` and `Mime`s through this crate rather than depending on exactly the same
+// versions of `hyper` and `mime` themselves
+pub use hyper;
+pub use mime;
+pub use hyper::header::{Charset, ContentDisposition, ContentType, DispositionParam,
+                        DispositionType, Headers};
+pub use mime::{Attr, Mime, SubLevel, TopLevel, Value};
 
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::ops::Drop;
 use encoding::{all, Encoding, DecoderTrap};
-use hyper::header::{ContentType, Headers, ContentDisposition, DispositionParam,
-                    DispositionType, Charset};
+use hyper::header::{HeaderView, ContentLength};
+use hyper::header::parsing::parse_extended_value;
 use textnonce::TextNonce;
-use mime::{Attr, Mime, TopLevel, Value};
 use buf_read_ext::BufReadExt;
+use collision::CollisionWriter;
+use limit::LimitWriter;
+use progress::ProgressReader;
+use spill::{SpillWriter, Spilled};
+use transfer::{Base64Writer, CrlfWriter, Decoding, DecodingReader, DotStuffWriter, QpWriter};
+
+/// Header `(name, value)` pairs exactly as they were received: in their original order,
+/// with their original case, and including any duplicates.
+pub type RawHeaders = Vec<(String, Vec<u8>)>;
 
 /// A multipart part which is not a file (stored in memory)
 #[derive(Clone, Debug, PartialEq)]
@@ -33,10 +84,115 @@ pub struct Part {
     pub body: Vec<u8>,
 }
 impl Part {
+    /// Create a new in-memory part
+    pub fn new(headers: Headers, body: Vec<u8>) -> Part {
+        Part {
+            headers,
+            body,
+        }
+    }
+
     /// Mime content-type specified in the header
     pub fn content_type(&self) -> Option<Mime> {
-        let ct: Option<&ContentType> = self.headers.get();
-        ct.map(|ref ct| ct.0.clone())
+        content_type(&self.headers)
+    }
+
+    /// The language tags given by the `Content-Language` header (RFC 3282), in order, or
+    /// `None` if there is no such header.  Comments are removed.
+    pub fn content_language(&self) -> Option<Vec<String>> {
+        content_language(&self.headers)
+    }
+
+    /// The `charset` parameter of the `Content-Type` header, if there is one.  The value is
+    /// unquoted and matched to a known `Charset` case-insensitively, so that
+    /// `charset="UTF-8"` and `charset=utf-8` give the same `Charset`.
+    pub fn charset(&self) -> Option<Charset> {
+        content_charset(&self.headers)
+    }
+
+    /// The `Content-Disposition` header, if there is one (and hyper could parse it)
+    pub fn content_disposition(&self) -> Option<&ContentDisposition> {
+        self.headers.get()
+    }
+
+    /// Set the `Content-Disposition` header, replacing any existing one
+    pub fn set_content_disposition(&mut self, cd: ContentDisposition) {
+        self.headers.set(cd);
+    }
+
+    /// Deserialize the body as JSON.  Fails with `Error::NotJson` unless the part's
+    /// content-type is `application/json` or another `+json` type.
+    ///
+    /// Only available with the `json` feature.
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, Error> {
+        let is_json = match self.content_type() {
+            Some(Mime(TopLevel::Application, SubLevel::Json, _)) => true,
+            Some(Mime(_, SubLevel::Ext(ref sub), _)) => sub.to_ascii_lowercase().ends_with("+json"),
+            _ => false,
+        };
+        if ! is_json {
+            return Err(Error::NotJson);
+        }
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// Write the body out to a new temporary file, converting this into a `FilePart` with
+    /// the same headers.  The file will be deleted once the `FilePart` goes out of scope.
+    pub fn into_file(self) -> Result<FilePart, Error> {
+        let mut filepart = FilePart::create(self.headers)?;
+        let mut file = File::create(&filepart.path)?;
+        file.write_all(&self.body)?;
+        filepart.size = Some(self.body.len() as u64);
+        Ok(filepart)
+    }
+
+    /// Append data to the end of the body.  This is convenient when the body is being
+    /// assembled from several pieces.
+    pub fn append_body(&mut self, data: &[u8]) {
+        self.body.extend_from_slice(data);
+    }
+
+    /// A reader over the body
+    pub fn body_reader(&self) -> Cursor<&[u8]> {
+        Cursor::new(&self.body)
+    }
+
+    /// A reader over the body which decodes its `Content-Transfer-Encoding` (`base64` or
+    /// `quoted-printable`) as it is read.  A body which is not encoded is read as it is.
+    /// Fails with `Error::Decoding` if the encoding is not known; a malformed body fails
+    /// the read instead.
+    pub fn decoded_reader(&self) -> Result<Box<dyn Read + '_>, Error> {
+        decoding_reader(&self.headers, self.body_reader())
+    }
+
+    /// The body decoded as text, in the charset given by the `Content-Type` (see
+    /// `charset()`), or UTF-8 if none is given.  A leading byte order mark is removed
+    /// rather than becoming part of the text.  A UTF-8 or UTF-16 byte order mark overrides
+    /// the declared charset, as it shows what the body really is.  Fails with
+    /// `Error::Decoding` if the body is not valid in its charset, or the charset is not
+    /// supported.
+    pub fn body_text(&self) -> Result<String, Error> {
+        decode_text(&self.body, self.charset().as_ref())
+    }
+
+    /// Whether this part has the same headers and body as `other`, regardless of the order
+    /// of the headers.  Header names are compared case-insensitively.  Unlike `==`, this
+    /// does not depend on the order in which hyper happens to hold the headers.
+    pub fn semantically_eq(&self, other: &Part) -> bool {
+        self.body == other.body && headers_eq(&self.headers, &other.headers)
+    }
+}
+
+/// Writing to a `Part` appends to its body.
+impl Write for Part {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.append_body(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        Ok(())
     }
 }
 
@@ -46,11 +202,21 @@ impl Part {
 pub struct FilePart {
     /// The headers of the part
     pub headers: Headers,
-    /// A temporary file containing the file content
+    /// A temporary file containing the file content.  This is empty if the content is held
+    /// in memory instead (see `from_bytes()`), or in an anonymous file (see
+    /// `create_anonymous()`).
     pub path: PathBuf,
     /// Optionally, the size of the file.  This is filled when multiparts are parsed, but is
-    /// not necessary when they are generated.
-    pub size: Option<usize>,
+    /// not necessary when they are generated.  It is a `u64` so that large files are sized
+    /// correctly even where `usize` is 32 bits.
+    pub size: Option<u64>,
+    // How the filename is decoded and checked, as set by the `ParseOptions` it was parsed
+    // with
+    filename_policy: FilenamePolicy,
+    // The content, if it is held in memory rather than in the file at `path`
+    content: Option<Vec<u8>>,
+    // The anonymous file holding the content, if it is not at `path`
+    file: Option<AnonymousFile>,
     // The temporary directory the upload was put into, saved for the Drop trait
     tempdir: Option<PathBuf>,
 }
@@ -58,10 +224,110 @@ impl FilePart {
     pub fn new(headers: Headers, path: &Path) -> FilePart
     {
         FilePart {
-            headers: headers,
+            headers,
             path: path.to_owned(),
             size: None,
+            filename_policy: FilenamePolicy::default(),
+            content: None,
+            file: None,
+            tempdir: None,
+        }
+    }
+
+    /// Create a FilePart whose content is held in memory rather than on disk.  It is written
+    /// like any other file part, which avoids writing a temporary file just to send an
+    /// in-memory blob (with its filename headers) as a file.
+    pub fn from_bytes(headers: Headers, content: Vec<u8>) -> FilePart {
+        FilePart {
+            headers,
+            path: PathBuf::new(),
+            size: Some(content.len() as u64),
+            filename_policy: FilenamePolicy::default(),
+            content: Some(content),
+            file: None,
+            tempdir: None,
+        }
+    }
+
+    /// Create a new FilePart backed by an anonymous temporary file, which is unlinked as
+    /// soon as it is created so that nothing is left behind even if the process is killed.
+    /// It has no `path`; its content is reached through `reader()`.  Clones of it share
+    /// the one file, which is removed once they have all dropped.
+    pub fn create_anonymous(headers: Headers) -> Result<FilePart, Error> {
+        Ok(FilePart {
+            headers,
+            path: PathBuf::new(),
+            size: None,
+            filename_policy: FilenamePolicy::default(),
+            content: None,
+            file: Some(AnonymousFile(Arc::new(tempfile::tempfile()?))),
             tempdir: None,
+        })
+    }
+
+    /// The content, if it is held in memory rather than in a file (see `from_bytes()` and
+    /// `ParseOptions::buffer_files_up_to`)
+    pub fn content(&self) -> Option<&[u8]> {
+        self.content.as_deref()
+    }
+
+    /// Move content which is held in memory out to a new temporary file, as `create()`
+    /// makes, once it has been inspected and is to be kept.  Content which is already in a
+    /// file is left where it is.
+    pub fn commit_to_disk(&mut self) -> Result<(), Error> {
+        let content = match self.content {
+            Some(ref content) => content,
+            None => return Ok(()),
+        };
+        let mut created = FilePart::create(Headers::new())?;
+        File::create(&created.path)?.write_all(content)?;
+        self.path = ::std::mem::take(&mut created.path);
+        self.tempdir = created.tempdir.take();
+        self.content = None;
+        Ok(())
+    }
+
+    /// Whether the content is held in an anonymous file (see `create_anonymous()`)
+    pub fn is_anonymous(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Open the content for reading, whether it is in memory, in the file at `path` or in
+    /// an anonymous file.
+    pub fn reader(&self) -> Result<Box<dyn Read + '_>, Error> {
+        match (&self.content, &self.file) {
+            (Some(content), _) => Ok(Box::new(&content[..])),
+            (None, Some(file)) => Ok(Box::new(file.rewound()?)),
+            (None, None) => Ok(Box::new(File::open(&self.path)?)),
+        }
+    }
+
+    /// Open the content for reading, as `reader()` does, decoding its
+    /// `Content-Transfer-Encoding` (`base64` or `quoted-printable`) as it is read, so that
+    /// a large encoded attachment is never decoded all at once.  Content which is not
+    /// encoded is read as it is.  Fails with `Error::Decoding` if the encoding is not
+    /// known; malformed content fails the read instead.
+    pub fn decoded_reader(&self) -> Result<Box<dyn Read + '_>, Error> {
+        decoding_reader(&self.headers, self.reader()?)
+    }
+
+    // The length of the content
+    fn content_len(&self) -> Result<u64, Error> {
+        match (&self.content, &self.file) {
+            (Some(content), _) => Ok(content.len() as u64),
+            (None, Some(file)) => Ok(file.0.metadata()?.len()),
+            (None, None) => Ok(::std::fs::metadata(&self.path)?.len()),
+        }
+    }
+
+    // Open the file to write the content into, emptying it
+    fn create_file(&self) -> io::Result<File> {
+        match self.file {
+            Some(ref file) => {
+                file.0.set_len(0)?;
+                file.rewound()
+            },
+            None => File::create(&self.path),
         }
     }
 
@@ -71,42 +337,212 @@ impl FilePart {
         self.tempdir = None;
     }
 
+    /// Consume the `FilePart`, returning the path of its file, which is then no longer
+    /// deleted.  It becomes your responsibility to clean up the file and the temporary
+    /// directory it is in.  Returns `None` if the content is not in a file at a path (see
+    /// `from_bytes()` and `create_anonymous()`), in which case nothing is kept.
+    pub fn take_path(mut self) -> Option<PathBuf> {
+        if self.content.is_some() || self.file.is_some() {
+            return None;
+        }
+        self.tempdir = None;
+        Some(::std::mem::take(&mut self.path))
+    }
+
     /// Create a new temporary FilePart (when created this way, the file will be
     /// deleted once the FilePart object goes out of scope).
     pub fn create(headers: Headers) -> Result<FilePart, Error> {
         // Setup a file to capture the contents.
-        let mut path = tempfile::Builder::new().prefix("mime_multipart").tempdir()?.into_path();
+        let mut path = tempfile::Builder::new().prefix("mime_multipart").tempdir()?.keep();
         let tempdir = Some(path.clone());
-        path.push(TextNonce::sized_urlsafe(32).unwrap().into_string());
+        path.push(TextNonce::sized_urlsafe(32).map_err(Error::NonceGeneration)?.into_string());
         Ok(FilePart {
-            headers: headers,
-            path: path,
+            headers,
+            path,
             size: None,
-            tempdir: tempdir,
+            filename_policy: FilenamePolicy::default(),
+            content: None,
+            file: None,
+            tempdir,
         })
     }
 
     /// Filename that was specified when the file was uploaded.  Returns `Ok<None>` if there
     /// was no content-disposition header supplied.
+    ///
+    /// A filename which does not declare its charset is decoded with the
+    /// `default_filename_charset` it was parsed with, or as UTF-8 if the part was not parsed.
+    /// The decoded filename is then normalized and checked against `max_filename_chars` as
+    /// those options specified, failing with `Error::InvalidFilename` if it is too long.
     pub fn filename(&self) -> Result<Option<String>, Error> {
-        let cd: Option<&ContentDisposition> = self.headers.get();
-        match cd {
-            Some(cd) => get_content_disposition_filename(cd),
+        let utf8 = Charset::Ext("UTF-8".to_owned());
+        self.filename_with_default_charset(self.filename_policy.charset.as_ref().unwrap_or(&utf8))
+    }
+
+    /// Filename that was specified when the file was uploaded, decoding a filename which
+    /// does not declare its charset with `default_charset`.  Returns `Ok<None>` if there was
+    /// no content-disposition header supplied.  The decoded filename is checked as it is by
+    /// `filename()`.
+    pub fn filename_with_default_charset(&self, default_charset: &Charset)
+        -> Result<Option<String>, Error>
+    {
+        match get_filename(&self.headers, default_charset)? {
+            Some(filename) => self.filename_policy.check(filename).map(Some),
             None => Ok(None),
         }
     }
 
     /// Mime content-type specified in the header
     pub fn content_type(&self) -> Option<Mime> {
-        let ct: Option<&ContentType> = self.headers.get();
-        ct.map(|ref ct| ct.0.clone())
+        content_type(&self.headers)
+    }
+
+    /// The language tags given by the `Content-Language` header (RFC 3282), in order, or
+    /// `None` if there is no such header.  Comments are removed.
+    pub fn content_language(&self) -> Option<Vec<String>> {
+        content_language(&self.headers)
+    }
+
+    /// The `Content-Disposition` header, if there is one (and hyper could parse it)
+    pub fn content_disposition(&self) -> Option<&ContentDisposition> {
+        self.headers.get()
+    }
+
+    /// Set the `Content-Disposition` header, replacing any existing one
+    pub fn set_content_disposition(&mut self, cd: ContentDisposition) {
+        self.headers.set(cd);
+    }
+
+    /// Set the `Content-Type` header, replacing any existing one
+    pub fn set_content_type(&mut self, mime: Mime) {
+        self.headers.set(ContentType(mime));
+    }
+
+    /// Set the filename in the `Content-Disposition` header, keeping its disposition type and
+    /// other parameters (such as `name`), or adding an `attachment` disposition if there is
+    /// none.  A filename which is not ASCII is given as an RFC 5987 `filename*` parameter in
+    /// UTF-8, along with a `filename` parameter with its non-ASCII characters replaced by
+    /// `_` for clients which do not understand `filename*`.
+    pub fn set_filename(&mut self, filename: &str) {
+        set_filename(&mut self.headers, filename);
+    }
+
+    /// Whether this file part has the same headers and content as `other`, regardless of
+    /// the order of the headers, as `Part::semantically_eq()` decides.  The contents are
+    /// compared a buffer at a time, whether in memory or in files, and their paths are
+    /// ignored.
+    pub fn semantically_eq(&self, other: &FilePart) -> Result<bool, Error> {
+        if ! headers_eq(&self.headers, &other.headers) ||
+            self.content_len()? != other.content_len()?
+        {
+            return Ok(false);
+        }
+        let mut ours = BufReader::new(self.reader()?);
+        let mut theirs = BufReader::new(other.reader()?);
+        loop {
+            let n = {
+                let a = ours.fill_buf()?;
+                let b = theirs.fill_buf()?;
+                if a.is_empty() || b.is_empty() {
+                    return Ok(a.is_empty() && b.is_empty());
+                }
+                let n = ::std::cmp::min(a.len(), b.len());
+                if a[..n] != b[..n] {
+                    return Ok(false);
+                }
+                n
+            };
+            ours.consume(n);
+            theirs.consume(n);
+        }
+    }
+
+    /// Read the file into memory, converting this into a `Part` with the same headers.  If
+    /// the file would have been deleted when this `FilePart` dropped, it is deleted now.
+    pub fn into_part(mut self) -> Result<Part, Error> {
+        let body = match self.content.take() {
+            Some(content) => content,
+            None => {
+                let mut body: Vec<u8> = Vec::new();
+                self.reader()?.read_to_end(&mut body)?;
+                body
+            },
+        };
+        Ok(Part {
+            headers: ::std::mem::replace(&mut self.headers, Headers::new()),
+            body,
+        })
+    }
+}
+// Whether two sets of headers hold the same values, regardless of their order and of the
+// case of their names
+fn headers_eq(a: &Headers, b: &Headers) -> bool {
+    let sorted = |headers: &Headers| {
+        let mut pairs: Vec<(String, String)> = headers.iter()
+            .map(|header| (header.name().to_ascii_lowercase(), header.value_string()))
+            .collect();
+        pairs.sort();
+        pairs
+    };
+    a.len() == b.len() && sorted(a) == sorted(b)
+}
+
+// An unlinked temporary file, shared by the clones of a `FilePart`.  Clones are equal only
+// if they share the one file.
+#[derive(Clone, Debug)]
+struct AnonymousFile(Arc<File>);
+
+impl AnonymousFile {
+    // A handle on the file positioned at its start.  Handles share their position, so only
+    // one should be used at a time.
+    fn rewound(&self) -> io::Result<File> {
+        let mut file = self.0.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    }
+}
+
+impl PartialEq for AnonymousFile {
+    fn eq(&self, other: &AnonymousFile) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// How the filename of a `FilePart` is decoded and checked
+#[derive(Clone, Debug, Default, PartialEq)]
+struct FilenamePolicy {
+    // The charset used to decode a filename which does not declare one, if not UTF-8
+    charset: Option<Charset>,
+    // The maximum number of characters in the decoded filename
+    max_chars: Option<usize>,
+    // Whether the decoded filename is normalized to NFC
+    #[cfg(feature = "unicode-normalization")]
+    normalize: bool,
+}
+
+impl FilenamePolicy {
+    fn check(&self, filename: String) -> Result<String, Error> {
+        #[cfg(feature = "unicode-normalization")]
+        let filename = if self.normalize {
+            use unicode_normalization::UnicodeNormalization;
+            filename.nfc().collect()
+        } else {
+            filename
+        };
+        if let Some(max_chars) = self.max_chars {
+            if filename.chars().count() > max_chars {
+                return Err(Error::InvalidFilename(filename));
+            }
+        }
+        Ok(filename)
     }
 }
+
 impl Drop for FilePart {
     fn drop(&mut self) {
-        if self.tempdir.is_some() {
+        if let Some(ref tempdir) = self.tempdir {
             let _ = ::std::fs::remove_file(&self.path);
-            let _ = ::std::fs::remove_dir(&self.tempdir.as_ref().unwrap());
+            let _ = ::std::fs::remove_dir(tempdir);
         }
     }
 }
@@ -123,6 +559,139 @@ pub enum Node {
     Multipart((Headers, Vec<Node>)),
 }
 
+impl Node {
+    /// The headers of the node, whichever variant it is
+    pub fn headers(&self) -> &Headers {
+        match *self {
+            Node::Part(ref part) => &part.headers,
+            Node::File(ref filepart) => &filepart.headers,
+            Node::Multipart((ref headers, _)) => headers,
+        }
+    }
+
+    /// The subtype of a `Node::Multipart` (such as `mixed`, `alternative`, `related` or
+    /// `parallel`), which tells how its parts relate, or `None` for any other node.  See
+    /// `multipart_subtype()`.
+    pub fn multipart_subtype(&self) -> Option<String> {
+        match *self {
+            Node::Multipart((ref headers, _)) => multipart_subtype(headers),
+            _ => None,
+        }
+    }
+}
+
+/// The subtype of a `multipart/*` `Content-Type` in `headers` (such as `mixed`,
+/// `alternative`, `related` or `parallel`), lowercased, or `None` if the headers give no
+/// `multipart/*` content type.  Every subtype is parsed the same way; this lets consumers
+/// act on its semantics, e.g. presenting the parts of a `parallel` body together.  Use this
+/// on the top-level headers, and `Node::multipart_subtype()` on nested multiparts.
+pub fn multipart_subtype(headers: &Headers) -> Option<String> {
+    match headers.get::<ContentType>() {
+        Some(&ContentType(Mime(TopLevel::Multipart, ref sub, _))) =>
+            Some(sub.as_str().to_ascii_lowercase()),
+        _ => None,
+    }
+}
+
+/// Every `FilePart` in the tree of `nodes`, depth first and in order, including those within
+/// nested `multipart/*` nodes.  This suits the common loop over all uploaded files.
+pub fn files(nodes: &[Node]) -> impl Iterator<Item = &FilePart> {
+    Leaves::new(nodes).filter_map(|node| match *node {
+        Node::File(ref filepart) => Some(filepart),
+        _ => None,
+    })
+}
+
+/// Every in-memory `Part` in the tree of `nodes`, depth first and in order, including those
+/// within nested `multipart/*` nodes.
+pub fn parts(nodes: &[Node]) -> impl Iterator<Item = &Part> {
+    Leaves::new(nodes).filter_map(|node| match *node {
+        Node::Part(ref part) => Some(part),
+        _ => None,
+    })
+}
+
+// A depth-first iterator over the nodes of a tree which are not `Node::Multipart`
+struct Leaves<'a> {
+    stack: Vec<::std::slice::Iter<'a, Node>>,
+}
+
+impl<'a> Leaves<'a> {
+    fn new(nodes: &'a [Node]) -> Leaves<'a> {
+        Leaves { stack: vec![nodes.iter()] }
+    }
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        loop {
+            let node = match self.stack.last_mut()?.next() {
+                Some(node) => node,
+                None => {
+                    self.stack.pop();
+                    continue;
+                },
+            };
+            match *node {
+                Node::Multipart((_, ref subnodes)) => self.stack.push(subnodes.iter()),
+                _ => return Some(node),
+            }
+        }
+    }
+}
+
+/// Pick the best alternative from a `multipart/alternative` node, given the content types
+/// acceptable to the caller in order of preference (most preferred first).  A `*` type or
+/// subtype in a preference matches any; parameters are ignored.
+///
+/// Returns the sub-node matching the earliest preference.  If several match it, the last is
+/// returned, as RFC 2046 orders alternatives from least to most faithful.  A sub-node
+/// without a `Content-Type` is taken to be `text/plain`.  Returns `None` if `multipart` is
+/// not a `Node::Multipart` or nothing matches.
+pub fn select_alternative<'a>(multipart: &'a Node, preferences: &[Mime]) -> Option<&'a Node> {
+    let subnodes = match *multipart {
+        Node::Multipart((_, ref subnodes)) => subnodes,
+        _ => return None,
+    };
+    preferences.iter().find_map(|preference| {
+        subnodes.iter().rev().find(|node| {
+            let ct: Option<&ContentType> = node.headers().get();
+            match ct {
+                Some(&ContentType(Mime(ref top, ref sub, _))) => mime_matches(preference, top, sub),
+                None => mime_matches(preference, &TopLevel::Text, &SubLevel::Plain),
+            }
+        })
+    })
+}
+
+/// Put the sub-nodes of a `multipart/alternative` body into the order RFC 2046 requires,
+/// from least to most faithful, before writing it: `text/plain` first, then other `text/*`
+/// types, then `text/html`, then everything else (such as nested `multipart/related`
+/// bodies).  A node without a `Content-Type` is taken to be `text/plain`.  The sort is
+/// stable, so nodes of equal rank keep their relative order.
+pub fn sort_alternatives(nodes: &mut [Node]) {
+    nodes.sort_by_key(alternative_rank);
+}
+
+fn alternative_rank(node: &Node) -> u8 {
+    let ct: Option<&ContentType> = node.headers().get();
+    match ct {
+        None => 0,
+        Some(&ContentType(Mime(TopLevel::Text, SubLevel::Plain, _))) => 0,
+        Some(&ContentType(Mime(TopLevel::Text, SubLevel::Html, _))) => 2,
+        Some(&ContentType(Mime(TopLevel::Text, _, _))) => 1,
+        Some(_) => 3,
+    }
+}
+
+fn mime_matches(preference: &Mime, top: &TopLevel, sub: &SubLevel) -> bool {
+    let Mime(ref want_top, ref want_sub, _) = *preference;
+    (*want_top == TopLevel::Star || want_top == top) &&
+        (*want_sub == SubLevel::Star || want_sub == sub)
+}
+
 /// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, streaming
 /// files to disk and keeping the rest in memory.  Recursive `multipart/*` parts will are
 /// parsed as well and returned within a `Node::Multipart` variant.
@@ -132,15 +701,119 @@ pub enum Node {
 /// parameter will be streamed to files.
 ///
 /// It is presumed that the headers are still in the stream.  If you have them separately,
-/// use `read_multipart_body()` instead.
+/// use `read_multipart_body()` instead.  If those headers carry a `Content-Type` that is not
+/// `multipart/*`, `Error::NotMultipart` is returned before any of the body is read.
 pub fn read_multipart<S: Read>(
     stream: &mut S,
     always_use_files: bool)
     -> Result<Vec<Node>, Error>
 {
-    let mut reader = BufReader::with_capacity(4096, stream);
+    read_multipart_with_options(stream, &ParseOptions::new(always_use_files))
+}
+
+/// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart()` does, but under the given `ParseOptions`.
+pub fn read_multipart_with_options<S: Read>(
+    stream: &mut S,
+    options: &ParseOptions)
+    -> Result<Vec<Node>, Error>
+{
+    let (_, nodes) = read_multipart_with_headers(stream, options)?;
+    Ok(nodes)
+}
+
+/// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart()` does, but within the limits of the given `ParseBudget` on the bytes
+/// read, the time taken and the number of parts (of fields and of files).  Each limit which
+/// is exceeded aborts the parse with its own error: `Error::BodyTooLarge`,
+/// `Error::TimedOut`, `Error::TooManyParts`, `Error::TooManyFields` or
+/// `Error::TooManyFiles`.
+pub fn read_multipart_with_budget<S: Read>(
+    stream: &mut S,
+    always_use_files: bool,
+    budget: ParseBudget)
+    -> Result<Vec<Node>, Error>
+{
+    let options = ParseOptions {
+        budget,
+        ..ParseOptions::new(always_use_files)
+    };
+    read_multipart_with_options(stream, &options)
+}
+
+/// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_with_options()` does, also returning the top-level headers which were
+/// read from the stream.  This suits proxies, which can re-emit the `Content-Type` (and so
+/// the boundary) exactly as it was received, from `headers.get_raw("Content-Type")`.
+pub fn read_multipart_with_headers<S: Read>(
+    stream: &mut S,
+    options: &ParseOptions)
+    -> Result<(Headers, Vec<Node>), Error>
+{
+    read_multipart_with_control(stream, options, &mut Control::default())
+}
+
+/// Scratch buffers for parsing, which can be kept and passed to each of many parses (see
+/// `read_multipart_with_context()`) so that they need not be allocated afresh each time.
+/// This suits servers which parse a high volume of small bodies.
+#[derive(Debug, Default)]
+pub struct MultipartContext {
+    buf: Vec<u8>,
+}
+
+impl MultipartContext {
+    /// A new context, with nothing yet allocated
+    pub fn new() -> MultipartContext {
+        MultipartContext::default()
+    }
+}
+
+/// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart()` does, but reusing the scratch buffers held by `context` (and leaving
+/// them there for the next parse) rather than allocating them.
+pub fn read_multipart_with_context<S: Read>(
+    stream: &mut S,
+    context: &mut MultipartContext,
+    always_use_files: bool)
+    -> Result<Vec<Node>, Error>
+{
+    let mut control = Control {
+        buf: ::std::mem::take(&mut context.buf),
+        ..Default::default()
+    };
+    let options = ParseOptions::new(always_use_files);
+    let result = read_multipart_with_control(stream, &options, &mut control);
+    context.buf = control.buf;
+    context.buf.clear();
+    let (_, nodes) = result?;
+    Ok(nodes)
+}
+
+fn read_multipart_with_control<S: Read>(
+    stream: &mut S,
+    options: &ParseOptions,
+    control: &mut Control)
+    -> Result<(Headers, Vec<Node>), Error>
+{
+    let mut reader = body_reader(stream, options);
     let mut nodes: Vec<Node> = Vec::new();
+    let result = read_main_headers(&mut reader, options).and_then(|headers| {
+        check_multipart(&headers)?;
+        Ok(headers)
+    });
+    let headers = check_progress(&reader.inner, result)?;
+    parse_body(&mut reader, BodyBoundary::Headers(&headers), &mut nodes, options, control)?;
+    Ok((headers, nodes))
+}
+
+fn read_main_headers<R: BufRead>(reader: &mut R, options: &ParseOptions) -> Result<Headers, Error> {
+    read_entity_headers(reader, options)
+}
 
+// Read and parse a block of headers ending in a blank line
+fn read_entity_headers<R: BufRead>(reader: &mut R, options: &ParseOptions)
+    -> Result<Headers, Error>
+{
     let mut buf: Vec<u8> = Vec::new();
 
     let (_, found) = reader.stream_until_token(b"\r\n\r\n", &mut buf)?;
@@ -150,17 +823,81 @@ pub fn read_multipart<S: Read>(
     buf.extend(b"\r\n\r\n".iter().cloned());
 
     // Parse the headers
-    let mut header_memory = [httparse::EMPTY_HEADER; 64];
-    let headers = match httparse::parse_headers(&buf, &mut header_memory) {
-        Ok(httparse::Status::Complete((_, raw_headers))) => {
-            Headers::from_raw(raw_headers).map_err(|e| From::from(e))
-        },
-        Ok(httparse::Status::Partial) => Err(Error::PartialHeaders),
-        Err(err) => Err(From::from(err)),
-    }?;
+    let mut header_memory = vec![httparse::EMPTY_HEADER; header_capacity(&buf)];
+    let raw = match httparse::parse_headers(&buf, &mut header_memory)? {
+        httparse::Status::Complete((_, raw)) => raw,
+        httparse::Status::Partial => return Err(Error::PartialHeaders),
+    };
+    headers_from_raw(raw, options)
+}
 
-    inner(&mut reader, &headers, &mut nodes, always_use_files)?;
-    Ok(nodes)
+/// Parse a single MIME entity (headers, a blank line, then the body, without any multipart
+/// boundaries) from a `Read`able stream into a `Node`.  The rest of the stream after the
+/// headers is taken as the body.
+///
+/// The part is streamed to a file or kept in memory just as a part of a `multipart/*` body
+/// would be, and if `always_use_file` is true it is always streamed to a file.  If the
+/// entity is itself `multipart/*`, it is parsed into a `Node::Multipart`.
+pub fn read_part<S: Read>(stream: &mut S, always_use_file: bool) -> Result<Node, Error> {
+    read_part_with_options(stream, &ParseOptions::new(always_use_file))
+}
+
+/// Parse a single MIME entity from a `Read`able stream into a `Node`, as `read_part()`
+/// does, but under the given `ParseOptions`.
+pub fn read_part_with_options<S: Read>(stream: &mut S, options: &ParseOptions)
+    -> Result<Node, Error>
+{
+    let mut reader = BufReader::with_capacity(4096, ProgressReader::new(stream, options));
+    let result = read_entity(&mut reader, options);
+    check_progress(&reader, result)
+}
+
+fn read_entity<R: BufRead>(reader: &mut R, options: &ParseOptions) -> Result<Node, Error> {
+    let headers = read_entity_headers(reader, options)?;
+
+    let is_multipart = matches!(headers.get::<ContentType>(),
+                                Some(ContentType(Mime(TopLevel::Multipart, _, _))));
+    if is_multipart {
+        let mut nodes: Vec<Node> = Vec::new();
+        let boundary = get_multipart_boundary(&headers)?;
+        inner(reader, &boundary, &mut nodes, options, &mut Control::default())?;
+        return Ok(Node::Multipart((headers, nodes)));
+    }
+
+    if options.always_use_files || options.disposition_storage.is_file(&headers) {
+        let mut filepart = create_filepart(headers, options)?;
+        let mut file = filepart.create_file()?;
+        let mut out = LimitWriter::new(&mut file, options.max_part_bytes);
+        let result = ::std::io::copy(reader, &mut out);
+        filepart.size = Some(out.check(result)?);
+        drop(file);
+        check_file_content_type(&filepart, options)?;
+        Ok(Node::File(filepart))
+    } else {
+        let mut body: Vec<u8> = Vec::new();
+        let mut out = LimitWriter::new(&mut body, options.max_part_bytes);
+        let result = ::std::io::copy(reader, &mut out);
+        out.check(result)?;
+        check_content_type(&headers, &body, options)?;
+        Ok(Node::Part(Part {
+            headers,
+            body,
+        }))
+    }
+}
+
+// If parsing failed because the stream stopped making progress or was cancelled, say so
+// rather than reporting whatever the parser made of it.
+fn check_progress<R, T>(reader: &BufReader<ProgressReader<R>>, result: Result<T, Error>)
+    -> Result<T, Error>
+{
+    match result {
+        Err(_) if reader.get_ref().cancelled => Err(Error::Cancelled),
+        Err(_) if reader.get_ref().stalled => Err(Error::NoProgress),
+        Err(_) if reader.get_ref().too_large => Err(Error::BodyTooLarge),
+        Err(_) if reader.get_ref().timed_out => Err(Error::TimedOut),
+        result => result,
+    }
 }
 
 /// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, streaming
@@ -173,88 +910,632 @@ pub fn read_multipart<S: Read>(
 ///
 /// It is presumed that you have the `Headers` already and the stream starts at the body.
 /// If the headers are still in the stream, use `read_multipart()` instead.
+///
+/// A part's body ends at the first line terminator followed by the boundary, as MIME
+/// requires, so a body which itself contains that sequence is cut short there, and if what
+/// follows happens to parse, the truncation goes unnoticed.  A sender choosing random
+/// boundaries (see `generate_boundary()`) makes this vanishingly unlikely, but a boundary
+/// chosen by someone who also controls the content cannot be relied upon.  Where the
+/// sender gives each part a `Content-Length`, the `content_length_framing` option reads
+/// bodies by their length instead, so their content is never scanned for the boundary.
 pub fn read_multipart_body<S: Read>(
     stream: &mut S,
     headers: &Headers,
     always_use_files: bool)
     -> Result<Vec<Node>, Error>
 {
-    let mut reader = BufReader::with_capacity(4096, stream);
+    read_multipart_body_with_options(stream, headers, &ParseOptions::new(always_use_files))
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_body()` does, but under the given `ParseOptions`.
+pub fn read_multipart_body_with_options<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions)
+    -> Result<Vec<Node>, Error>
+{
     let mut nodes: Vec<Node> = Vec::new();
-    inner(&mut reader, headers, &mut nodes, always_use_files)?;
+    read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                           options, &mut Control::default())?;
     Ok(nodes)
 }
 
-fn inner<R: BufRead>(
-    reader: &mut R,
-    headers: &Headers,
+// Where the boundary of a multipart body comes from
+enum BodyBoundary<'h> {
+    // The `Content-Type` of these headers or, if the options allow it, the body itself (see
+    // `body_boundary()`)
+    Headers(&'h Headers),
+    // This boundary, with its leading `--`
+    Given(Vec<u8>),
+}
+
+// The reader all multipart bodies are parsed through: buffered, watching the progress and
+// limits of the stream (see `check_progress()`), and able to push back a line read ahead
+type BodyReader<S> = Pushback<BufReader<ProgressReader<S>>>;
+
+fn body_reader<S: Read>(stream: S, options: &ParseOptions) -> BodyReader<S> {
+    Pushback::new(BufReader::with_capacity(4096, ProgressReader::new(stream, options)))
+}
+
+// Parse a multipart body from the `stream` into `nodes`.  Every `read_multipart_body*()`
+// variant comes here, differing only in the `boundary` and in what the `control` collects.
+fn read_body_with_control<S: Read>(
+    stream: &mut S,
+    boundary: BodyBoundary,
     nodes: &mut Vec<Node>,
-    always_use_files: bool)
+    options: &ParseOptions,
+    control: &mut Control)
     -> Result<(), Error>
 {
-    let mut buf: Vec<u8> = Vec::new();
+    parse_body(&mut body_reader(stream, options), boundary, nodes, options, control)
+}
 
-    let boundary = get_multipart_boundary(headers)?;
+// Parse one multipart body from the `reader` into `nodes`, as `read_body_with_control()`
+// does, leaving the reader just past it
+fn parse_body<S: Read>(
+    reader: &mut BodyReader<S>,
+    boundary: BodyBoundary,
+    nodes: &mut Vec<Node>,
+    options: &ParseOptions,
+    control: &mut Control)
+    -> Result<(), Error>
+{
+    let boundary = match boundary {
+        BodyBoundary::Headers(headers) => body_boundary(reader, headers, options),
+        BodyBoundary::Given(boundary) => Ok(boundary),
+    };
+    let result = boundary.and_then(|boundary| inner(reader, &boundary, nodes, options, control));
+    check_progress(&reader.inner, result)
+}
 
-    // Read past the initial boundary
-    let (_, found) = reader.stream_until_token(&boundary, &mut buf)?;
-    if ! found { return Err(Error::EofBeforeFirstBoundary); }
+/// Parse a gzip-compressed MIME `multipart/*` from a `Read`able stream into a `Vec` of
+/// `Node`s, as `read_multipart()` does once the stream is decompressed.  This suits a
+/// whole multipart entity (headers included) which was compressed, such as a stored message.
+///
+/// Only available with the `flate2` feature.
+#[cfg(feature = "flate2")]
+pub fn read_multipart_gzip<S: Read>(
+    stream: &mut S,
+    always_use_files: bool)
+    -> Result<Vec<Node>, Error>
+{
+    read_multipart(&mut flate2::read::GzDecoder::new(stream), always_use_files)
+}
 
-    // Define the boundary, including the line terminator preceding it.
-    // Use their first line terminator to determine whether to use CRLF or LF.
-    let (lt, ltlt, lt_boundary) = {
-        let peeker = reader.fill_buf()?;
-        if peeker.len() > 1 && &peeker[..2]==b"\r\n" {
-            let mut output = Vec::with_capacity(2 + boundary.len());
-            output.push(b'\r');
-            output.push(b'\n');
-            output.extend(boundary.clone());
-            (vec![b'\r', b'\n'], vec![b'\r', b'\n', b'\r', b'\n'], output)
-        }
-        else if peeker.len() > 0 && peeker[0]==b'\n' {
-            let mut output = Vec::with_capacity(1 + boundary.len());
-            output.push(b'\n');
-            output.extend(boundary.clone());
-            (vec![b'\n'], vec![b'\n', b'\n'], output)
-        }
-        else {
-            return Err(Error::NoCrLfAfterBoundary);
-        }
-    };
+/// Parse a gzip-compressed MIME `multipart/*` body from a `Read`able stream into a `Vec` of
+/// `Node`s, as `read_multipart_body()` does once the stream is decompressed.  This suits a
+/// request with `Content-Encoding: gzip`, where the body as a whole (not each part) is
+/// compressed; the `headers` are as received.
+///
+/// Only available with the `flate2` feature.
+#[cfg(feature = "flate2")]
+pub fn read_multipart_body_gzip<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    always_use_files: bool)
+    -> Result<Vec<Node>, Error>
+{
+    read_multipart_body(&mut flate2::read::GzDecoder::new(stream), headers, always_use_files)
+}
 
-    loop {
-        // If the next two lookahead characters are '--', parsing is finished.
+// The boundary (with its leading `--`) from the headers, or if they do not give one and
+// the options allow it, from the first line of the body which looks like a boundary
+fn body_boundary<R: BufRead>(reader: &mut Pushback<R>, headers: &Headers, options: &ParseOptions)
+    -> Result<Vec<u8>, Error>
+{
+    match get_multipart_boundary(headers) {
+        Err(Error::NoRequestContentType) | Err(Error::BoundaryNotSpecified)
+            if options.detect_boundary =>
         {
+            next_boundary(reader)?.ok_or(Error::EofBeforeFirstBoundary)
+        },
+        result => result,
+    }
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_body()` does, but using the given `boundary` (as it would appear in the
+/// `Content-Type` header, without the leading `--`) rather than one from the headers.  This
+/// suits protocols which convey the boundary some other way.
+pub fn read_multipart_body_with_boundary<S: Read>(
+    stream: &mut S,
+    boundary: &[u8],
+    always_use_files: bool)
+    -> Result<Vec<Node>, Error>
+{
+    read_multipart_body_with_boundary_and_options(
+        stream, boundary, &ParseOptions::new(always_use_files))
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_body_with_boundary()` does, but under the given `ParseOptions`.
+pub fn read_multipart_body_with_boundary_and_options<S: Read>(
+    stream: &mut S,
+    boundary: &[u8],
+    options: &ParseOptions)
+    -> Result<Vec<Node>, Error>
+{
+    if boundary.is_empty() {
+        return Err(Error::BoundaryNotSpecified);
+    }
+    let mut full_boundary: Vec<u8> = b"--".to_vec();
+    full_boundary.extend_from_slice(boundary);
+
+    let mut nodes: Vec<Node> = Vec::new();
+    read_body_with_control(stream, BodyBoundary::Given(full_boundary), &mut nodes, options,
+                           &mut Control::default())?;
+    Ok(nodes)
+}
+
+/// Statistics on the reading of one part, from `read_multipart_body_with_stats()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartStats {
+    /// The number of bytes of headers, including the blank line which ends them
+    pub header_bytes: usize,
+    /// The number of bytes of body
+    pub body_bytes: usize,
+    /// Whether the body was streamed to a file
+    pub to_disk: bool,
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_body_with_options()` does, also returning statistics on each part for
+/// metrics.  There is one `PartStats` for each `Node::Part` and `Node::File`, in the order
+/// they appear in the body, including those within nested multiparts.
+pub fn read_multipart_body_with_stats<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions)
+    -> Result<(Vec<Node>, Vec<PartStats>), Error>
+{
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut stats: Vec<PartStats> = Vec::new();
+    let mut control = Control {
+        stats: Some(&mut stats),
+        ..Default::default()
+    };
+    read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                           options, &mut control)?;
+    Ok((nodes, stats))
+}
+
+/// Something suspicious noticed while parsing, which did not stop the parse.  See
+/// `read_multipart_body_with_warnings()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseWarning {
+    /// The boundary of the enclosing multipart (with its leading `--`) appears within the
+    /// body of a part, so the body was generated with a boundary which collides with its
+    /// content.  Where the boundary began a line the part will have been cut short there,
+    /// and what followed taken for another part.  `part` is the index of the part among all
+    /// parts in the order they appear in the body, including those within nested multiparts.
+    BoundaryInBody {
+        /// The index of the part
+        part: usize,
+    },
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_body_with_options()` does, also checking the body of each part for the
+/// boundary and returning a `ParseWarning` for each part where it is found.  This is a
+/// diagnostic aid for parts which come out truncated because the sender chose a boundary
+/// which occurs in the content; it costs a scan of every body, so is not done otherwise.
+pub fn read_multipart_body_with_warnings<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions)
+    -> Result<(Vec<Node>, Vec<ParseWarning>), Error>
+{
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut warnings: Vec<ParseWarning> = Vec::new();
+    let mut control = Control {
+        warnings: Some(&mut warnings),
+        ..Default::default()
+    };
+    read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                           options, &mut control)?;
+    Ok((nodes, warnings))
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_body_with_options()` does, also returning the headers of each part
+/// exactly as they were received: in their original order, with their original case, and
+/// including any duplicates, which the typed `Headers` may not keep.  This suits forensic
+/// inspection of what was sent, such as looking for header smuggling.  There is one
+/// `RawHeaders` for each node, in the order the parts appear in the body, so that a
+/// `Node::Multipart` comes before the nodes within it.
+pub fn read_multipart_body_with_raw_headers<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions)
+    -> Result<(Vec<Node>, Vec<RawHeaders>), Error>
+{
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut raw_headers: Vec<RawHeaders> = Vec::new();
+    let mut control = Control {
+        raw_headers: Some(&mut raw_headers),
+        ..Default::default()
+    };
+    read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                           options, &mut control)?;
+    Ok((nodes, raw_headers))
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, under
+/// the given `ParseOptions`, on a best-effort basis.
+///
+/// Errors which are confined to a single part (unparseable part headers, or a part exceeding
+/// `max_part_bytes`) do not end the parse; the part is skipped and parsing continues with
+/// the next one.  Any other error ends the parse.
+///
+/// Returns the nodes which were parsed, along with `Ok(())` if there were no errors, the
+/// error if there was just one, or `Error::Multiple` holding every error if there were
+/// several.
+pub fn read_multipart_body_best_effort<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions)
+    -> (Vec<Node>, Result<(), Error>)
+{
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut errors: Vec<Error> = Vec::new();
+    let mut control = Control {
+        errors: Some(&mut errors),
+        ..Default::default()
+    };
+    let result = read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                                        options, &mut control);
+    if let Err(e) = result {
+        errors.push(e);
+    }
+    (nodes, combine_errors(errors))
+}
+
+/// Parse a stream holding several complete MIME `multipart/*` bodies one after another, as
+/// some tools produce by concatenating them, into a `Vec` of `Node`s for each body.
+///
+/// The first body uses the boundary from the `headers`.  After each body ends, its epilogue
+/// is read past up to the first line which looks like the opening boundary of another body
+/// (a line of `--` followed by a valid boundary), and parsing continues with that boundary,
+/// which may or may not be the same as before.  Parsing ends at the end of the stream.  An
+/// epilogue line which merely looks like a boundary will be taken for one, so this is only
+/// for streams known to be concatenated.
+pub fn read_multipart_bodies<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions)
+    -> Result<Vec<Vec<Node>>, Error>
+{
+    let mut reader = body_reader(stream, options);
+    let mut bodies: Vec<Vec<Node>> = Vec::new();
+    let mut boundary = BodyBoundary::Headers(headers);
+    loop {
+        let mut nodes: Vec<Node> = Vec::new();
+        parse_body(&mut reader, boundary, &mut nodes, options, &mut Control::default())?;
+        bodies.push(nodes);
+
+        let result = next_boundary(&mut reader);
+        match check_progress(&reader.inner, result)? {
+            Some(next) => boundary = BodyBoundary::Given(next),
+            None => return Ok(bodies),
+        }
+    }
+}
+
+// Read past the epilogue of a multipart body up to the first line which looks like the
+// opening boundary of another body, and push that line back to be read again.  Returns the
+// boundary (with its leading `--`), or `None` at the end of the stream.
+fn next_boundary<R: BufRead>(reader: &mut Pushback<R>) -> Result<Option<Vec<u8>>, Error> {
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        line.truncate(0);
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        let mut content = &line[..];
+        if content.ends_with(b"\n") {
+            content = &content[..content.len() - 1];
+        }
+        if content.ends_with(b"\r") {
+            content = &content[..content.len() - 1];
+        }
+        if is_boundary_line(content) {
+            let boundary = content.to_vec();
+            reader.pending = line;
+            reader.pos = 0;
+            return Ok(Some(boundary));
+        }
+    }
+}
+
+// Whether a line (without its terminator) is `--` followed by a valid boundary (RFC 2046
+// section 5.1.1), and not a close delimiter
+fn is_boundary_line(line: &[u8]) -> bool {
+    let boundary = match line.strip_prefix(b"--") {
+        Some(boundary) => boundary,
+        None => return false,
+    };
+    ! boundary.is_empty() &&
+        boundary.len() <= MAX_BOUNDARY_LEN &&
+        ! boundary.ends_with(b" ") &&
+        ! boundary.ends_with(b"--") &&
+        boundary.iter().all(|&b| b.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(&b))
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream, returning only the first part
+/// whose headers match the `predicate` (or `None` if no part does).  Parts before it are
+/// read past and discarded without being stored, and parsing stops once it is found.  A
+/// nested `multipart/*` part is matched by its own headers, and returned whole.
+///
+/// As with `read_multipart_body()`, it is presumed that you have the `Headers` already and
+/// the stream starts at the body.
+pub fn read_until<S, F>(
+    stream: &mut S,
+    headers: &Headers,
+    predicate: F)
+    -> Result<Option<Node>, Error>
+    where S: Read, F: Fn(&Headers) -> bool
+{
+    read_until_with_options(stream, headers, predicate, &ParseOptions::default())
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream, returning only the first part
+/// whose headers match the `predicate`, as `read_until()` does, but under the given
+/// `ParseOptions`.
+pub fn read_until_with_options<S, F>(
+    stream: &mut S,
+    headers: &Headers,
+    predicate: F,
+    options: &ParseOptions)
+    -> Result<Option<Node>, Error>
+    where S: Read, F: Fn(&Headers) -> bool
+{
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut control = Control {
+        select: Some(&predicate),
+        ..Default::default()
+    };
+    read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                           options, &mut control)?;
+    Ok(nodes.pop())
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream, sending each top-level `Node`
+/// down the channel `tx` as soon as it has been parsed, so that another thread can process
+/// the parts while parsing continues.  A nested `multipart/*` part is sent whole, once all of
+/// it has been parsed.
+///
+/// As with `read_multipart_body()`, it is presumed that you have the `Headers` already and
+/// the stream starts at the body.  If the receiver is dropped, parsing stops with
+/// `Error::Disconnected`.  Nodes already sent are not recalled if parsing later fails.
+pub fn read_multipart_to_channel<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    always_use_files: bool,
+    tx: Sender<Node>)
+    -> Result<(), Error>
+{
+    read_multipart_to_channel_with_options(stream, headers, tx, &ParseOptions::new(always_use_files))
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream, sending each top-level `Node`
+/// down the channel `tx` as `read_multipart_to_channel()` does, but under the given
+/// `ParseOptions`.
+pub fn read_multipart_to_channel_with_options<S: Read>(
+    stream: &mut S,
+    headers: &Headers,
+    tx: Sender<Node>,
+    options: &ParseOptions)
+    -> Result<(), Error>
+{
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut send = |node: Node| tx.send(node).map_err(|_| Error::Disconnected);
+    let mut control = Control {
+        sink: Some(&mut send),
+        ..Default::default()
+    };
+    read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                           options, &mut control)
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_body_with_options()` does, calling `on_file` with each file part
+/// (including those within nested `multipart/*` parts) as soon as its content is complete,
+/// before the rest of the body is parsed.  This lets work on each file, such as scanning or
+/// uploading it, start while parsing continues.  An error returned by `on_file` aborts the
+/// parse with that error.
+pub fn read_multipart_body_with_file_callback<S, F>(
+    stream: &mut S,
+    headers: &Headers,
+    options: &ParseOptions,
+    mut on_file: F)
+    -> Result<Vec<Node>, Error>
+    where S: Read, F: FnMut(&FilePart) -> Result<(), Error>
+{
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut control = Control {
+        on_file: Some(&mut on_file),
+        ..Default::default()
+    };
+    read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                           options, &mut control)?;
+    Ok(nodes)
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream, returning just the headers of
+/// each part, in order.  The bodies are read past and discarded without being stored, which
+/// suits routing or indexing decisions made from the headers alone.  A nested
+/// `multipart/*` part gives only its own headers, not those of the parts within it.
+///
+/// As with `read_multipart_body()`, it is presumed that you have the `Headers` already and
+/// the stream starts at the body.
+pub fn read_part_headers<S: Read>(stream: &mut S, headers: &Headers)
+    -> Result<Vec<Headers>, Error>
+{
+    let options = ParseOptions::default();
+    let mut nodes: Vec<Node> = Vec::new();
+    let all_headers: RefCell<Vec<Headers>> = RefCell::new(Vec::new());
+    let record = |part_headers: &Headers| {
+        all_headers.borrow_mut().push(part_headers.clone());
+        false
+    };
+    let mut control = Control {
+        select: Some(&record),
+        ..Default::default()
+    };
+    read_body_with_control(stream, BodyBoundary::Headers(headers), &mut nodes,
+                           &options, &mut control)?;
+    Ok(all_headers.into_inner())
+}
+
+fn combine_errors(mut errors: Vec<Error>) -> Result<(), Error> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::Multiple(errors)),
+    }
+}
+
+/// Parse a MIME `multipart/*` body directly from a hyper server `Request`, streaming files
+/// to disk and keeping the rest in memory.  This deconstructs the request, taking its
+/// `Headers` and body reader, and then behaves like `read_multipart_body()`.
+///
+/// Only available with the `hyper-server` feature.
+#[cfg(feature = "hyper-server")]
+pub fn read_multipart_from_request(
+    request: hyper::server::Request,
+    always_use_files: bool)
+    -> Result<Vec<Node>, Error>
+{
+    let (_, _, headers, _, _, mut reader) = request.deconstruct();
+    read_multipart_body(&mut reader, &headers, always_use_files)
+}
+
+// A callback given each file part as soon as it is complete
+type FileCallback<'a> = dyn FnMut(&FilePart) -> Result<(), Error> + 'a;
+
+// Optional behaviours of `inner`
+#[derive(Default)]
+struct Control<'a> {
+    // If given, errors confined to a single part are recorded here and the part skipped,
+    // rather than ending the parse
+    errors: Option<&'a mut Vec<Error>>,
+    // If given, parts whose headers do not match are skipped, and parsing stops after the
+    // first part which does match
+    select: Option<&'a dyn Fn(&Headers) -> bool>,
+    // If given, statistics on each part are recorded here
+    stats: Option<&'a mut Vec<PartStats>>,
+    // If given, part bodies are checked for the boundary, and warnings recorded here
+    warnings: Option<&'a mut Vec<ParseWarning>>,
+    // If given, each part's headers are also recorded here as they were received
+    raw_headers: Option<&'a mut Vec<RawHeaders>>,
+    // If given, each node is passed here as soon as it is parsed rather than being added to
+    // `nodes`
+    sink: Option<&'a mut dyn FnMut(Node) -> Result<(), Error>>,
+    // If given, each file part, nested or not, is passed here as soon as it is complete
+    on_file: Option<&'a mut FileCallback<'a>>,
+    // The bytes of part bodies kept in memory so far, for the `SpillPolicy`
+    memory_used: usize,
+    // The bytes of part bodies written to files so far, for `max_total_file_bytes`
+    file_bytes: u64,
+    // The parts begun so far, including nested ones, for the `ParseBudget`
+    parts: usize,
+    // The parts kept as fields and as files so far, including nested ones, for the
+    // `ParseBudget`
+    fields: usize,
+    files: usize,
+    // Scratch space for each part's headers, which may be reused across parses
+    buf: Vec<u8>,
+}
+
+impl<'a> Control<'a> {
+    // Record a warning if the boundary was found within the body of the `part`th part
+    fn warn_collision(&mut self, collided: bool, part: usize) {
+        if let (true, Some(warnings)) = (collided, self.warnings.as_mut()) {
+            warnings.push(ParseWarning::BoundaryInBody { part });
+        }
+    }
+
+    fn emit(&mut self, nodes: &mut Vec<Node>, node: Node) -> Result<(), Error> {
+        if let (Node::File(filepart), Some(on_file)) = (&node, self.on_file.as_mut()) {
+            on_file(filepart)?;
+        }
+        match self.sink {
+            Some(ref mut sink) => sink(node),
+            None => {
+                nodes.push(node);
+                Ok(())
+            },
+        }
+    }
+}
+
+// Parse the body of a multipart into `nodes`.  The `boundary` includes its leading `--`.
+fn inner<R: BufRead>(
+    reader: &mut R,
+    boundary: &[u8],
+    nodes: &mut Vec<Node>,
+    options: &ParseOptions,
+    control: &mut Control)
+    -> Result<(), Error>
+{
+    let (lt, lt_boundary) = read_first_boundary(reader, boundary)?;
+
+    loop {
+        if options.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        // If the next two lookahead characters are '--', parsing is finished.
+        {
+            skip_transport_padding(reader)?;
             let peeker = reader.fill_buf()?;
             if peeker.len() >= 2 && &peeker[..2] == b"--" {
                 return Ok(());
             }
+            if peeker.is_empty() {
+                return Err(Error::EofAfterBoundary);
+            }
         }
 
         // Read the line terminator after the boundary
-        let (_, found) = reader.stream_until_token(&lt, &mut buf)?;
+        let (_, found) = reader.stream_until_token(&lt, &mut control.buf)?;
         if ! found { return Err(Error::NoCrLfAfterBoundary); }
 
-        // Read the headers (which end in 2 line terminators)
-        buf.truncate(0); // start fresh
-        let (_, found) = reader.stream_until_token(&ltlt, &mut buf)?;
-        if ! found { return Err(Error::EofInPartHeaders); }
+        // Count the part against the budget
+        let part = control.parts;
+        control.parts += 1;
+        if options.budget.max_parts.is_some_and(|max| control.parts > max) {
+            return Err(Error::TooManyParts);
+        }
 
-        // Keep the 2 line terminators as httparse will expect it
-        buf.extend(ltlt.iter().cloned());
+        // Read the headers, a line at a time up to the blank line which ends them
+        control.buf.truncate(0); // start fresh
+        let header_bytes = read_header_lines(reader, &lt, &mut control.buf, options)?;
 
         // Parse the headers
-        let part_headers = {
-            let mut header_memory = [httparse::EMPTY_HEADER; 4];
-            match httparse::parse_headers(&buf, &mut header_memory) {
-                Ok(httparse::Status::Complete((_, raw_headers))) => {
-                    Headers::from_raw(raw_headers).map_err(|e| From::from(e))
-                },
-                Ok(httparse::Status::Partial) => Err(Error::PartialHeaders),
-                Err(err) => Err(From::from(err)),
-            }?
+        let preserve = control.raw_headers.is_some();
+        let parsed = parse_part_headers(&control.buf, options, preserve);
+        let (part_headers, raw_headers) = match parsed {
+            Ok(headers) => headers,
+            Err(e) => {
+                recover(&mut control.errors, e)?;
+                skip_part(reader, &lt_boundary)?;
+                continue;
+            },
         };
 
+        if let Some(select) = control.select {
+            if ! select(&part_headers) {
+                skip_part(reader, &lt_boundary)?;
+                continue;
+            }
+        }
+
+        if let (Some(all), Some(raw_headers)) = (control.raw_headers.as_mut(), raw_headers) {
+            all.push(raw_headers);
+        }
+
         // Check for a nested multipart
         let nested = {
             let ct: Option<&ContentType> = part_headers.get();
@@ -266,53 +1547,626 @@ fn inner<R: BufRead>(
             }
         };
         if nested {
+            // A nested multipart without a boundary cannot be parsed, but can be skipped
+            let nested_boundary = match get_multipart_boundary(&part_headers) {
+                Ok(boundary) => boundary,
+                Err(e) => {
+                    let e = match e {
+                        Error::BoundaryNotSpecified => Error::NestedMultipartMissingBoundary,
+                        e => e,
+                    };
+                    recover(&mut control.errors, e)?;
+                    skip_part(reader, &lt_boundary)?;
+                    continue;
+                },
+            };
+
             // Recurse:
             let mut inner_nodes: Vec<Node> = Vec::new();
-            inner(reader, &part_headers, &mut inner_nodes, always_use_files)?;
-            nodes.push(Node::Multipart((part_headers, inner_nodes)));
+            let mut nested_control = Control {
+                errors: control.errors.as_deref_mut(),
+                select: None,
+                stats: control.stats.as_deref_mut(),
+                warnings: control.warnings.as_deref_mut(),
+                raw_headers: control.raw_headers.as_deref_mut(),
+                sink: None,
+                // Reborrowed by hand, as `as_deref_mut()` would not shorten the closure's
+                // lifetime to the nested control's
+                on_file: match control.on_file {
+                    Some(ref mut on_file) => Some(&mut **on_file),
+                    None => None,
+                },
+                memory_used: control.memory_used,
+                file_bytes: control.file_bytes,
+                parts: control.parts,
+                fields: control.fields,
+                files: control.files,
+                buf: ::std::mem::take(&mut control.buf),
+            };
+            let result = inner(reader, &nested_boundary, &mut inner_nodes, options,
+                               &mut nested_control);
+            control.memory_used = nested_control.memory_used;
+            control.file_bytes = nested_control.file_bytes;
+            control.parts = nested_control.parts;
+            control.fields = nested_control.fields;
+            control.files = nested_control.files;
+            control.buf = nested_control.buf;
+            result?;
+            control.emit(nodes, Node::Multipart((part_headers, inner_nodes)))?;
+
+            // Skip the nested epilogue, up to our next boundary
+            let (_, found) = reader.stream_until_token(&lt_boundary, &mut ::std::io::sink())?;
+            if ! found { return Err(Error::EofInPart); }
+            if control.select.is_some() {
+                return Ok(());
+            }
             continue;
         }
 
-        let is_file = always_use_files || {
-            let cd: Option<&ContentDisposition> = part_headers.get();
-            if cd.is_some() {
-                if cd.unwrap().disposition == DispositionType::Attachment {
-                    true
-                } else {
-                    cd.unwrap().parameters.iter().any(|x| match x {
-                        &DispositionParam::Filename(_,_,_) => true,
-                        _ => false
-                    })
-                }
-            } else {
-                false
-            }
-        };
-        if is_file {
-            // Setup a file to capture the contents.
-            let mut filepart = FilePart::create(part_headers)?;
-            let mut file = File::create(filepart.path.clone())?;
+        // Whether to decode the body if it looks like base64
+        let detect_base64 = options.detect_base64 &&
+            transfer::transfer_encoding(&part_headers).is_none();
+
+        // The most bytes which may yet be written to files
+        let file_limit = options.max_total_file_bytes
+            .map(|max| (max as u64).saturating_sub(control.file_bytes) as usize);
+
+        // The boundary to look for within the body, if collisions are to be reported
+        let collision_check = control.warnings.as_ref().map(|_| boundary);
+
+        // The length of the body, if it is to be read by its Content-Length
+        let length = if options.content_length_framing {
+            part_headers.get::<ContentLength>().map(|&ContentLength(length)| length)
+        } else {
+            None
+        };
+
+        let is_file = options.always_use_files ||
+            options.disposition_storage.is_file(&part_headers);
+
+        // Count the part against the budget for its kind
+        if is_file {
+            control.files += 1;
+            if options.budget.max_file_parts.is_some_and(|max| control.files > max) {
+                return Err(Error::TooManyFiles);
+            }
+        } else {
+            control.fields += 1;
+            if options.budget.max_memory_parts.is_some_and(|max| control.fields > max) {
+                return Err(Error::TooManyFields);
+            }
+        }
+        if is_file && options.buffer_files_up_to.is_none() {
+            // Setup a file to capture the contents.
+            let mut filepart = create_filepart(part_headers, options)?;
+            let mut file = filepart.create_file()?;
+
+            // Stream out the file.
+            let limit = min_limit(options.max_part_bytes, file_limit);
+            let mut scan = CollisionWriter::new(&mut file, collision_check);
+            let mut out = LimitWriter::new(&mut scan, limit);
+            let result = stream_body(reader, &lt_boundary, length, &mut out);
+            let written = out.written();
+            let (read, found) = match out.check(result) {
+                Err(Error::SizeLimitExceeded) => {
+                    recover(&mut control.errors, Error::SizeLimitExceeded)?;
+                    skip_part(reader, &lt_boundary)?;
+                    continue;
+                },
+                result => result?,
+            };
+            if ! found { return Err(Error::EofInFile); }
+            control.warn_collision(scan.found(), part);
+            if length.is_some() { expect_boundary(reader, &lt_boundary)?; }
+            control.file_bytes += written;
+            filepart.size = Some(written);
+            drop(file);
+            if let Some(ref mut stats) = control.stats {
+                stats.push(PartStats { header_bytes, body_bytes: read, to_disk: true });
+            }
+            if detect_base64 {
+                if let Err(e) = decode_filepart_if_base64(&mut filepart) {
+                    recover(&mut control.errors, e)?;
+                    continue;
+                }
+            }
+            if let Err(e) = check_file_content_type(&filepart, options) {
+                recover(&mut control.errors, e)?;
+                continue;
+            }
+
+            // The body is kept as it was sent.  A Content-Transfer-Encoding (deprecated by
+            // RFC 7578 section 4.7) is decoded when the content is read through
+            // `FilePart::decoded_reader()`, or here already under `detect_base64`.
+
+            control.emit(nodes, Node::File(filepart))?;
+        } else {
+            // Read the body into its own buffer which moves into the Part, so that the header
+            // buffer keeps its capacity for the next part's headers.  Under a `SpillPolicy` the
+            // body moves to a file instead if it would exceed the memory budget, as does a
+            // buffered file part's body if it would exceed `buffer_files_up_to`.
+            let memory_limit = if is_file {
+                options.buffer_files_up_to
+            } else {
+                memory_limit(options, control.memory_used)
+            };
+            let mut body = SpillWriter::new(memory_limit, file_limit, options);
+            let mut scan = CollisionWriter::new(&mut body, collision_check);
+            let mut out = LimitWriter::new(&mut scan, options.max_part_bytes);
+            let result = stream_body(reader, &lt_boundary, length, &mut out);
+            let written = out.written();
+            let result = out.check(result);
+            let collided = scan.found();
+            let (read, found) = match body.check(result) {
+                Err(Error::SizeLimitExceeded) => {
+                    recover(&mut control.errors, Error::SizeLimitExceeded)?;
+                    skip_part(reader, &lt_boundary)?;
+                    continue;
+                },
+                result => result?,
+            };
+            if ! found {
+                return Err(if is_file { Error::EofInFile } else { Error::EofInPart });
+            }
+            control.warn_collision(collided, part);
+            if length.is_some() { expect_boundary(reader, &lt_boundary)?; }
+            if let Some(ref mut stats) = control.stats {
+                let to_disk = body.is_spilled();
+                stats.push(PartStats { header_bytes, body_bytes: read, to_disk });
+            }
+
+            match body.finish() {
+                Spilled::Memory(body) => {
+                    // A buffered file part is bound for disk, so counts as a file
+                    if ! is_file {
+                        control.memory_used += body.len();
+                    } else if file_limit.is_some_and(|limit| written > limit as u64) {
+                        recover(&mut control.errors, Error::SizeLimitExceeded)?;
+                        continue;
+                    } else {
+                        control.file_bytes += written;
+                    }
+                    let body = if detect_base64 {
+                        transfer::decode_if_base64(body)
+                    } else {
+                        body
+                    };
+                    if let Err(e) = check_content_type(&part_headers, &body, options) {
+                        recover(&mut control.errors, e)?;
+                        continue;
+                    }
+                    let node = if is_file {
+                        let filepart = FilePart::from_bytes(part_headers, body);
+                        Node::File(parsed_filepart(filepart, options))
+                    } else {
+                        Node::Part(Part {
+                            headers: part_headers,
+                            body,
+                        })
+                    };
+                    control.emit(nodes, node)?;
+                },
+                Spilled::File(mut filepart) => {
+                    control.file_bytes += written;
+                    filepart.headers = part_headers;
+                    filepart.size = Some(written);
+                    if detect_base64 {
+                        if let Err(e) = decode_filepart_if_base64(&mut filepart) {
+                            recover(&mut control.errors, e)?;
+                            continue;
+                        }
+                    }
+                    if let Err(e) = check_file_content_type(&filepart, options) {
+                        recover(&mut control.errors, e)?;
+                        continue;
+                    }
+                    control.emit(nodes, Node::File(filepart))?;
+                },
+            }
+        }
+
+        if control.select.is_some() {
+            return Ok(());
+        }
+    }
+}
+
+// Stream a part's body to `out`: exactly `length` bytes if it is given, otherwise everything
+// up to the boundary, which is consumed.  Returns the number of bytes streamed, and whether
+// the body was complete.
+fn stream_body<R: BufRead, W: Write>(
+    reader: &mut R,
+    lt_boundary: &[u8],
+    length: Option<u64>,
+    out: &mut W)
+    -> io::Result<(usize, bool)>
+{
+    match length {
+        Some(length) => {
+            let read = io::copy(&mut reader.take(length), out)?;
+            Ok((read as usize, read == length))
+        },
+        None => reader.stream_until_token(lt_boundary, out),
+    }
+}
+
+// Consume the boundary which must immediately follow a body read by its Content-Length
+fn expect_boundary<R: BufRead>(reader: &mut R, lt_boundary: &[u8]) -> Result<(), Error> {
+    let mut found = vec![0; lt_boundary.len()];
+    match reader.read_exact(&mut found) {
+        Ok(()) if found == lt_boundary => Ok(()),
+        Ok(()) => Err(Error::ContentLengthMismatch),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(Error::ContentLengthMismatch),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+// Fail with `Error::ContentTypeMismatch` if the options ask for content types to be
+// checked, and the content (of which `prefix` is the start) is recognisably of another type
+// than the headers declare
+fn check_content_type(headers: &Headers, prefix: &[u8], options: &ParseOptions)
+    -> Result<(), Error>
+{
+    let check = match options.content_type_check {
+        Some(ref check) => check,
+        None => return Ok(()),
+    };
+    let declared = match headers.get::<ContentType>() {
+        Some(ContentType(declared)) => declared,
+        None => return Ok(()),
+    };
+    let sniffed = match sniff::sniff(&prefix[..::std::cmp::min(prefix.len(), sniff::SNIFF_LEN)]) {
+        Some(sniffed) => sniffed,
+        None => return Ok(()),
+    };
+    let Mime(ref top, ref sub, _) = *declared;
+    let Mime(ref sniffed_top, ref sniffed_sub, _) = sniffed;
+    let octet_stream = *top == TopLevel::Application && sub.as_str() == "octet-stream";
+    let allowed = check.allowed_mismatches.iter().any(|(declared, sniffed)| {
+        mime_matches(declared, top, sub) && mime_matches(sniffed, sniffed_top, sniffed_sub)
+    });
+    if (top == sniffed_top && sub == sniffed_sub) || octet_stream || allowed {
+        Ok(())
+    } else {
+        Err(Error::ContentTypeMismatch(declared.to_string(), sniffed.to_string()))
+    }
+}
+
+// As `check_content_type()`, for a file part
+fn check_file_content_type(filepart: &FilePart, options: &ParseOptions) -> Result<(), Error> {
+    if options.content_type_check.is_none() {
+        return Ok(());
+    }
+    let mut prefix: Vec<u8> = Vec::with_capacity(sniff::SNIFF_LEN);
+    filepart.reader()?.take(sniff::SNIFF_LEN as u64).read_to_end(&mut prefix)?;
+    check_content_type(&filepart.headers, &prefix, options)
+}
+
+// Decode a file part's content in place if it appears to be base64, updating its size
+fn decode_filepart_if_base64(filepart: &mut FilePart) -> Result<(), Error> {
+    let decoded = match filepart.file {
+        Some(ref file) => transfer::decode_anonymous_if_base64(&file.0)?
+            .map(|(decoded, size)| {
+                filepart.file = Some(AnonymousFile(Arc::new(decoded)));
+                size
+            }),
+        None => transfer::decode_file_if_base64(&filepart.path)?,
+    };
+    if let Some(size) = decoded {
+        filepart.size = Some(size);
+    }
+    Ok(())
+}
+
+// The lower of two optional limits
+fn min_limit(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(::std::cmp::min(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// The most bytes of the next part body which may be kept in memory under the `SpillPolicy`,
+// given the bytes of part bodies kept in memory so far
+fn memory_limit(options: &ParseOptions, memory_used: usize) -> Option<usize> {
+    options.spill.as_ref().map(|spill| {
+        let remaining = spill.memory_budget.saturating_sub(memory_used);
+        match spill.max_part_in_memory {
+            Some(max) => ::std::cmp::min(remaining, max),
+            None => remaining,
+        }
+    })
+}
+
+// Read past the preamble and the first boundary (which includes its leading `--`), up to
+// the line terminator after it.  Returns the line terminator which the body uses, and the
+// boundary as it appears after the first, preceded by that line terminator.
+pub(crate) fn read_first_boundary<R: BufRead>(reader: &mut R, boundary: &[u8])
+    -> Result<(Vec<u8>, Vec<u8>), Error>
+{
+    // Read past the initial boundary.  The preamble is discarded, and boundary-like bytes
+    // within it which are not at the start of a line are skipped over.
+    let mut first = true;
+    loop {
+        let mut preamble = LastByteWriter(None);
+        let (read, found) = reader.stream_until_token(boundary, &mut preamble)?;
+        if ! found { return Err(Error::EofBeforeFirstBoundary); }
+        let at_line_start = if read == 0 { first } else { preamble.0 == Some(b'\n') };
+        if at_line_start {
+            break;
+        }
+        first = false;
+    }
+
+    // Define the boundary, including the line terminator preceding it.
+    // Use their first line terminator to determine whether to use CRLF or LF.
+    skip_transport_padding(reader)?;
+    let peeker = reader.fill_buf()?;
+    if peeker.len() > 1 && &peeker[..2]==b"\r\n" {
+        let mut output = Vec::with_capacity(2 + boundary.len());
+        output.push(b'\r');
+        output.push(b'\n');
+        output.extend_from_slice(boundary);
+        Ok((vec![b'\r', b'\n'], output))
+    }
+    else if !peeker.is_empty() && peeker[0]==b'\n' {
+        let mut output = Vec::with_capacity(1 + boundary.len());
+        output.push(b'\n');
+        output.extend_from_slice(boundary);
+        Ok((vec![b'\n'], output))
+    }
+    else if peeker.is_empty() {
+        Err(Error::EofAfterBoundary)
+    }
+    else {
+        Err(Error::NoCrLfAfterBoundary)
+    }
+}
+
+// Consume any transport padding (spaces and tabs, which RFC 2046 allows after a boundary)
+// so that the reader is positioned at the line terminator, or the `--` which closes the
+// body.  Padding before the `--` is not allowed, but is tolerated.
+pub(crate) fn skip_transport_padding<R: BufRead>(reader: &mut R) -> io::Result<()> {
+    loop {
+        let (padding, more) = {
+            let peeker = reader.fill_buf()?;
+            let padding = peeker.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+            (padding, padding > 0 && padding == peeker.len())
+        };
+        reader.consume(padding);
+        if ! more {
+            return Ok(());
+        }
+    }
+}
+
+// Read the header lines of a part into `buf`, ready for httparse, up to and including the
+// blank line which ends them (which may be the very first line).  Returns the number of
+// bytes read from the stream.
+pub(crate) fn read_header_lines<R: BufRead>(
+    reader: &mut R,
+    lt: &[u8],
+    buf: &mut Vec<u8>,
+    options: &ParseOptions)
+    -> Result<usize, Error>
+{
+    let mut line: Vec<u8> = Vec::new();
+    let mut count: usize = 0;
+    loop {
+        line.truncate(0);
+        let mut out = LimitWriter::new(&mut line, options.max_header_line_len);
+        let result = reader.stream_until_token(lt, &mut out);
+        let (read, found) = match out.check(result) {
+            Err(Error::SizeLimitExceeded) => return Err(Error::HeaderLineTooLong),
+            result => result?,
+        };
+        if ! found { return Err(Error::EofInPartHeaders); }
+        count += read + lt.len();
+
+        match options.header_line_endings {
+            HeaderLineEndings::Normalize => {
+                // Terminate every line with CRLF, as httparse expects, whether it ended in
+                // the part's line terminator or in CRLF
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                buf.extend_from_slice(&line);
+                buf.extend_from_slice(b"\r\n");
+            },
+            HeaderLineEndings::AsReceived => {
+                buf.extend_from_slice(&line);
+                buf.extend_from_slice(lt);
+            },
+        }
+
+        if line.is_empty() {
+            return Ok(count);
+        }
+    }
+}
+
+// Parse a part's block of headers, also returning them as received if `preserve` is true
+pub(crate) fn parse_part_headers(buf: &[u8], options: &ParseOptions, preserve: bool)
+    -> Result<(Headers, Option<RawHeaders>), Error>
+{
+    let mut header_memory = vec![httparse::EMPTY_HEADER; header_capacity(buf)];
+    let raw = match httparse::parse_headers(buf, &mut header_memory)? {
+        httparse::Status::Complete((_, raw)) => raw,
+        httparse::Status::Partial => return Err(Error::PartialHeaders),
+    };
+    let raw_headers: Option<RawHeaders> = if preserve {
+        Some(raw.iter().map(|h| (h.name.to_owned(), h.value.to_vec())).collect())
+    } else {
+        None
+    };
+    let mut headers = headers_from_raw(raw, options)?;
+    check_content_type_syntax(&mut headers, options)?;
+    if let Some(ref policy) = options.header_policy {
+        if let Some(header) = headers.iter().find(|header| ! policy.allows(header.name())) {
+            return Err(Error::DisallowedHeader(header.name().to_owned()));
+        }
+    }
+    Ok((headers, raw_headers))
+}
+
+// Apply the `InvalidContentType` policy to a `Content-Type` which hyper cannot parse
+fn check_content_type_syntax(headers: &mut Headers, options: &ParseOptions) -> Result<(), Error> {
+    let raw = match headers.get_raw("Content-Type").and_then(|raw| raw.first()) {
+        Some(raw) if headers.get::<ContentType>().is_none() => raw.clone(),
+        _ => return Ok(()),
+    };
+    match options.invalid_content_type {
+        InvalidContentType::Keep => {},
+        InvalidContentType::OctetStream => {
+            let octet_stream = SubLevel::Ext("octet-stream".to_owned());
+            headers.set(ContentType(Mime(TopLevel::Application, octet_stream, vec![])));
+        },
+        InvalidContentType::Reject => {
+            return Err(Error::InvalidContentType(String::from_utf8_lossy(&raw).into_owned()));
+        },
+    }
+    Ok(())
+}
+
+// Record an error confined to one part if errors are being collected, otherwise fail with it
+fn recover(errors: &mut Option<&mut Vec<Error>>, e: Error) -> Result<(), Error> {
+    match *errors {
+        Some(ref mut errors) => {
+            errors.push(e);
+            Ok(())
+        },
+        None => Err(e),
+    }
+}
+
+// Discard the rest of a part, up to and including the next boundary
+fn skip_part<R: BufRead>(reader: &mut R, lt_boundary: &[u8]) -> Result<(), Error> {
+    let (_, found) = reader.stream_until_token(lt_boundary, &mut ::std::io::sink())?;
+    if ! found { return Err(Error::EofInPart); }
+    Ok(())
+}
+
+// Discards what is written to it, remembering only the last byte
+struct LastByteWriter(Option<u8>);
+
+impl Write for LastByteWriter {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        if let Some(&last) = buf.last() {
+            self.0 = Some(last);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// A `BufRead` which gives some pushed back bytes before those of the inner reader
+struct Pushback<R> {
+    pending: Vec<u8>,
+    pos: usize,
+    inner: R,
+}
+
+impl<R> Pushback<R> {
+    fn new(inner: R) -> Pushback<R> {
+        Pushback {
+            pending: Vec::new(),
+            pos: 0,
+            inner,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Pushback<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Pushback<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos < self.pending.len() {
+            Ok(&self.pending[self.pos..])
+        } else {
+            self.inner.fill_buf()
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.pos < self.pending.len() {
+            self.pos += amt;
+        } else {
+            self.inner.consume(amt);
+        }
+    }
+}
+
+// Create a temporary `FilePart` for a part being parsed
+fn create_filepart(headers: Headers, options: &ParseOptions) -> Result<FilePart, Error>
+{
+    let filepart = if options.anonymous_files {
+        FilePart::create_anonymous(headers)?
+    } else {
+        FilePart::create(headers)?
+    };
+    Ok(parsed_filepart(filepart, options))
+}
+
+// Give a file part which was parsed the filename policy of the options it was parsed with
+fn parsed_filepart(mut filepart: FilePart, options: &ParseOptions) -> FilePart {
+    if options.default_filename_charset != Charset::Ext("UTF-8".to_owned()) {
+        filepart.filename_policy.charset = Some(options.default_filename_charset.clone());
+    }
+    filepart.filename_policy.max_chars = options.max_filename_chars;
+    #[cfg(feature = "unicode-normalization")]
+    {
+        filepart.filename_policy.normalize = options.normalize_filenames;
+    }
+    filepart
+}
 
-            // Stream out the file.
-            let (read, found) = reader.stream_until_token(&lt_boundary, &mut file)?;
-            if ! found { return Err(Error::EofInFile); }
-            filepart.size = Some(read);
+// The header block is fully buffered before it is parsed, and every header ends in a line
+// terminator, so counting the line feeds gives an upper bound on the number of headers.
+fn header_capacity(buf: &[u8]) -> usize {
+    buf.iter().filter(|&&b| b == b'\n').count()
+}
 
-            // TODO: Handle Content-Transfer-Encoding.  RFC 7578 section 4.7 deprecated
-            // this, and the authors state "Currently, no deployed implementations that
-            // send such bodies have been discovered", so this is very low priority.
+// Build `Headers` from the raw httparse output.  Header names are kept as received
+// unless the options ask for them to be canonicalized.
+fn headers_from_raw(raw: &[httparse::Header], options: &ParseOptions) -> Result<Headers, Error> {
+    if ! options.canonicalize_header_names {
+        return Ok(Headers::from_raw(raw)?);
+    }
 
-            nodes.push(Node::File(filepart));
-        } else {
-            buf.truncate(0); // start fresh
-            let (_, found) = reader.stream_until_token(&lt_boundary, &mut buf)?;
-            if ! found { return Err(Error::EofInPart); }
+    let names: Vec<String> = raw.iter().map(|h| canonical_header_name(h.name)).collect();
+    let canonical: Vec<httparse::Header> = raw.iter().zip(names.iter())
+        .map(|(h, name)| httparse::Header { name, value: h.value })
+        .collect();
+    Ok(Headers::from_raw(&canonical)?)
+}
 
-            nodes.push(Node::Part(Part {
-                headers: part_headers,
-                body: buf.clone(),
-            }));
-        }
+// Canonical header case: the first letter and every letter following a '-' are uppercase,
+// the rest are lowercase (e.g. `content-type` becomes `Content-Type`).
+fn canonical_header_name(name: &str) -> String {
+    let mut upper = true;
+    name.chars().map(|ch| {
+        let ch = if upper { ch.to_ascii_uppercase() } else { ch.to_ascii_lowercase() };
+        upper = ch == '-';
+        ch
+    }).collect()
+}
+
+// Fail with `Error::NotMultipart` if the headers give a `Content-Type` that is not
+// `multipart/*`.  A missing `Content-Type` passes, as the boundary may yet be detected.
+fn check_multipart(headers: &Headers) -> Result<(), Error> {
+    match headers.get() {
+        Some(&ContentType(Mime(ref top_level, _, _))) if *top_level != TopLevel::Multipart =>
+            Err(Error::NotMultipart),
+        _ => Ok(()),
     }
 }
 
@@ -323,31 +2177,152 @@ pub fn get_multipart_boundary(headers: &Headers) -> Result<Vec<u8>, Error> {
         Some(ct) => ct,
         None => return Err(Error::NoRequestContentType),
     };
-    let ContentType(ref mime) = *ct;
-    let Mime(ref top_level, _, ref params) = *mime;
-
-    if *top_level != TopLevel::Multipart {
-        return Err(Error::NotMultipart);
+    check_multipart(headers)?;
+    let ContentType(Mime(_, _, ref params)) = *ct;
+
+    let mut value: Option<Vec<u8>> = params.iter().find_map(|(attr, val)| match (attr, val) {
+        (&Attr::Boundary, Value::Ext(val)) => Some(val.as_bytes().to_vec()),
+        _ => None,
+    });
+
+    // mime drops parameters it cannot parse (such as a boundary value with leading
+    // whitespace), so fall back to the raw header.
+    if value.is_none() {
+        if let Some(raw) = headers.get_raw("Content-Type").and_then(|raw| raw.first()) {
+            value = params::find_param(raw, "boundary");
+        }
     }
 
-    for &(ref attr, ref val) in params.iter() {
-        if let (&Attr::Boundary, &Value::Ext(ref val)) = (attr, val) {
+    // Some clients pad the value with whitespace, which is not part of the boundary.
+    match value.as_ref().map(|v| params::trim(v)) {
+        Some(val) if ! val.is_empty() => {
             let mut boundary = Vec::with_capacity(2 + val.len());
             boundary.extend(b"--".iter().cloned());
-            boundary.extend(val.as_bytes());
-            return Ok(boundary);
+            boundary.extend(val);
+            Ok(boundary)
+        },
+        _ => Err(Error::BoundaryNotSpecified),
+    }
+}
+
+fn set_filename(headers: &mut Headers, filename: &str) {
+    let (disposition, mut params) = match headers.get_raw("Content-Disposition")
+        .and_then(|raw| raw.first())
+    {
+        Some(raw) => params::split_header_params(raw),
+        None => (b"attachment".to_vec(), Vec::new()),
+    };
+    params.retain(|(name, _)| {
+        ! name.eq_ignore_ascii_case("filename") && ! name.eq_ignore_ascii_case("filename*")
+    });
+    if filename.is_ascii() {
+        params.push(("filename".to_owned(), filename.as_bytes().to_vec()));
+    } else {
+        let fallback: String = filename.chars()
+            .map(|c| if c.is_ascii() { c } else { '_' })
+            .collect();
+        params.push(("filename".to_owned(), fallback.into_bytes()));
+        params.push(("filename*".to_owned(), params::extended_value(filename)));
+    }
+    headers.set_raw("Content-Disposition", vec![params::format_header_params(&disposition, &params)]);
+}
+
+// The `Content-Type` header, with all of its parameters.  mime stops parsing parameters at
+// the first one after a quoted value, so they are taken from the raw header instead.
+fn content_type(headers: &Headers) -> Option<Mime> {
+    let &ContentType(Mime(ref top, ref sub, _)) = headers.get::<ContentType>()?;
+    let raw = headers.get_raw("Content-Type")?.first()?;
+    let (_, params) = params::parse_header_params(raw);
+    let params = params.into_iter()
+        .filter_map(|(name, value)| {
+            let value = String::from_utf8(value).ok()?;
+            Some((name.parse().ok()?, value.parse().ok()?))
+        })
+        .collect();
+    Some(Mime(top.clone(), sub.clone(), params))
+}
+
+// Wrap a reader of a part's body in a decoder of the part's `Content-Transfer-Encoding`
+fn decoding_reader<'a, R: Read + 'a>(headers: &Headers, body: R)
+    -> Result<Box<dyn Read + 'a>, Error>
+{
+    match Decoding::for_headers(headers) {
+        Ok(None) => Ok(Box::new(body)),
+        Ok(Some(decoding)) => Ok(Box::new(DecodingReader::new(body, decoding))),
+        Err(encoding) => Err(Error::Decoding(
+            format!("unknown Content-Transfer-Encoding {:?}", encoding).into())),
+    }
+}
+
+// The `charset` parameter of a `Content-Type` header.  This is taken from the raw header,
+// as mime drops parameters it cannot parse.
+fn content_charset(headers: &Headers) -> Option<Charset> {
+    let value = params::find_header_param(headers, "Content-Type", "charset")?;
+    String::from_utf8_lossy(params::trim(&value)).parse().ok()
+}
+
+// The language tags of a `Content-Language` header, which may be split over several
+// header lines
+fn content_language(headers: &Headers) -> Option<Vec<String>> {
+    let raw = headers.get_raw("Content-Language")?;
+    let mut tags: Vec<String> = Vec::new();
+    for line in raw {
+        let mut value = String::new();
+        let mut depth: usize = 0;
+        for c in String::from_utf8_lossy(line).chars() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                c if depth == 0 => value.push(c),
+                _ => {},
+            }
+        }
+        tags.extend(value.split(',')
+                    .map(|tag| tag.trim())
+                    .filter(|tag| ! tag.is_empty())
+                    .map(|tag| tag.to_owned()));
+    }
+    Some(tags)
+}
+
+// A plain `filename` parameter carries no charset, and hyper presumes UTF-8 (failing to
+// parse the header at all if it is not).  So unless an RFC 5987 `filename*` parameter
+// declares the charset, decode the raw bytes of `filename` with the default charset.
+// hyper also takes whichever of the two comes first, whereas `filename*` should win.
+fn get_filename(headers: &Headers, default_charset: &Charset) -> Result<Option<String>, Error> {
+    if let Some(raw) = headers.get_raw("Content-Disposition").and_then(|raw| raw.first()) {
+        let (_, params) = params::parse_header_params(raw);
+        // An RFC 5987 `filename*` takes precedence over `filename`, whichever comes first
+        let extended = params.iter()
+            .find(|(name, _)| name == "filename*")
+            .and_then(|(_, value)| decode_extended_value(value));
+        if let Some(filename) = extended {
+            return filename.map(Some);
         }
+        if let Some((_, bytes)) = params.iter().find(|(name, _)| name == "filename") {
+            return match charset_decode(default_charset, bytes) {
+                Ok(filename) => Ok(Some(filename)),
+                Err(e) => Err(Error::Decoding(e)),
+            };
+        }
+    }
+    match headers.get::<ContentDisposition>() {
+        Some(cd) => get_content_disposition_filename(cd),
+        None => Ok(None),
     }
-    Err(Error::BoundaryNotSpecified)
+}
+
+// Decode an RFC 5987 extended parameter value, such as that of `filename*`, or `None` if it
+// is not well formed
+pub(crate) fn decode_extended_value(value: &[u8]) -> Option<Result<String, Error>> {
+    let extended = parse_extended_value(::std::str::from_utf8(value).ok()?).ok()?;
+    Some(charset_decode(&extended.charset, &extended.value).map_err(Error::Decoding))
 }
 
 #[inline]
 fn get_content_disposition_filename(cd: &ContentDisposition) -> Result<Option<String>, Error> {
-    if let Some(&DispositionParam::Filename(ref charset, _, ref bytes)) =
-        cd.parameters.iter().find(|&x| match *x {
-            DispositionParam::Filename(_,_,_) => true,
-            _ => false,
-        })
+    if let Some(DispositionParam::Filename(charset, _, bytes)) =
+        cd.parameters.iter().find(|&x| matches!(*x, DispositionParam::Filename(_,_,_)))
     {
         match charset_decode(charset, bytes) {
             Ok(filename) => Ok(Some(filename)),
@@ -358,6 +2333,22 @@ fn get_content_disposition_filename(cd: &ContentDisposition) -> Result<Option<St
     }
 }
 
+// Decode a text body in `charset` (UTF-8 if none), or as its byte order mark shows, which is
+// removed
+fn decode_text(bytes: &[u8], charset: Option<&Charset>) -> Result<String, Error> {
+    let decoded = if let Some(text) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+        all::UTF_8.decode(text, DecoderTrap::Strict)
+    } else if let Some(text) = bytes.strip_prefix(b"\xff\xfe") {
+        all::UTF_16LE.decode(text, DecoderTrap::Strict)
+    } else if let Some(text) = bytes.strip_prefix(b"\xfe\xff") {
+        all::UTF_16BE.decode(text, DecoderTrap::Strict)
+    } else {
+        let utf8 = Charset::Ext("UTF-8".to_owned());
+        charset_decode(charset.unwrap_or(&utf8), bytes)
+    };
+    decoded.map_err(Error::Decoding)
+}
+
 // This decodes bytes encoded according to a hyper::header::Charset encoding, using the
 // rust-encoding crate.  Only supports encodings defined in both crates.
 fn charset_decode(charset: &Charset, bytes: &[u8]) -> Result<String, Cow<'static, str>> {
@@ -394,12 +2385,41 @@ fn charset_decode(charset: &Charset, bytes: &[u8]) -> Result<String, Cow<'static
 }
 
 /// Generate a valid multipart boundary, statistically unlikely to be found within
-/// the content of the parts.
-pub fn generate_boundary() -> Vec<u8> {
-    TextNonce::sized(68).unwrap().into_string().into_bytes().iter().map(|&ch| {
-        if ch==b'=' { return b'-'; }
-        else if ch==b'/' { return b'.'; }
-        else { return ch; }
+/// the content of the parts.  Fails with `Error::NonceGeneration` if no random nonce could
+/// be generated.
+pub fn generate_boundary() -> Result<Vec<u8>, Error> {
+    let nonce = TextNonce::sized(68).map_err(Error::NonceGeneration)?;
+    Ok(boundary_chars(nonce.into_string()))
+}
+
+/// The longest boundary allowed by RFC 2046
+pub const MAX_BOUNDARY_LEN: usize = 70;
+
+/// The shortest boundary `generate_boundary_with_len()` will generate, as any shorter would
+/// risk being found within the content of the parts.
+pub const MIN_GENERATED_BOUNDARY_LEN: usize = 8;
+
+/// Generate a valid multipart boundary of `len` characters (or of `MAX_BOUNDARY_LEN` if
+/// `len` is longer), statistically unlikely to be found within the content of the parts.
+/// Fails with `Error::BoundaryTooShort` if `len` is less than `MIN_GENERATED_BOUNDARY_LEN`,
+/// or with `Error::NonceGeneration` if no random nonce could be generated.
+pub fn generate_boundary_with_len(len: usize) -> Result<Vec<u8>, Error> {
+    if len < MIN_GENERATED_BOUNDARY_LEN {
+        return Err(Error::BoundaryTooShort);
+    }
+    let len = ::std::cmp::min(len, MAX_BOUNDARY_LEN);
+
+    // A TextNonce starts with the time, so take the random characters from its end
+    let nonce = TextNonce::sized(72).map_err(Error::NonceGeneration)?.into_string();
+    Ok(boundary_chars(nonce[nonce.len() - len..].to_owned()))
+}
+
+// Replace the base64 characters of a nonce which are not valid in a boundary
+fn boundary_chars(nonce: String) -> Vec<u8> {
+    nonce.into_bytes().iter().map(|&ch| {
+        if ch==b'=' { b'-' }
+        else if ch==b'/' { b'.' }
+        else { ch }
     }).collect()
 }
 
@@ -407,7 +2427,7 @@ pub fn generate_boundary() -> Vec<u8> {
 trait WriteAllCount {
     fn write_all_count(&mut self, buf: &[u8]) -> ::std::io::Result<usize>;
 }
-impl<T: Write> WriteAllCount for T {
+impl<T: Write + ?Sized> WriteAllCount for T {
     fn write_all_count(&mut self, buf: &[u8]) -> ::std::io::Result<usize>
     {
         self.write_all(buf)?;
@@ -415,85 +2435,517 @@ impl<T: Write> WriteAllCount for T {
     }
 }
 
+// hyper folds any CR or LF within a header value into a space when writing it, and does
+// not check header names at all.  A line break in a header (for instance in a filename
+// taken from user input) is almost certainly an attempt to inject further headers, so
+// refuse to write it rather than silently altering it.
+fn check_header(headers: &Headers, header: &HeaderView) -> Result<(), Error> {
+    let is_line_break = |b: u8| b == b'\r' || b == b'\n';
+    let name = header.name();
+    let bad_name = name.is_empty() || name.bytes().any(|b| is_line_break(b) || b == b':');
+    let typed_value = if header.is::<ContentDisposition>() {
+        header.value::<ContentDisposition>().map(|cd| cd.to_string())
+    } else if header.is::<ContentType>() {
+        header.value::<ContentType>().map(|ct| ct.to_string())
+    } else {
+        None
+    };
+    let bad_value = if let Some(value) = typed_value {
+        value.bytes().any(is_line_break)
+    } else {
+        headers.get_raw(name)
+            .is_some_and(|lines| lines.iter().any(|line| line.iter().any(|&b| is_line_break(b))))
+    };
+    if bad_name || bad_value {
+        return Err(Error::HeaderInjection(name.to_owned()));
+    }
+    Ok(())
+}
+
+// The value of a header, as written.  hyper formats `Content-Type` parameters without any
+// quoting, which is invalid for values containing spaces or special characters (such as some
+// boundaries), so the `Content-Type` parameters are quoted here as required.
+fn header_value(headers: &Headers, header: &HeaderView) -> Vec<u8> {
+    if header.is::<ContentType>() {
+        if let Some(raw) = headers.get_raw(header.name()).and_then(|raw| raw.first()) {
+            let (value, params) = params::split_header_params(raw);
+            return params::format_header_params(&value, &params);
+        }
+    }
+    header.value_string().into_bytes()
+}
+
+// Write headers, each followed by a line terminator.  Returns the number of bytes written.
+fn write_headers<S: Write + ?Sized>(stream: &mut S, headers: &Headers, lt: &[u8])
+    -> Result<usize, Error>
+{
+    let mut count: usize = 0;
+    for header in headers.iter() {
+        check_header(headers, &header)?;
+        count += stream.write_all_count(header.name().as_bytes())?;
+        count += stream.write_all_count(b": ")?;
+        count += stream.write_all_count(&header_value(headers, &header))?;
+        count += stream.write_all_count(lt)?;
+    }
+    Ok(count)
+}
+
 /// Stream a multipart body to the output `stream` given, made up of the `parts`
 /// given.  Top-level headers are NOT included in this stream; the caller must send
 /// those prior to calling write_multipart().
+/// Any `Write` may be used, including a `&mut dyn Write` or a `TeeWriter` to send the
+/// output to two destinations at once.
+/// Returns the number of bytes written, or an error.
+pub fn write_multipart<S: Write + ?Sized>(
+    stream: &mut S,
+    boundary: &[u8],
+    nodes: &[Node])
+    -> Result<usize, Error>
+{
+    write_multipart_with_options(stream, boundary, nodes, &WriteOptions::default())
+}
+
+/// Stream a multipart body to the output `stream` given, as `write_multipart()` does, but
+/// under the given `WriteOptions`.
 /// Returns the number of bytes written, or an error.
-pub fn write_multipart<S: Write>(
+pub fn write_multipart_with_options<S: Write + ?Sized>(
     stream: &mut S,
-    boundary: &Vec<u8>,
-    nodes: &Vec<Node>)
+    boundary: &[u8],
+    nodes: &[Node],
+    options: &WriteOptions)
     -> Result<usize, Error>
 {
+    if options.consume_once {
+        check_sources_once(nodes, &mut Vec::new())?;
+    }
+
     let mut count: usize = 0;
 
     for node in nodes {
-        // write a boundary
-        count += stream.write_all_count(b"--")?;
-        count += stream.write_all_count(&boundary)?;
-        count += stream.write_all_count(b"\r\n")?;
+        count += write_node(stream, boundary, node, options)?;
+    }
 
-        match node {
-            &Node::Part(ref part) => {
-                // write the part's headers
-                for header in part.headers.iter() {
-                    count += stream.write_all_count(header.name().as_bytes())?;
-                    count += stream.write_all_count(b": ")?;
-                    count += stream.write_all_count(header.value_string().as_bytes())?;
-                    count += stream.write_all_count(b"\r\n")?;
-                }
+    count += write_final_boundary(stream, boundary)?;
 
-                // write the blank line
-                count += stream.write_all_count(b"\r\n")?;
+    Ok(count)
+}
 
-                // Write the part's content
-                count += stream.write_all_count(&part.body)?;
-            },
-            &Node::File(ref filepart) => {
-                // write the part's headers
-                for header in filepart.headers.iter() {
-                    count += stream.write_all_count(header.name().as_bytes())?;
-                    count += stream.write_all_count(b": ")?;
-                    count += stream.write_all_count(header.value_string().as_bytes())?;
-                    count += stream.write_all_count(b"\r\n")?;
-                }
+/// Re-emit a parsed multipart body, such as one returned by `read_multipart_with_headers()`,
+/// to the output `stream` given: its top-level `headers`, a blank line, and then the body
+/// made up of `nodes`, with the boundary taken from `headers`.  Nested `multipart/*` parts
+/// keep their own headers and boundaries, so the structure of the original is preserved.
+/// This is how a multipart message is proxied onward unchanged.
+///
+/// Lines are terminated with CRLF; a body received with bare LF endings can be re-emitted
+/// as such with `rewrite_multipart_with_options()` and `LineEnding::Lf`.
+/// Returns the number of bytes written, or an error.
+pub fn rewrite_multipart<S: Write + ?Sized>(
+    stream: &mut S,
+    nodes: &[Node],
+    headers: &Headers)
+    -> Result<usize, Error>
+{
+    rewrite_multipart_with_options(stream, nodes, headers, &WriteOptions::default())
+}
 
-                // write the blank line
-                count += stream.write_all_count(b"\r\n")?;
+/// Re-emit a parsed multipart body, as `rewrite_multipart()` does, but under the given
+/// `WriteOptions`.
+/// Returns the number of bytes written, or an error.
+pub fn rewrite_multipart_with_options<S: Write + ?Sized>(
+    stream: &mut S,
+    nodes: &[Node],
+    headers: &Headers,
+    options: &WriteOptions)
+    -> Result<usize, Error>
+{
+    let boundary = get_multipart_boundary(headers)?;
+    let lt = options.line_ending.as_bytes();
 
-                // Write out the files's content
-                let mut file = File::open(&filepart.path)?;
-                count += std::io::copy(&mut file, stream)? as usize;
-            },
-            &Node::Multipart((ref headers, ref subnodes)) => {
-                // Get boundary
-                let boundary = get_multipart_boundary(headers)?;
+    let mut count = write_headers(stream, headers, lt)?;
+    count += stream.write_all_count(lt)?;
+    count += write_multipart_with_options(stream, &boundary[2..], nodes, options)?;
+    Ok(count)
+}
 
-                // write the multipart headers
-                for header in headers.iter() {
-                    count += stream.write_all_count(header.name().as_bytes())?;
-                    count += stream.write_all_count(b": ")?;
-                    count += stream.write_all_count(header.value_string().as_bytes())?;
-                    count += stream.write_all_count(b"\r\n")?;
-                }
+/// Stream a multipart body to the output `stream` given, as `write_multipart()` does, while
+/// computing the SHA-256 digest of everything written (e.g. for a `Digest` header or an
+/// integrity record), without a second pass over the output.
+/// Returns the number of bytes written and the digest, or an error.
+///
+/// Only available with the `sha2` feature.
+#[cfg(feature = "sha2")]
+pub fn write_multipart_hashed<S: Write + ?Sized>(
+    stream: &mut S,
+    boundary: &[u8],
+    nodes: &[Node])
+    -> Result<(usize, [u8; 32]), Error>
+{
+    use sha2::{Digest, Sha256};
+    let mut tee = TeeWriter::new(stream, Sha256::new());
+    let count = write_multipart(&mut tee, boundary, nodes)?;
+    let (_, hasher) = tee.into_inner();
+    Ok((count, hasher.finalize().into()))
+}
 
-                // write the blank line
-                count += stream.write_all_count(b"\r\n")?;
+/// Stream a `multipart/form-data` body to the output `stream` given, as `write_multipart()`
+/// does, but first checking that each part has a `Content-Disposition` with a `name`, as
+/// form-data requires (see `DispositionPolicy::FormData`).
+/// Returns the number of bytes written, or an error.
+pub fn write_form_data<S: Write + ?Sized>(
+    stream: &mut S,
+    boundary: &[u8],
+    nodes: &[Node])
+    -> Result<usize, Error>
+{
+    let options = WriteOptions {
+        disposition_policy: DispositionPolicy::FormData,
+        ..WriteOptions::default()
+    };
+    write_multipart_with_options(stream, boundary, nodes, &options)
+}
 
-                // Recurse
-                count += write_multipart(stream, &boundary, &subnodes)?;
-            },
+/// Stream a multipart body to the output `stream` given, as `write_multipart()` does, but
+/// taking the nodes from an iterator so that each can be produced just before it is
+/// written, rather than all held in memory at once.
+/// Writing stops at the first error, whether produced by the iterator or in writing.
+/// Returns the number of bytes written, or an error.
+pub fn write_multipart_streaming<S, I>(
+    stream: &mut S,
+    boundary: &[u8],
+    nodes: I)
+    -> Result<usize, Error>
+    where S: Write + ?Sized, I: IntoIterator<Item = Result<Node, Error>>
+{
+    let mut count: usize = 0;
+
+    for node in nodes {
+        count += write_node(stream, boundary, &node?, &WriteOptions::default())?;
+    }
+
+    count += write_final_boundary(stream, boundary)?;
+
+    Ok(count)
+}
+
+/// Write a single `node` to the output `stream` given, as one part of a multipart body
+/// with the given `boundary`: the boundary, the part's headers, the blank line, the body,
+/// and the line terminator which ends the body.  The final boundary is NOT written; this
+/// is for callers which frame the body themselves, and must write it with
+/// `write_final_boundary()` once the last part is written.
+/// Returns the number of bytes written, or an error.
+pub fn write_single_part<S: Write + ?Sized>(
+    stream: &mut S,
+    boundary: &[u8],
+    node: &Node)
+    -> Result<usize, Error>
+{
+    write_node(stream, boundary, node, &WriteOptions::default())
+}
+
+/// Writes a multipart body to a stream a part at a time, as `write_multipart()` would write
+/// it, so that each part can be produced just before it is written.  As well as `Node`s, it
+/// can write parts whose body is generated directly into the stream by a closure (e.g.
+/// serializing JSON on the fly), without buffering it or needing a `Read`.
+///
+/// Top-level headers are NOT included in this stream; the caller must send those first.
+/// `finish()` must be called to write the final boundary.
+pub struct MultipartWriter<'a, S: Write + ?Sized> {
+    stream: &'a mut S,
+    boundary: Vec<u8>,
+    options: WriteOptions,
+    count: usize,
+    // The files written so far, under `consume_once`
+    sources: Vec<FileSource>,
+}
+
+impl<'a, S: Write + ?Sized> MultipartWriter<'a, S> {
+    /// A writer of a multipart body with the given `boundary` to `stream`, under the
+    /// default `WriteOptions`
+    pub fn new(stream: &'a mut S, boundary: &[u8]) -> MultipartWriter<'a, S> {
+        MultipartWriter::with_options(stream, boundary, WriteOptions::default())
+    }
+
+    /// A writer of a multipart body with the given `boundary` to `stream`, under the given
+    /// `WriteOptions`
+    pub fn with_options(stream: &'a mut S, boundary: &[u8], options: WriteOptions)
+        -> MultipartWriter<'a, S>
+    {
+        MultipartWriter {
+            stream,
+            boundary: boundary.to_vec(),
+            options,
+            count: 0,
+            sources: Vec::new(),
         }
+    }
 
-        // write a line terminator
-        count += stream.write_all_count(b"\r\n")?;
+    /// Write a node
+    pub fn write_node(&mut self, node: &Node) -> Result<(), Error> {
+        if self.options.consume_once {
+            check_sources_once(::std::slice::from_ref(node), &mut self.sources)?;
+        }
+        self.count += write_node(self.stream, &self.boundary, node, &self.options)?;
+        Ok(())
     }
 
-    // write a final boundary
+    /// Write a part with the given `headers`, whose body is written by the `body` closure
+    /// directly into the stream.  The closure returns the number of bytes it wrote.  If the
+    /// part is to be base64 or quoted-printable encoded (see
+    /// `WriteOptions::apply_transfer_encoding`), the closure writes the unencoded body, and
+    /// it is encoded on its way to the stream.
+    pub fn write_part_with<F>(&mut self, headers: &Headers, body: F) -> Result<(), Error>
+        where F: FnOnce(&mut dyn Write) -> io::Result<u64>
+    {
+        check_disposition(headers, &self.options)?;
+        if self.options.dot_stuff {
+            let mut stuffer = DotStuffWriter::new(&mut *self.stream);
+            write_part_with(&mut stuffer, &self.boundary, headers, &self.options, body)?;
+            self.count += stuffer.count;
+        } else {
+            self.count += write_part_with(self.stream, &self.boundary, headers, &self.options, body)?;
+        }
+        Ok(())
+    }
+
+    /// Write the final boundary, ending the body.  Returns the number of bytes written in
+    /// total.
+    pub fn finish(self) -> Result<usize, Error> {
+        Ok(self.count + write_final_boundary(self.stream, &self.boundary)?)
+    }
+}
+
+// Passes writes through to a possibly unsized stream, so that it can be used as a
+// `&mut dyn Write`, counting the bytes written
+struct StreamRef<'a, S: Write + ?Sized> {
+    inner: &'a mut S,
+    count: usize,
+}
+
+impl<'a, S: Write + ?Sized> Write for StreamRef<'a, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Write a boundary followed by a part whose body is written by `body`, and the line
+// terminator which ends it.  Returns the number of bytes written.
+fn write_part_with<S, F>(
+    stream: &mut S,
+    boundary: &[u8],
+    headers: &Headers,
+    options: &WriteOptions,
+    body: F)
+    -> Result<usize, Error>
+    where S: Write + ?Sized, F: FnOnce(&mut dyn Write) -> io::Result<u64>
+{
+    let lt = options.line_ending.as_bytes();
+    let mut count: usize = 0;
+
+    // write a boundary
     count += stream.write_all_count(b"--")?;
-    count += stream.write_all_count(&boundary)?;
+    count += stream.write_all_count(boundary)?;
+    count += stream.write_all_count(lt)?;
+
+    // write the part's headers, and the blank line
+    count += write_headers(stream, headers, lt)?;
+    count += stream.write_all_count(lt)?;
+
+    // Generate the part's content
+    count += write_body(stream, headers, options, |out| body(out).map(|_| ()))?;
+
+    // write a line terminator
+    count += stream.write_all_count(lt)?;
+
+    Ok(count)
+}
+
+// Write a boundary followed by the node, and the line terminator which ends it.  Returns
+// the number of bytes written.
+fn write_node<S: Write + ?Sized>(
+    stream: &mut S,
+    boundary: &[u8],
+    node: &Node,
+    options: &WriteOptions)
+    -> Result<usize, Error>
+{
+    check_disposition(node.headers(), options)?;
+    if options.dot_stuff {
+        // Each node starts at the start of a line, so can be stuffed on its own.  The
+        // stuffer is passed as a `dyn Write` so that this recursion is not generic.
+        let mut stuffer = DotStuffWriter::new(stream);
+        let options = WriteOptions { dot_stuff: false, ..options.clone() };
+        write_node(&mut stuffer as &mut dyn Write, boundary, node, &options)?;
+        return Ok(stuffer.count);
+    }
+    let lt = options.line_ending.as_bytes();
+    let mut count: usize = 0;
+
+    // write a boundary
     count += stream.write_all_count(b"--")?;
+    count += stream.write_all_count(boundary)?;
+    count += stream.write_all_count(lt)?;
+
+    match node {
+        Node::Part(part) => {
+            // write the part's headers
+            count += write_headers(stream, &part.headers, lt)?;
+
+            // write the blank line
+            count += stream.write_all_count(lt)?;
+
+            // Write the part's content
+            count += write_body(stream, &part.headers, options, |out| out.write_all(&part.body))?;
+        },
+        Node::File(filepart) => {
+            // write the part's headers
+            count += write_headers(stream, &filepart.headers, lt)?;
+
+            // write the blank line
+            count += stream.write_all_count(lt)?;
+
+            // Write out the files's content
+            let mut file = filepart.reader()?;
+            count += write_body(stream, &filepart.headers, options, |out| {
+                io::copy(&mut file, out).map(|_| ())
+            })?;
+        },
+        &Node::Multipart((ref headers, ref subnodes)) => {
+            // Get boundary (less the leading '--', which the writer adds)
+            let boundary = get_multipart_boundary(headers)?;
+
+            // write the multipart headers
+            count += write_headers(stream, headers, lt)?;
+
+            // write the blank line
+            count += stream.write_all_count(lt)?;
+
+            // Recurse, requiring dispositions only if the nested body is itself form-data.
+            // Its files were checked along with ours under `consume_once`.
+            let nested = WriteOptions {
+                disposition_policy: match options.disposition_policy {
+                    DispositionPolicy::Optional => DispositionPolicy::Optional,
+                    DispositionPolicy::FormData if is_form_data(headers) =>
+                        DispositionPolicy::FormData,
+                    DispositionPolicy::FormData => DispositionPolicy::Optional,
+                },
+                consume_once: false,
+                ..options.clone()
+            };
+            count += write_multipart_with_options(stream, &boundary[2..], subnodes, &nested)?;
+        },
+    }
+
+    // write a line terminator
+    count += stream.write_all_count(lt)?;
+
+    Ok(count)
+}
+
+// Where a file part's content is read from, for `WriteOptions::consume_once`
+#[derive(PartialEq)]
+enum FileSource {
+    Path(PathBuf),
+    // The address of the shared file, kept as a number so that writers remain `Send`
+    Anonymous(usize),
+}
+
+// Check that no file in `nodes` is read from a source in `seen`, or from the same source as
+// another, adding each to `seen`.  Content held in memory is not a source.
+fn check_sources_once(nodes: &[Node], seen: &mut Vec<FileSource>) -> Result<(), Error> {
+    for filepart in files(nodes) {
+        let source = match (&filepart.content, &filepart.file) {
+            (Some(_), _) => continue,
+            (None, Some(file)) => FileSource::Anonymous(Arc::as_ptr(&file.0) as usize),
+            (None, None) => FileSource::Path(filepart.path.clone()),
+        };
+        if seen.contains(&source) {
+            return Err(Error::SourceReused);
+        }
+        seen.push(source);
+    }
+    Ok(())
+}
+
+// Write a part's body, as written by `body`, to the stream, encoding or normalizing it as
+// the options call for.  Returns the number of bytes written to the stream.
+fn write_body<S, F>(stream: &mut S, headers: &Headers, options: &WriteOptions, body: F)
+    -> Result<usize, Error>
+    where S: Write + ?Sized, F: FnOnce(&mut dyn Write) -> io::Result<()>
+{
+    let crlf = options.normalize_text_crlf && is_text(headers);
+    if is_encoded_as(headers, options, "base64") {
+        let lt = options.line_ending.as_bytes();
+        let mut writer = Base64Writer::new(stream, options.base64_line_length, lt);
+        if crlf {
+            body(&mut CrlfWriter::new(&mut writer))?;
+        } else {
+            body(&mut writer)?;
+        }
+        Ok(writer.finish()?)
+    } else if is_encoded_as(headers, options, "quoted-printable") {
+        let lt = options.line_ending.as_bytes();
+        let mut writer = QpWriter::new(stream, lt);
+        if crlf {
+            body(&mut CrlfWriter::new(&mut writer))?;
+        } else {
+            body(&mut writer)?;
+        }
+        Ok(writer.finish()?)
+    } else if crlf {
+        let mut writer = CrlfWriter::new(stream);
+        body(&mut writer)?;
+        Ok(writer.count)
+    } else {
+        let mut writer = StreamRef { inner: stream, count: 0 };
+        body(&mut writer)?;
+        Ok(writer.count)
+    }
+}
+
+// Fail with `Error::MissingDisposition` if the policy requires the part to have a
+// `Content-Disposition` with a `name`, and it has none
+fn check_disposition(headers: &Headers, options: &WriteOptions) -> Result<(), Error> {
+    match options.disposition_policy {
+        DispositionPolicy::FormData if form::disposition_name(headers).is_none() =>
+            Err(Error::MissingDisposition),
+        _ => Ok(()),
+    }
+}
+
+// Whether a nested multipart's content type is `multipart/form-data`
+fn is_form_data(headers: &Headers) -> bool {
+    let ct: Option<&ContentType> = headers.get();
+    matches!(ct, Some(&ContentType(Mime(TopLevel::Multipart, SubLevel::FormData, _))))
+}
+
+// Whether a part has a `text/*` content type
+fn is_text(headers: &Headers) -> bool {
+    let ct: Option<&ContentType> = headers.get();
+    matches!(ct, Some(&ContentType(Mime(TopLevel::Text, _, _))))
+}
 
+// Whether a part's body is to be given the named transfer encoding as it is written
+fn is_encoded_as(headers: &Headers, options: &WriteOptions, encoding: &str) -> bool {
+    options.apply_transfer_encoding &&
+        transfer::transfer_encoding(headers).is_some_and(|cte| cte == encoding)
+}
+
+/// Write the final boundary with which a multipart body ends, for callers writing the parts
+/// with `write_single_part()`.
+/// Returns the number of bytes written, or an error.
+pub fn write_final_boundary<S: Write + ?Sized>(stream: &mut S, boundary: &[u8])
+    -> Result<usize, Error>
+{
+    let mut count: usize = 0;
+    count += stream.write_all_count(b"--")?;
+    count += stream.write_all_count(boundary)?;
+    count += stream.write_all_count(b"--")?;
     Ok(count)
 }
 
@@ -507,30 +2959,37 @@ pub fn write_chunk<S: Write>(
     Ok(())
 }
 
+// Write headers as chunks, each followed by a line terminator.
+fn write_headers_chunked<S: Write>(stream: &mut S, headers: &Headers) -> Result<(), Error> {
+    for header in headers.iter() {
+        check_header(headers, &header)?;
+        write_chunk(stream, header.name().as_bytes())?;
+        write_chunk(stream, b": ")?;
+        write_chunk(stream, &header_value(headers, &header))?;
+        write_chunk(stream, b"\r\n")?;
+    }
+    Ok(())
+}
+
 /// Stream a multipart body to the output `stream` given, made up of the `parts`
 /// given, using Tranfer-Encoding: Chunked.  Top-level headers are NOT included in this
 /// stream; the caller must send those prior to calling write_multipart_chunked().
 pub fn write_multipart_chunked<S: Write>(
     stream: &mut S,
-    boundary: &Vec<u8>,
-    nodes: &Vec<Node>)
+    boundary: &[u8],
+    nodes: &[Node])
     -> Result<(), Error>
 {
     for node in nodes {
         // write a boundary
         write_chunk(stream, b"--")?;
-        write_chunk(stream, &boundary)?;
+        write_chunk(stream, boundary)?;
         write_chunk(stream, b"\r\n")?;
 
         match node {
-            &Node::Part(ref part) => {
+            Node::Part(part) => {
                 // write the part's headers
-                for header in part.headers.iter() {
-                    write_chunk(stream, header.name().as_bytes())?;
-                    write_chunk(stream, b": ")?;
-                    write_chunk(stream, header.value_string().as_bytes())?;
-                    write_chunk(stream, b"\r\n")?;
-                }
+                write_headers_chunked(stream, &part.headers)?;
 
                 // write the blank line
                 write_chunk(stream, b"\r\n")?;
@@ -538,44 +2997,33 @@ pub fn write_multipart_chunked<S: Write>(
                 // Write the part's content
                 write_chunk(stream, &part.body)?;
             },
-            &Node::File(ref filepart) => {
+            Node::File(filepart) => {
                 // write the part's headers
-                for header in filepart.headers.iter() {
-                    write_chunk(stream, header.name().as_bytes())?;
-                    write_chunk(stream, b": ")?;
-                    write_chunk(stream, header.value_string().as_bytes())?;
-                    write_chunk(stream, b"\r\n")?;
-                }
+                write_headers_chunked(stream, &filepart.headers)?;
 
                 // write the blank line
                 write_chunk(stream, b"\r\n")?;
 
                 // Write out the files's length
-                let metadata = std::fs::metadata(&filepart.path)?;
-                write!(stream, "{:x}\r\n", metadata.len())?;
+                write!(stream, "{:x}\r\n", filepart.content_len()?)?;
 
                 // Write out the file's content
-                let mut file = File::open(&filepart.path)?;
-                std::io::copy(&mut file, stream)? as usize;
-                stream.write(b"\r\n")?;
+                let mut file = filepart.reader()?;
+                std::io::copy(&mut file, stream)?;
+                stream.write_all(b"\r\n")?;
             },
             &Node::Multipart((ref headers, ref subnodes)) => {
-                // Get boundary
+                // Get boundary (less the leading '--', which the writer adds)
                 let boundary = get_multipart_boundary(headers)?;
 
                 // write the multipart headers
-                for header in headers.iter() {
-                    write_chunk(stream, header.name().as_bytes())?;
-                    write_chunk(stream, b": ")?;
-                    write_chunk(stream, header.value_string().as_bytes())?;
-                    write_chunk(stream, b"\r\n")?;
-                }
+                write_headers_chunked(stream, headers)?;
 
                 // write the blank line
                 write_chunk(stream, b"\r\n")?;
 
                 // Recurse
-                write_multipart_chunked(stream, &boundary, &subnodes)?;
+                write_multipart_chunked(stream, &boundary[2..], subnodes)?;
             },
         }
 
@@ -585,7 +3033,7 @@ pub fn write_multipart_chunked<S: Write>(
 
     // write a final boundary
     write_chunk(stream, b"--")?;
-    write_chunk(stream, &boundary)?;
+    write_chunk(stream, boundary)?;
     write_chunk(stream, b"--")?;
 
     // Write an empty chunk to signal the end of the body
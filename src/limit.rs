@@ -0,0 +1,70 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::io::{self, Write};
+use super::Error;
+
+// Wraps the destination of a part body, failing the write which would take it past the
+// limit.  Every part body is written through one of these as it is received, so that the
+// limit is enforced as the data arrives, before it is all allocated or stored.  Decoding
+// is not: the base64 decoded under `detect_base64` comes after the limit is checked, and
+// is only within it because decoded base64 is always smaller than its encoding.  Any
+// decoding done while parsing which could expand its input must be written through one of
+// these too.
+pub(crate) struct LimitWriter<W> {
+    inner: W,
+    remaining: Option<usize>,
+    exceeded: bool,
+    written: u64,
+}
+
+impl<W: Write> LimitWriter<W> {
+    pub(crate) fn new(inner: W, limit: Option<usize>) -> LimitWriter<W> {
+        LimitWriter {
+            inner,
+            remaining: limit,
+            exceeded: false,
+            written: 0,
+        }
+    }
+
+    // The number of bytes written through this so far.  This is counted as a `u64` so that
+    // the size of a large part is correct even where `usize` is 32 bits.
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+
+    // Convert the result of writing through this into our result, reporting a write
+    // which failed due to the limit as `Error::SizeLimitExceeded`.
+    pub(crate) fn check<T>(&self, result: io::Result<T>) -> Result<T, Error> {
+        match result {
+            Err(_) if self.exceeded => Err(Error::SizeLimitExceeded),
+            result => Ok(result?),
+        }
+    }
+}
+
+impl<W: Write> Write for LimitWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(remaining) = self.remaining {
+            if buf.len() > remaining {
+                self.exceeded = true;
+                return Err(io::Error::other("size limit exceeded"));
+            }
+        }
+        let n = self.inner.write(buf)?;
+        if let Some(ref mut remaining) = self.remaining {
+            *remaining -= n;
+        }
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,601 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use hyper::header::{Charset, Headers};
+use mime::{Mime, SubLevel, TopLevel};
+use super::params;
+
+/// Options controlling how a `multipart/*` body is parsed.
+///
+/// The `Default` options reproduce the behaviour of `read_multipart()` and
+/// `read_multipart_body()` with `always_use_files` set to false.
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    /// If true, all parts will be streamed to files.  If false, only parts with a
+    /// `ContentDisposition` header set to `Attachment` or otherwise containing a `Filename`
+    /// parameter will be streamed to files.
+    pub always_use_files: bool,
+
+    /// If true, header names are rewritten into their canonical case (e.g. `content-type`
+    /// becomes `Content-Type`).  If false, header names are kept exactly as they were
+    /// received.  Typed header lookups are case-insensitive either way.
+    pub canonicalize_header_names: bool,
+
+    /// Decides, from each part's `Content-Disposition`, whether the part is kept in memory
+    /// or streamed to a file.  Ignored if `always_use_files` is true.
+    pub disposition_storage: DispositionStorage,
+
+    /// The charset used by `FilePart::filename()` to decode a `filename` parameter that
+    /// does not declare its own charset (via an RFC 5987 `filename*` parameter).  Most
+    /// clients send no charset.  Defaults to UTF-8.
+    pub default_filename_charset: Charset,
+
+    /// The maximum number of characters in a filename, once decoded (and normalized, if
+    /// `normalize_filenames` is set), beyond which `FilePart::filename()` fails with
+    /// `Error::InvalidFilename`.  This suits filesystems and databases which limit the
+    /// length of names.  Defaults to no limit.
+    pub max_filename_chars: Option<usize>,
+
+    /// If true, `FilePart::filename()` normalizes decoded filenames to Unicode NFC, so that
+    /// combining characters are composed where possible.  Defaults to false.
+    ///
+    /// Only available with the `unicode-normalization` feature.
+    #[cfg(feature = "unicode-normalization")]
+    pub normalize_filenames: bool,
+
+    /// The maximum size of the body of any one part, in bytes.  A part which exceeds it
+    /// aborts the parse with `Error::SizeLimitExceeded` as soon as the limit is crossed,
+    /// before the excess is stored, and any file already written for it is removed.  The
+    /// limit applies to the body as received, before any decoding.  Defaults to no limit.
+    pub max_part_bytes: Option<usize>,
+
+    /// The maximum total size of the bodies of all the parts streamed to files in one
+    /// parse, in bytes, so that many files which are each within `max_part_bytes` cannot
+    /// together fill the disk.  File parts held in memory under `buffer_files_up_to` count
+    /// too, as they are bound for disk.  The part which would take the total beyond it aborts the
+    /// parse with `Error::SizeLimitExceeded` as soon as the limit is crossed, and its file
+    /// is removed, as are those of the parts before it unless the parse is best-effort.
+    /// Defaults to no limit.
+    pub max_total_file_bytes: Option<usize>,
+
+    /// A flag which, when set (e.g. by another thread when the client disconnects or a
+    /// deadline passes), cancels the parse with `Error::Cancelled`.  It is checked between
+    /// parts and on every read of the stream.  Any temporary files are removed.
+    pub cancel: Option<Arc<AtomicBool>>,
+
+    /// How the line terminators of each part's header lines are handled.  Defaults to
+    /// `Normalize`.
+    pub header_line_endings: HeaderLineEndings,
+
+    /// The maximum length of any one line of a part's headers, in bytes, not counting its
+    /// line terminator.  A longer line aborts the parse with `Error::HeaderLineTooLong` as
+    /// soon as the limit is crossed, so that an enormous header line is never buffered.
+    /// Defaults to no limit.
+    pub max_header_line_len: Option<usize>,
+
+    /// If true, the body of a part without a `Content-Transfer-Encoding` header is decoded
+    /// as base64 if it looks like base64: lines of base64 characters, all but the last of
+    /// the same length (a multiple of 4, at most 76), with any `=` padding only at the end.
+    /// A body of just one line must be padded to qualify.  This can still mistake a short
+    /// text value for base64, so it is only for lenient handling of poorly labelled MIME,
+    /// such as by archival tools.  The part's headers are left unchanged.  Defaults to
+    /// false.
+    pub detect_base64: bool,
+
+    /// If given, parts which would be kept in memory are kept there only until a memory
+    /// budget is used up, and any part which would take the memory used beyond it is
+    /// streamed to a file instead, as a `Node::File`.  This keeps small parts fast without
+    /// risking running out of memory on large uploads.  Defaults to `None`, which keeps
+    /// such parts in memory however large they are.
+    pub spill: Option<SpillPolicy>,
+
+    /// If true, and the headers give no boundary (there is no `Content-Type`, or it has no
+    /// `boundary` parameter), the boundary is taken from the first line of the body which
+    /// looks like one (`--` followed by a valid boundary).  This is a best-effort recovery
+    /// for bodies whose headers were lost.  Used by `read_multipart()` and
+    /// `read_multipart_body()` and their variants with options.  Defaults to false.
+    pub detect_boundary: bool,
+
+    /// If true, parts streamed to files use anonymous temporary files, which are unlinked
+    /// as soon as they are created (see `FilePart::create_anonymous()`), so that they
+    /// cannot be left behind even if the process is killed.  Such a `FilePart` has no
+    /// `path`, and its content can only be read through it.  Defaults to false.
+    pub anonymous_files: bool,
+
+    /// If true, the body of a part with a `Content-Length` header is taken to be exactly
+    /// that many bytes, and the boundary must follow it immediately, rather than the body
+    /// being scanned for the boundary.  This suits protocols which frame binary parts that
+    /// may contain the boundary.  A body which is not followed by the boundary aborts the
+    /// parse with `Error::ContentLengthMismatch`.  Nested `multipart/*` parts are always
+    /// scanned.  Defaults to false.
+    pub content_length_framing: bool,
+
+    /// If given, the body of each part with a declared `Content-Type` is inspected, and a
+    /// part whose content is recognisably of another type (e.g. HTML or SVG, which may
+    /// carry scripts, declared as `image/png`) aborts the parse with
+    /// `Error::ContentTypeMismatch`, or is skipped if the parse is best-effort.  Defaults to
+    /// `None`, which trusts declared types.
+    pub content_type_check: Option<ContentTypeCheck>,
+
+    /// If given, the body of a part which would be streamed to a file is instead held in
+    /// memory if it is at most this many bytes, as a `FilePart` whose content can be
+    /// inspected (see `FilePart::content()`) before it is persisted with
+    /// `FilePart::commit_to_disk()`.  Larger bodies are streamed to files as usual.
+    /// Defaults to `None`, which streams all such parts to files.
+    pub buffer_files_up_to: Option<usize>,
+
+    /// Limits on the resources the whole parse may use: the bytes read, the time taken and
+    /// the number of parts.  Defaults to no limits.
+    pub budget: ParseBudget,
+
+    /// What to do with a part whose `Content-Type` header cannot be parsed as a MIME type.
+    /// Defaults to `Keep`.
+    pub invalid_content_type: InvalidContentType,
+
+    /// If given, restricts the header names a part may carry, for locking down an endpoint
+    /// against headers (such as `Content-Transfer-Encoding`, or custom `X-` headers) which
+    /// could be used to smuggle content past checks elsewhere.  A part carrying a disallowed
+    /// header aborts the parse with `Error::DisallowedHeader`, or is skipped if the parse is
+    /// best-effort.  Nested `multipart/*` parts are checked too.  Defaults to `None`, which
+    /// allows any header.
+    pub header_policy: Option<HeaderPolicy>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            always_use_files: false,
+            canonicalize_header_names: false,
+            disposition_storage: DispositionStorage::default(),
+            default_filename_charset: Charset::Ext("UTF-8".to_owned()),
+            max_filename_chars: None,
+            #[cfg(feature = "unicode-normalization")]
+            normalize_filenames: false,
+            max_part_bytes: None,
+            max_total_file_bytes: None,
+            cancel: None,
+            header_line_endings: HeaderLineEndings::Normalize,
+            max_header_line_len: None,
+            detect_base64: false,
+            spill: None,
+            detect_boundary: false,
+            anonymous_files: false,
+            content_length_framing: false,
+            content_type_check: None,
+            buffer_files_up_to: None,
+            budget: ParseBudget::default(),
+            invalid_content_type: InvalidContentType::Keep,
+            header_policy: None,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Options which stream parts to files as specified by `always_use_files`, and are
+    /// otherwise the default.
+    pub fn new(always_use_files: bool) -> ParseOptions {
+        ParseOptions {
+            always_use_files,
+            ..Default::default()
+        }
+    }
+
+    // Builder-style setters, one for each option, so that options can be chained from
+    // `ParseOptions::default()`.  Those taking an `Option` set `Some` of the value given.
+
+    /// Set `always_use_files`
+    pub fn always_use_files(self, always_use_files: bool) -> ParseOptions {
+        ParseOptions { always_use_files, ..self }
+    }
+
+    /// Set `canonicalize_header_names`
+    pub fn canonicalize_header_names(self, canonicalize: bool) -> ParseOptions {
+        ParseOptions { canonicalize_header_names: canonicalize, ..self }
+    }
+
+    /// Set `disposition_storage`
+    pub fn disposition_storage(self, storage: DispositionStorage) -> ParseOptions {
+        ParseOptions { disposition_storage: storage, ..self }
+    }
+
+    /// Set `default_filename_charset`
+    pub fn default_filename_charset(self, charset: Charset) -> ParseOptions {
+        ParseOptions { default_filename_charset: charset, ..self }
+    }
+
+    /// Set `max_filename_chars`
+    pub fn max_filename_chars(self, max: usize) -> ParseOptions {
+        ParseOptions { max_filename_chars: Some(max), ..self }
+    }
+
+    /// Set `normalize_filenames`.
+    ///
+    /// Only available with the `unicode-normalization` feature.
+    #[cfg(feature = "unicode-normalization")]
+    pub fn normalize_filenames(self, normalize: bool) -> ParseOptions {
+        ParseOptions { normalize_filenames: normalize, ..self }
+    }
+
+    /// Set `max_part_bytes`
+    pub fn max_part_bytes(self, max: usize) -> ParseOptions {
+        ParseOptions { max_part_bytes: Some(max), ..self }
+    }
+
+    /// Set `max_total_file_bytes`
+    pub fn max_total_file_bytes(self, max: usize) -> ParseOptions {
+        ParseOptions { max_total_file_bytes: Some(max), ..self }
+    }
+
+    /// Set `cancel`
+    pub fn cancel(self, cancel: Arc<AtomicBool>) -> ParseOptions {
+        ParseOptions { cancel: Some(cancel), ..self }
+    }
+
+    /// Set `header_line_endings`
+    pub fn header_line_endings(self, endings: HeaderLineEndings) -> ParseOptions {
+        ParseOptions { header_line_endings: endings, ..self }
+    }
+
+    /// Set `max_header_line_len`
+    pub fn max_header_line_len(self, max: usize) -> ParseOptions {
+        ParseOptions { max_header_line_len: Some(max), ..self }
+    }
+
+    /// Set `detect_base64`
+    pub fn detect_base64(self, detect: bool) -> ParseOptions {
+        ParseOptions { detect_base64: detect, ..self }
+    }
+
+    /// Set `spill`
+    pub fn spill(self, policy: SpillPolicy) -> ParseOptions {
+        ParseOptions { spill: Some(policy), ..self }
+    }
+
+    /// Set `detect_boundary`
+    pub fn detect_boundary(self, detect: bool) -> ParseOptions {
+        ParseOptions { detect_boundary: detect, ..self }
+    }
+
+    /// Set `anonymous_files`
+    pub fn anonymous_files(self, anonymous: bool) -> ParseOptions {
+        ParseOptions { anonymous_files: anonymous, ..self }
+    }
+
+    /// Set `content_length_framing`
+    pub fn content_length_framing(self, framing: bool) -> ParseOptions {
+        ParseOptions { content_length_framing: framing, ..self }
+    }
+
+    /// Set `content_type_check`
+    pub fn content_type_check(self, check: ContentTypeCheck) -> ParseOptions {
+        ParseOptions { content_type_check: Some(check), ..self }
+    }
+
+    /// Set `buffer_files_up_to`
+    pub fn buffer_files_up_to(self, max: usize) -> ParseOptions {
+        ParseOptions { buffer_files_up_to: Some(max), ..self }
+    }
+
+    /// Set `budget`
+    pub fn budget(self, budget: ParseBudget) -> ParseOptions {
+        ParseOptions { budget, ..self }
+    }
+
+    /// Set `invalid_content_type`
+    pub fn invalid_content_type(self, policy: InvalidContentType) -> ParseOptions {
+        ParseOptions { invalid_content_type: policy, ..self }
+    }
+
+    /// Set `header_policy`
+    pub fn header_policy(self, policy: HeaderPolicy) -> ParseOptions {
+        ParseOptions { header_policy: Some(policy), ..self }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+}
+
+/// Limits on the resources a parse may use, over the whole body (see
+/// `ParseOptions::budget`).  Exceeding any of them aborts the parse with its own error, even
+/// if the parse is best-effort.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseBudget {
+    /// The most bytes which may be read from the stream, including headers and boundaries,
+    /// or else `Error::BodyTooLarge`
+    pub max_bytes: Option<u64>,
+    /// The longest the parse may take, or else `Error::TimedOut`.  This is checked before
+    /// each read of the stream, so a read which blocks is not interrupted; set a timeout on
+    /// the stream itself for that.
+    pub max_duration: Option<Duration>,
+    /// The most parts the body may have, counting nested parts and the `multipart/*` parts
+    /// which contain them, or else `Error::TooManyParts`
+    pub max_parts: Option<usize>,
+    /// The most parts, nested or not, which may be kept in memory as fields (those which
+    /// the `DispositionStorage` does not stream to files), or else `Error::TooManyFields`.
+    /// A field which spills to a file under a `SpillPolicy` still counts as a field.
+    pub max_memory_parts: Option<usize>,
+    /// The most parts, nested or not, which may be streamed to files (or buffered as files,
+    /// under `ParseOptions::buffer_files_up_to`), or else `Error::TooManyFiles`.  This
+    /// allows a form, say, many small fields but only a few uploads.
+    pub max_file_parts: Option<usize>,
+}
+
+/// A policy for moving the bodies of parts which would be kept in memory to files instead,
+/// once they use too much memory.  Parts are moved as they are read, as soon as they cross
+/// a limit, so no more than the limits are ever held in memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpillPolicy {
+    /// The total number of bytes of part bodies, over the whole parse, which may be kept in
+    /// memory
+    pub memory_budget: usize,
+    /// The most bytes of any one part body which may be kept in memory, whatever remains
+    /// of the budget, or `None` for no limit beyond the budget
+    pub max_part_in_memory: Option<usize>,
+}
+
+/// How parts' declared content types are checked against their content (see
+/// `ParseOptions::content_type_check`).
+///
+/// Only a few types are recognised from content: PNG, JPEG, GIF and WebP images, PDF, ZIP
+/// and gzip files, HTML and SVG.  Content which is not recognised passes, as does any
+/// content declared as `application/octet-stream`.
+#[derive(Clone, Debug)]
+pub struct ContentTypeCheck {
+    /// Pairs of declared and recognised types which are allowed to disagree, for formats
+    /// which are known to be ambiguous.  A `*` type or subtype matches any; parameters are
+    /// ignored.  The default allows any `application/*` type to be a ZIP file, as many
+    /// formats (such as office documents and Java archives) are.
+    pub allowed_mismatches: Vec<(Mime, Mime)>,
+}
+
+impl Default for ContentTypeCheck {
+    fn default() -> ContentTypeCheck {
+        ContentTypeCheck {
+            allowed_mismatches: vec![(
+                Mime(TopLevel::Application, SubLevel::Star, vec![]),
+                Mime(TopLevel::Application, SubLevel::Ext("zip".to_owned()), vec![]),
+            )],
+        }
+    }
+}
+
+/// How the line terminators of part header lines are handled when parsing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderLineEndings {
+    /// Every header line is terminated with CRLF before the headers are parsed, whether the
+    /// body uses CRLF or bare LF line terminators, and whether or not the header lines
+    /// themselves end in CR.
+    Normalize,
+    /// Header lines are parsed exactly as received, with the line terminator detected for
+    /// the body.
+    AsReceived,
+}
+
+/// What to do with a part whose `Content-Type` header cannot be parsed as a MIME type (see
+/// `ParseOptions::invalid_content_type`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidContentType {
+    /// Keep the header as it was received.  The part is treated as having no content type,
+    /// so `content_type()` gives `None`, but the header is written back out unchanged.
+    Keep,
+    /// Replace the header with `application/octet-stream`, the type of arbitrary data.  The
+    /// header as it was received is still given by `read_multipart_body_with_raw_headers()`.
+    OctetStream,
+    /// Fail with `Error::InvalidContentType`, or skip the part if the parse is best-effort.
+    Reject,
+}
+
+/// Which header names a part may carry (see `ParseOptions::header_policy`).  Names are
+/// matched case-insensitively.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HeaderPolicy {
+    /// Allow only the headers named
+    Allow(Vec<String>),
+    /// Allow any header but those named
+    Deny(Vec<String>),
+}
+
+impl HeaderPolicy {
+    /// Whether a header of the given name is allowed
+    pub fn allows(&self, name: &str) -> bool {
+        match *self {
+            HeaderPolicy::Allow(ref names) => names.iter().any(|n| n.eq_ignore_ascii_case(name)),
+            HeaderPolicy::Deny(ref names) => ! names.iter().any(|n| n.eq_ignore_ascii_case(name)),
+        }
+    }
+}
+
+/// Where the body of a parsed part is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartStorage {
+    /// Keep the body in memory, as a `Node::Part`
+    Memory,
+    /// Stream the body to a file, as a `Node::File`
+    File,
+    /// Stream the body to a file if the `Content-Disposition` has a `filename` parameter,
+    /// otherwise keep it in memory
+    FileIfFilename,
+}
+
+/// A mapping from `Content-Disposition` types to the `PartStorage` used for parts of that
+/// type.
+///
+/// The default mapping streams `attachment` parts to files, keeps parts without a
+/// `Content-Disposition` in memory, and streams all other parts to files only if they
+/// specify a `filename`.
+#[derive(Clone, Debug)]
+pub struct DispositionStorage {
+    /// Storage for `Content-Disposition: inline` parts
+    pub inline: PartStorage,
+    /// Storage for `Content-Disposition: attachment` parts
+    pub attachment: PartStorage,
+    /// Storage for extension disposition types such as `form-data` or `file`, matched
+    /// case-insensitively.  Extension types not listed here use `default_ext`.
+    pub ext: Vec<(String, PartStorage)>,
+    /// Storage for extension disposition types not listed in `ext`
+    pub default_ext: PartStorage,
+    /// Storage for parts without a (parseable) `Content-Disposition` header
+    pub missing: PartStorage,
+}
+
+impl Default for DispositionStorage {
+    fn default() -> DispositionStorage {
+        DispositionStorage {
+            inline: PartStorage::FileIfFilename,
+            attachment: PartStorage::File,
+            ext: Vec::new(),
+            default_ext: PartStorage::FileIfFilename,
+            missing: PartStorage::Memory,
+        }
+    }
+}
+
+impl DispositionStorage {
+    /// Set the storage for an extension disposition type (e.g. `form-data`), replacing any
+    /// previous setting for that type.
+    pub fn set_ext(&mut self, disposition: &str, storage: PartStorage) {
+        self.ext.retain(|(d, _)| ! d.eq_ignore_ascii_case(disposition));
+        self.ext.push((disposition.to_owned(), storage));
+    }
+
+    /// The storage that applies to a part with the given headers, according to its
+    /// `Content-Disposition`.
+    pub fn storage(&self, headers: &Headers) -> PartStorage {
+        let disposition = match raw_disposition(headers) {
+            Some((disposition, _)) => disposition,
+            None => return self.missing,
+        };
+        if disposition.eq_ignore_ascii_case("inline") {
+            self.inline
+        } else if disposition.eq_ignore_ascii_case("attachment") {
+            self.attachment
+        } else {
+            self.ext.iter()
+                .find(|(d, _)| d.eq_ignore_ascii_case(&disposition))
+                .map(|&(_, storage)| storage)
+                .unwrap_or(self.default_ext)
+        }
+    }
+
+    /// Whether a part with the given headers should be streamed to a file.
+    pub fn is_file(&self, headers: &Headers) -> bool {
+        match self.storage(headers) {
+            PartStorage::Memory => false,
+            PartStorage::File => true,
+            PartStorage::FileIfFilename => raw_disposition(headers).is_some_and(|(_, params)| {
+                params.iter().any(|(name, _)| name == "filename" || name == "filename*")
+            }),
+        }
+    }
+}
+
+// The disposition type and parameters of the `Content-Disposition` header, if it has one.
+// This works from the raw header, as hyper fails to parse some valid headers (such as those
+// with a `;` within a quoted filename).
+fn raw_disposition(headers: &Headers) -> Option<(String, params::Params)> {
+    let raw = headers.get_raw("Content-Disposition")?.first()?;
+    let (disposition, params) = params::parse_header_params(raw);
+    if disposition.is_empty() {
+        return None;
+    }
+    Some((String::from_utf8_lossy(&disposition).into_owned(), params))
+}
+
+/// The line terminator used when writing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\r\n`, as MIME requires
+    CrLf,
+    /// `\n`
+    Lf,
+}
+
+impl LineEnding {
+    /// The bytes of the line terminator
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match *self {
+            LineEnding::CrLf => b"\r\n",
+            LineEnding::Lf => b"\n",
+        }
+    }
+}
+
+/// Options controlling how a `multipart/*` body is written.
+///
+/// The `Default` options reproduce the behaviour of `write_multipart()`.
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    /// The line terminator used after boundaries and headers, and within encoded bodies.
+    /// Defaults to `CrLf`.
+    pub line_ending: LineEnding,
+
+    /// If true, the body of a part with a `Content-Transfer-Encoding` of `base64` or
+    /// `quoted-printable` is taken to be unencoded, and is encoded as it is written.  If
+    /// false (the default), all bodies are written exactly as they are.
+    pub apply_transfer_encoding: bool,
+
+    /// The maximum length of the lines of base64 encoded bodies, or 0 to not break them
+    /// into lines at all.  Defaults to 76, as MIME email requires.
+    pub base64_line_length: usize,
+
+    /// If true, bare LFs in the bodies of parts with a `text/*` content type are converted
+    /// to CRLFs as they are written (before any transfer encoding), as SMTP requires.
+    /// Existing CRLFs are left alone, and other parts are written untouched.  Defaults to
+    /// false.
+    pub normalize_text_crlf: bool,
+
+    /// Which parts must have a `Content-Disposition` header.  Defaults to `Optional`.
+    pub disposition_policy: DispositionPolicy,
+
+    /// If true, SMTP dot-stuffing is applied to everything written (after any transfer
+    /// encoding): a `.` at the start of a line is doubled, so that the output is ready to
+    /// send as SMTP `DATA`.  The returned byte counts include the added dots.  Defaults to
+    /// false.
+    pub dot_stuff: bool,
+
+    /// If true, the content of each file is read exactly once, for sources such as named
+    /// pipes which can only be consumed once: writing fails with `Error::SourceReused`,
+    /// before anything is written, if the same file (by path, or the same anonymous file)
+    /// appears in more than one `FilePart`.  A `MultipartWriter` also refuses a file it
+    /// has already written.  Content held in memory may be repeated.  Defaults to false,
+    /// which opens and reads each file afresh wherever it appears.
+    pub consume_once: bool,
+}
+
+/// Which parts of a body being written must have a `Content-Disposition` header.
+///
+/// `multipart/form-data` requires every part to have a `Content-Disposition` with a `name`
+/// parameter, whereas other subtypes such as `multipart/mixed` and `multipart/related`
+/// require none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DispositionPolicy {
+    /// No part is required to have a `Content-Disposition`
+    Optional,
+    /// The body is `multipart/form-data`: every top-level part must have a
+    /// `Content-Disposition` with a `name`, or writing fails with
+    /// `Error::MissingDisposition`.  Nested `multipart/*` bodies are held to this only if
+    /// they are themselves `multipart/form-data`.
+    FormData,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions {
+            line_ending: LineEnding::CrLf,
+            apply_transfer_encoding: false,
+            base64_line_length: 76,
+            normalize_text_crlf: false,
+            disposition_policy: DispositionPolicy::Optional,
+            dot_stuff: false,
+            consume_once: false,
+        }
+    }
+}
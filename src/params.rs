@@ -0,0 +1,245 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Parsing of the `;`-separated parameters of raw header values such as `Content-Type`
+//! and `Content-Disposition`.
+//!
+//! The `mime` and `hyper` parsers silently drop parameters they cannot handle, so this is
+//! used where we need to see every parameter as it was sent.
+
+use hyper::header::Headers;
+
+// Header parameters as `(name, value)` pairs
+pub(crate) type Params = Vec<(String, Vec<u8>)>;
+
+// Split a raw header value such as `multipart/mixed; boundary="abc"` into its leading value
+// and its `(name, value)` parameters, in the order given.  Names are lowercased, whitespace
+// around names and values is trimmed, and quoted values have their quotes removed and any
+// backslash escapes (such as `\"` and `\\`) undone.
+// Parameters without a value are skipped, and RFC 2231 continuations are joined (see
+// `join_continuations()`).
+pub(crate) fn parse_header_params(raw: &[u8]) -> (Vec<u8>, Params) {
+    let (value, mut params) = split_header_params(raw);
+    for param in params.iter_mut() {
+        param.0.make_ascii_lowercase();
+    }
+    (value, join_continuations(params))
+}
+
+// Join the sections of each parameter which RFC 2231 splits into continuations (`name*0`,
+// `name*1`, and so on, each ending in another `*` if its value is percent-encoded) into one
+// parameter, in place of the first section.  Sections are joined in order up to the first
+// one missing.  If the first section is encoded, and so begins with the charset, the result
+// is an RFC 5987 extended value named `name*`, with any unencoded sections encoded to
+// match; otherwise it is named `name`, with any encoded sections decoded.
+fn join_continuations(params: Params) -> Params {
+    let mut joined: Params = Vec::with_capacity(params.len());
+    for (name, value) in &params {
+        let (base, first_encoded) = match continuation(name) {
+            Some((base, 0, encoded)) => (base, encoded),
+            Some(_) => continue,
+            None => {
+                joined.push((name.clone(), value.clone()));
+                continue;
+            },
+        };
+        let mut output = value.clone();
+        for section in 1.. {
+            let next = params.iter().find_map(|(name, value)| match continuation(name) {
+                Some((b, s, encoded)) if b == base && s == section => Some((value, encoded)),
+                _ => None,
+            });
+            match next {
+                Some((value, true)) if ! first_encoded => output.extend(percent_decode(value)),
+                Some((value, false)) if first_encoded => output.extend(percent_encode(value)),
+                Some((value, _)) => output.extend_from_slice(value),
+                None => break,
+            }
+        }
+        let name = if first_encoded { format!("{}*", base) } else { base.to_owned() };
+        joined.push((name, output));
+    }
+    joined
+}
+
+// Split an RFC 2231 continuation's name, such as `filename*1*`, into the name it continues,
+// its section number, and whether its value is encoded
+fn continuation(name: &str) -> Option<(&str, usize, bool)> {
+    let (name, encoded) = match name.strip_suffix('*') {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let star = name.rfind('*')?;
+    let section = &name[star + 1..];
+    if section.is_empty() || ! section.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((&name[..star], section.parse().ok()?, encoded))
+}
+
+// Undo RFC 2231 percent-encoding, leaving anything which is not a valid escape as it is
+fn percent_decode(value: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        let escaped = value.get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| ::std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) if value[i] == b'%' => {
+                output.push(b);
+                i += 3;
+            },
+            _ => {
+                output.push(value[i]);
+                i += 1;
+            },
+        }
+    }
+    output
+}
+
+// Percent-encode the bytes of a value which are not allowed unencoded in an RFC 5987
+// extended value
+fn percent_encode(value: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(value.len());
+    for &b in value {
+        if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+            output.push(b);
+        } else {
+            output.extend_from_slice(format!("%{:02X}", b).as_bytes());
+        }
+    }
+    output
+}
+
+// As `parse_header_params()`, but keeping the case of the names
+pub(crate) fn split_header_params(raw: &[u8]) -> (Vec<u8>, Params) {
+    let mut pos = raw.iter().position(|&b| b == b';').unwrap_or(raw.len());
+    let value = trim(&raw[..pos]).to_vec();
+    let mut params: Params = Vec::new();
+
+    while pos < raw.len() {
+        // Skip the ';'
+        pos += 1;
+
+        // Parameter name
+        let name_end = raw[pos..].iter().position(|&b| b == b'=' || b == b';')
+            .map(|i| pos + i)
+            .unwrap_or(raw.len());
+        let name = String::from_utf8_lossy(trim(&raw[pos..name_end])).into_owned();
+        pos = name_end;
+        if pos >= raw.len() || raw[pos] == b';' {
+            continue;
+        }
+
+        // Skip the '=' and any whitespace before the value
+        pos += 1;
+        while pos < raw.len() && is_whitespace(raw[pos]) {
+            pos += 1;
+        }
+
+        let value = if pos < raw.len() && raw[pos] == b'"' {
+            // A quoted-string, in which a backslash escapes the next character
+            pos += 1;
+            let mut value: Vec<u8> = Vec::new();
+            let mut end = pos;
+            while end < raw.len() && raw[end] != b'"' {
+                if raw[end] == b'\\' && end + 1 < raw.len() {
+                    end += 1;
+                }
+                value.push(raw[end]);
+                end += 1;
+            }
+            // Skip the closing quote and anything up to the next ';'
+            pos = raw[end..].iter().position(|&b| b == b';')
+                .map(|i| end + i)
+                .unwrap_or(raw.len());
+            value
+        } else {
+            let end = raw[pos..].iter().position(|&b| b == b';')
+                .map(|i| pos + i)
+                .unwrap_or(raw.len());
+            let value = trim(&raw[pos..end]).to_vec();
+            pos = end;
+            value
+        };
+
+        if ! name.is_empty() {
+            params.push((name, value));
+        }
+    }
+
+    (value, params)
+}
+
+// Find the value of a parameter (by lowercase name) within a raw header value
+pub(crate) fn find_param(raw: &[u8], name: &str) -> Option<Vec<u8>> {
+    let (_, params) = parse_header_params(raw);
+    params.into_iter().find(|(n, _)| n == name).map(|(_, v)| v)
+}
+
+// Find the value of a parameter (by lowercase name) within the first raw value of the
+// named header
+pub(crate) fn find_header_param(headers: &Headers, header: &str, name: &str) -> Option<Vec<u8>> {
+    headers.get_raw(header)
+        .and_then(|raw| raw.first())
+        .and_then(|raw| find_param(raw, name))
+}
+
+// Join a leading value and its parameters back into a header value, quoting any parameter
+// value which needs it (RFC 2045 section 5.1).
+pub(crate) fn format_header_params(value: &[u8], params: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut output = value.to_vec();
+    for (name, value) in params {
+        output.extend_from_slice(b"; ");
+        output.extend_from_slice(name.as_bytes());
+        output.push(b'=');
+        output.extend(quote(value));
+    }
+    output
+}
+
+// A parameter value, as a quoted-string if it is not a valid token
+pub(crate) fn quote(value: &[u8]) -> Vec<u8> {
+    let is_token = ! value.is_empty() &&
+        value.iter().all(|&b| b > b' ' && b < 0x7f && ! TSPECIALS.contains(&b));
+    if is_token {
+        return value.to_vec();
+    }
+    let mut output = Vec::with_capacity(value.len() + 2);
+    output.push(b'"');
+    for &b in value {
+        if b == b'"' || b == b'\\' {
+            output.push(b'\\');
+        }
+        output.push(b);
+    }
+    output.push(b'"');
+    output
+}
+
+// An RFC 5987 extended parameter value (as for `filename*`) holding `value` in UTF-8
+pub(crate) fn extended_value(value: &str) -> Vec<u8> {
+    let mut output = b"UTF-8''".to_vec();
+    output.extend(percent_encode(value.as_bytes()));
+    output
+}
+
+const TSPECIALS: &[u8] = b"()<>@,;:\\\"/[]?=";
+
+#[inline]
+fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+pub(crate) fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| ! is_whitespace(b)).unwrap_or(bytes.len());
+    let end = bytes.iter().rposition(|&b| ! is_whitespace(b)).map(|i| i + 1).unwrap_or(start);
+    &bytes[start..end]
+}
@@ -0,0 +1,125 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use super::ParseOptions;
+
+// The number of consecutive interruptions, or of reads in a row at the end of the stream,
+// before we give up
+const MAX_STALLED_READS: usize = 16;
+
+// Wraps the stream being parsed to defend against misbehaving readers.
+//
+// A read which returns `Ok(0)` is the end of the stream, and is passed straight on without
+// reading again.  A read which keeps failing with `Interrupted`, or a stream which is read
+// again and again after it has ended with nothing to show for it, is given up on and flagged
+// as `stalled`, so that the parser can report `Error::NoProgress` instead of spinning or (as
+// `stream_until_token` would) mistaking interruptions for the end of the stream.
+//
+// Each read also checks the cancel flag, if there is one, so that a parse is cancelled
+// promptly even in the middle of a large part.  A cancelled read is flagged as `cancelled`
+// so that the parser can report `Error::Cancelled`.
+//
+// The `ParseBudget`'s byte and time limits are enforced here too, as every byte parsed
+// passes through.  A read past either is flagged as `too_large` or `timed_out`.  The time
+// limit is checked as each read starts, so a read which blocks is not cut short.
+pub(crate) struct ProgressReader<R> {
+    inner: R,
+    cancel: Option<Arc<AtomicBool>>,
+    remaining: Option<u64>,
+    deadline: Option<Instant>,
+    // The number of reads in a row which have returned nothing
+    empty_reads: usize,
+    pub(crate) stalled: bool,
+    pub(crate) cancelled: bool,
+    pub(crate) too_large: bool,
+    pub(crate) timed_out: bool,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub(crate) fn new(inner: R, options: &ParseOptions) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            cancel: options.cancel.clone(),
+            remaining: options.budget.max_bytes,
+            deadline: options.budget.max_duration.map(|duration| Instant::now() + duration),
+            empty_reads: 0,
+            stalled: false,
+            cancelled: false,
+            too_large: false,
+            timed_out: false,
+        }
+    }
+
+    // Flag the stream as having made no progress
+    fn stall(&mut self) -> io::Error {
+        self.stalled = true;
+        io::Error::other("the stream made no progress")
+    }
+
+    // Count the bytes of a successful read against the byte limit
+    fn count(&mut self, result: io::Result<usize>) -> io::Result<usize> {
+        let n = result?;
+        if let Some(ref mut remaining) = self.remaining {
+            if n as u64 > *remaining {
+                self.too_large = true;
+                return Err(io::Error::other("body too large"));
+            }
+            *remaining -= n as u64;
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            self.cancelled = true;
+            return Err(io::Error::other("cancelled"));
+        }
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.timed_out = true;
+            return Err(io::Error::other("timed out"));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // Read at most one byte past the limit, to see whether the stream goes past it
+        let len = match self.remaining {
+            Some(remaining) => usize::try_from(remaining.saturating_add(1))
+                .map_or(buf.len(), |max| buf.len().min(max)),
+            None => buf.len(),
+        };
+        let buf = &mut buf[..len];
+        let mut interruptions: usize = 0;
+        loop {
+            match self.inner.read(buf) {
+                Ok(0) => {
+                    self.empty_reads += 1;
+                    if self.empty_reads > MAX_STALLED_READS {
+                        return Err(self.stall());
+                    }
+                    return Ok(0);
+                },
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {
+                    interruptions += 1;
+                    if interruptions >= MAX_STALLED_READS {
+                        return Err(self.stall());
+                    }
+                },
+                result => {
+                    self.empty_reads = 0;
+                    return self.count(result);
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Reports on trees of nodes, for auditing uploads and debugging.
+
+use std::io::Write;
+use hyper::header::{ContentLength, Headers};
+use super::{get_multipart_boundary, header_value, params, Error, Leaves, Node};
+
+/// The declared and actual sizes of the body of one part, from `size_report()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartSize {
+    /// The index of the part among all parts (but not nested multiparts themselves), depth
+    /// first and in order, as `files()` and `parts()` visit them
+    pub index: usize,
+    /// The size the part declared, from its `Content-Length` header or else the `size`
+    /// parameter of its `Content-Disposition` (RFC 2183), if either was given and valid
+    pub declared: Option<u64>,
+    /// The size of the body as parsed (after any decoding, such as `detect_base64`)
+    pub actual: u64,
+}
+
+impl PartSize {
+    /// Whether the part declared a size which differs from its actual size, as when an
+    /// upload was truncated or padded
+    pub fn is_discrepant(&self) -> bool {
+        self.declared.is_some_and(|declared| declared != self.actual)
+    }
+}
+
+/// The declared and actual sizes of the bodies of all the parts in a tree of nodes, from
+/// `size_report()`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// The sizes of each part, in order
+    pub parts: Vec<PartSize>,
+    /// The sum of the sizes declared, over the parts which declared one
+    pub declared_total: u64,
+    /// The sum of the actual sizes of all the parts
+    pub actual_total: u64,
+}
+
+impl SizeReport {
+    /// The parts whose declared size differs from their actual size
+    pub fn discrepancies(&self) -> impl Iterator<Item = &PartSize> {
+        self.parts.iter().filter(|part| part.is_discrepant())
+    }
+}
+
+/// Report the declared and actual sizes of the bodies of every part in the tree of `nodes`,
+/// including those within nested `multipart/*` nodes, for detecting truncated or padded
+/// uploads.  This only reads the nodes; a file part whose size was not recorded when it was
+/// parsed is sized from its file, which fails if the file cannot be read.
+pub fn size_report(nodes: &[Node]) -> Result<SizeReport, Error> {
+    let mut report = SizeReport::default();
+    for (index, node) in Leaves::new(nodes).enumerate() {
+        let actual = match *node {
+            Node::Part(ref part) => part.body.len() as u64,
+            Node::File(ref filepart) => match filepart.size {
+                Some(size) => size,
+                None => filepart.content_len()?,
+            },
+            Node::Multipart(_) => continue,
+        };
+        let declared = declared_size(node.headers());
+        report.declared_total += declared.unwrap_or(0);
+        report.actual_total += actual;
+        report.parts.push(PartSize { index, declared, actual });
+    }
+    Ok(report)
+}
+
+// The size a part declares in its headers
+fn declared_size(headers: &Headers) -> Option<u64> {
+    if let Some(&ContentLength(length)) = headers.get::<ContentLength>() {
+        return Some(length);
+    }
+    params::find_header_param(headers, "Content-Disposition", "size")
+        .and_then(|size| String::from_utf8(size).ok())
+        .and_then(|size| size.parse().ok())
+}
+
+// How many bytes of a part body `debug_dump()` shows
+const PREVIEW_LEN: usize = 40;
+
+/// Write a human-readable outline of the tree of `nodes` to `out`, for logging and for
+/// diffing in tests: each node's kind and headers, a preview of each part body, the
+/// location and size of each file, and the boundary of each nested multipart, indented by
+/// depth.  This is not MIME; use `write_multipart()` to write the nodes themselves.
+pub fn debug_dump<W: Write + ?Sized>(nodes: &[Node], out: &mut W) -> Result<(), Error> {
+    dump_nodes(nodes, out, 0)
+}
+
+fn dump_nodes<W: Write + ?Sized>(nodes: &[Node], out: &mut W, depth: usize)
+    -> Result<(), Error>
+{
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        match *node {
+            Node::Part(ref part) => {
+                writeln!(out, "{}part", indent)?;
+                dump_headers(&part.headers, out, &indent)?;
+                let preview = &part.body[..part.body.len().min(PREVIEW_LEN)];
+                let more = if preview.len() < part.body.len() { "..." } else { "" };
+                writeln!(out, "{}  body: {} bytes \"{}\"{}", indent, part.body.len(),
+                         preview.escape_ascii(), more)?;
+            },
+            Node::File(ref filepart) => {
+                let location = if filepart.content().is_some() {
+                    "in memory".to_owned()
+                } else if filepart.is_anonymous() {
+                    "anonymous file".to_owned()
+                } else {
+                    filepart.path.display().to_string()
+                };
+                writeln!(out, "{}file ({})", indent, location)?;
+                dump_headers(&filepart.headers, out, &indent)?;
+                match filepart.size.map_or_else(|| filepart.content_len(), Ok) {
+                    Ok(size) => writeln!(out, "{}  size: {} bytes", indent, size)?,
+                    Err(_) => writeln!(out, "{}  size: unknown", indent)?,
+                }
+            },
+            Node::Multipart((ref headers, ref subnodes)) => {
+                match get_multipart_boundary(headers) {
+                    Ok(boundary) => writeln!(out, "{}multipart (boundary \"{}\")", indent,
+                                             boundary[2..].escape_ascii())?,
+                    Err(_) => writeln!(out, "{}multipart (no boundary)", indent)?,
+                }
+                dump_headers(headers, out, &indent)?;
+                dump_nodes(subnodes, out, depth + 1)?;
+            },
+        }
+    }
+    Ok(())
+}
+
+fn dump_headers<W: Write + ?Sized>(headers: &Headers, out: &mut W, indent: &str)
+    -> Result<(), Error>
+{
+    for header in headers.iter() {
+        writeln!(out, "{}  {}: {}", indent, header.name(),
+                 String::from_utf8_lossy(&header_value(headers, &header)))?;
+    }
+    Ok(())
+}
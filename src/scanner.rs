@@ -0,0 +1,102 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::io::{BufRead, Write};
+use buf_read_ext::BufReadExt;
+use super::{read_first_boundary, skip_transport_padding, Error};
+
+/// Splits a `multipart/*` body into the raw bytes of its parts (each part's headers, blank
+/// line and body, exactly as received) by scanning for its boundaries, without parsing
+/// anything else.  This is for building custom processing without the `Node` machinery.
+///
+/// Each part can be streamed to a `Write` with `next_part()`, or the scanner can be used as
+/// an `Iterator` yielding each part's bytes in memory along with whether it is the final
+/// part.  The preamble and epilogue are discarded.
+#[derive(Debug)]
+pub struct BoundaryScanner<R: BufRead> {
+    reader: R,
+    boundary: Vec<u8>,
+    // The line terminator and the boundary which follows it, once the first boundary is found
+    lt_boundary: Option<(Vec<u8>, Vec<u8>)>,
+    finished: bool,
+}
+
+impl<R: BufRead> BoundaryScanner<R> {
+    /// Create a `BoundaryScanner` of the body in `reader`, with the given `boundary` (as it
+    /// would appear in the `Content-Type` header, without the leading `--`)
+    pub fn new(reader: R, boundary: &[u8]) -> BoundaryScanner<R> {
+        let mut full_boundary = Vec::with_capacity(2 + boundary.len());
+        full_boundary.extend_from_slice(b"--");
+        full_boundary.extend_from_slice(boundary);
+        BoundaryScanner {
+            reader,
+            boundary: full_boundary,
+            lt_boundary: None,
+            finished: false,
+        }
+    }
+
+    /// Stream the raw bytes of the next part to `out`.  Returns the number of bytes written
+    /// and whether this was the final part, or `None` if there are no more parts.
+    pub fn next_part<W: Write>(&mut self, out: &mut W) -> Result<Option<(usize, bool)>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+        let (lt, lt_boundary) = match self.lt_boundary {
+            Some(ref lt_boundary) => lt_boundary,
+            None => self.lt_boundary.insert(read_first_boundary(&mut self.reader, &self.boundary)?),
+        };
+
+        // If the next two lookahead characters are '--', there are no more parts
+        {
+            skip_transport_padding(&mut self.reader)?;
+            let peeker = self.reader.fill_buf()?;
+            if peeker.len() >= 2 && &peeker[..2] == b"--" {
+                self.finished = true;
+                return Ok(None);
+            }
+            if peeker.is_empty() {
+                return Err(Error::EofAfterBoundary);
+            }
+        }
+
+        // Read the line terminator after the boundary
+        let (_, found) = self.reader.stream_until_token(lt, &mut ::std::io::sink())?;
+        if ! found { return Err(Error::NoCrLfAfterBoundary); }
+
+        // Stream the part, up to the next boundary
+        let (read, found) = self.reader.stream_until_token(lt_boundary, out)?;
+        if ! found { return Err(Error::EofInPart); }
+
+        skip_transport_padding(&mut self.reader)?;
+        let peeker = self.reader.fill_buf()?;
+        self.finished = peeker.len() >= 2 && &peeker[..2] == b"--";
+        Ok(Some((read, self.finished)))
+    }
+
+    /// Consume the `BoundaryScanner`, returning the reader.  After the final part, the
+    /// reader is positioned at the `--` which ends the closing boundary.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: BufRead> Iterator for BoundaryScanner<R> {
+    type Item = Result<(Vec<u8>, bool), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut part: Vec<u8> = Vec::new();
+        match self.next_part(&mut part) {
+            Ok(Some((_, is_final))) => Some(Ok((part, is_final))),
+            Ok(None) => None,
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Recognition of content types from the first bytes of a body.
+
+use mime::Mime;
+
+// How many bytes of a body are enough to recognise its type
+pub(crate) const SNIFF_LEN: usize = 512;
+
+// The content type of a body, recognised from (up to) its first `SNIFF_LEN` bytes by magic
+// numbers or markup, or `None` if it is not recognised.  Only types which are unambiguous
+// from their first bytes are recognised.
+pub(crate) fn sniff(prefix: &[u8]) -> Option<Mime> {
+    let found = if prefix.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if prefix.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if prefix.starts_with(b"GIF87a") || prefix.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if prefix.len() >= 12 && &prefix[..4] == b"RIFF" && &prefix[8..12] == b"WEBP" {
+        "image/webp"
+    } else if prefix.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if prefix.starts_with(b"PK\x03\x04") {
+        "application/zip"
+    } else if prefix.starts_with(b"\x1f\x8b") {
+        "application/gzip"
+    } else {
+        return sniff_markup(prefix).map(|found| found.parse().unwrap());
+    };
+    Some(found.parse().unwrap())
+}
+
+// Recognise HTML and SVG, which a browser might run scripts within
+fn sniff_markup(prefix: &[u8]) -> Option<&'static str> {
+    let text = prefix.strip_prefix(b"\xef\xbb\xbf").unwrap_or(prefix);
+    let start = text.iter().position(|b| ! b.is_ascii_whitespace())?;
+    let text = text[start..].to_ascii_lowercase();
+    const HTML: &[&[u8]] = &[b"<!doctype html", b"<html", b"<head", b"<body", b"<script",
+                             b"<iframe"];
+    if HTML.iter().any(|tag| text.starts_with(tag)) {
+        Some("text/html")
+    } else if text.starts_with(b"<svg") ||
+        (text.starts_with(b"<?xml") && text.windows(4).any(|w| w == b"<svg"))
+    {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::fs::File;
+use std::io::{self, Write};
+use hyper::header::Headers;
+use super::{create_filepart, Error, FilePart, ParseOptions};
+
+// Where a part body written through a `SpillWriter` ended up
+pub(crate) enum Spilled {
+    Memory(Vec<u8>),
+    // The file part has no headers yet
+    File(FilePart),
+}
+
+// Collects a part body in memory until it would exceed `limit` bytes, then moves it into a
+// temporary file and writes the rest there, failing with `Error::SizeLimitExceeded` if the
+// file would exceed `file_limit` bytes.
+pub(crate) struct SpillWriter<'a> {
+    memory: Vec<u8>,
+    limit: Option<usize>,
+    file_limit: Option<usize>,
+    file: Option<(FilePart, File)>,
+    file_len: usize,
+    options: &'a ParseOptions,
+    // An error creating the file, which the failed write could not carry
+    error: Option<Error>,
+}
+
+impl<'a> SpillWriter<'a> {
+    pub(crate) fn new(limit: Option<usize>, file_limit: Option<usize>, options: &'a ParseOptions)
+        -> SpillWriter<'a>
+    {
+        SpillWriter {
+            memory: Vec::new(),
+            limit,
+            file_limit,
+            file: None,
+            file_len: 0,
+            options,
+            error: None,
+        }
+    }
+
+    pub(crate) fn is_spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    // Convert the result of writing through this into our result, reporting a write which
+    // failed because the file could not be created, or would exceed its limit, with the
+    // error which caused it.
+    pub(crate) fn check<T>(&mut self, result: Result<T, Error>) -> Result<T, Error> {
+        match result {
+            Err(_) if self.error.is_some() => Err(self.error.take().unwrap()),
+            result => result,
+        }
+    }
+
+    pub(crate) fn finish(self) -> Spilled {
+        match self.file {
+            Some((filepart, _)) => Spilled::File(filepart),
+            None => Spilled::Memory(self.memory),
+        }
+    }
+
+    fn spill(&mut self) -> io::Result<&mut File> {
+        let (filepart, mut file) = match self.create() {
+            Ok(created) => created,
+            Err(e) => {
+                self.error = Some(e);
+                return Err(io::Error::other("failed to spill to a file"));
+            },
+        };
+        file.write_all(&self.memory)?;
+        self.memory = Vec::new();
+        Ok(&mut self.file.insert((filepart, file)).1)
+    }
+
+    fn create(&self) -> Result<(FilePart, File), Error> {
+        let filepart = create_filepart(Headers::new(), self.options)?;
+        let file = filepart.create_file()?;
+        Ok((filepart, file))
+    }
+}
+
+impl<'a> Write for SpillWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let spilling = self.file.is_some() ||
+            self.limit.is_some_and(|limit| self.memory.len() + buf.len() > limit);
+        if ! spilling {
+            self.memory.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+
+        let file_len = if self.file.is_some() { self.file_len } else { self.memory.len() };
+        if self.file_limit.is_some_and(|limit| file_len + buf.len() > limit) {
+            self.error = Some(Error::SizeLimitExceeded);
+            return Err(io::Error::other("size limit exceeded"));
+        }
+        let file = match self.file {
+            Some((_, ref mut file)) => file,
+            None => self.spill()?,
+        };
+        let n = file.write(buf)?;
+        self.file_len = file_len + n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some((_, ref mut file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
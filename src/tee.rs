@@ -0,0 +1,68 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::io::{Result, Write};
+
+/// A `Write` that fans every write out to two destinations, e.g. a network stream and a
+/// hasher or log.
+///
+/// Each write is written completely to both destinations before it is counted, so the
+/// count returned by `write_multipart(&mut tee, ...)` (and by `bytes_written()`) is the
+/// single number of bytes that reached each of them.
+#[derive(Debug)]
+pub struct TeeWriter<A: Write, B: Write> {
+    first: A,
+    second: B,
+    count: u64,
+}
+
+impl<A: Write, B: Write> TeeWriter<A, B> {
+    /// Create a `TeeWriter` writing to both `first` and `second`
+    pub fn new(first: A, second: B) -> TeeWriter<A, B> {
+        TeeWriter {
+            first,
+            second,
+            count: 0,
+        }
+    }
+
+    /// The number of bytes written to each destination so far
+    pub fn bytes_written(&self) -> u64 {
+        self.count
+    }
+
+    /// Get references to the two destinations
+    pub fn get_ref(&self) -> (&A, &B) {
+        (&self.first, &self.second)
+    }
+
+    /// Get mutable references to the two destinations
+    pub fn get_mut(&mut self) -> (&mut A, &mut B) {
+        (&mut self.first, &mut self.second)
+    }
+
+    /// Consume the `TeeWriter`, returning the two destinations
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Write, B: Write> Write for TeeWriter<A, B> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // Partial writes to either side would leave the two out of step, so always write
+        // everything to both.
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        self.count += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
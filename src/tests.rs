@@ -234,7 +234,7 @@ fn get_content_disposition_name(cd: &ContentDisposition) -> Option<String> {
 #[test]
 fn test_output() {
     let mut output: Vec<u8> = Vec::new();
-    let boundary = generate_boundary();
+    let boundary = generate_boundary().unwrap();
 
     let first_name = Part {
         headers: {
@@ -283,7 +283,7 @@ fn test_output() {
 #[test]
 fn test_chunked() {
     let mut output: Vec<u8> = Vec::new();
-    let boundary = generate_boundary();
+    let boundary = generate_boundary().unwrap();
 
     let first_name = Part {
         headers: {
@@ -324,3 +324,3018 @@ fn test_chunked() {
 
     assert_eq!(output.len(), 557);
 }
+
+#[test]
+fn nested_followed_by_part() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"files\"\r\n\
+                  Content-Type: multipart/mixed; boundary=BbC04y\r\n\
+                  \r\n\
+                  --BbC04y\r\n\
+                  Content-Disposition: file; filename=\"file1.txt\"\r\n\
+                  \r\n\
+                  ... contents of file1.txt ...\r\n\
+                  --BbC04y--\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x--";
+
+    let nodes = read_multipart(&mut &input[..], false).unwrap();
+    assert_eq!(nodes.len(), 2);
+
+    if let Node::Part(ref part) = nodes[1] {
+        assert_eq!(part.body, b"Larry");
+    } else {
+        panic!("2nd node of wrong type");
+    }
+}
+
+#[test]
+fn nested_missing_boundary() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"files\"\r\n\
+                  Content-Type: multipart/mixed\r\n\
+                  \r\n\
+                  --BbC04y\r\n\
+                  \r\n\
+                  ... contents of file1.txt ...\r\n\
+                  --BbC04y--\r\n\
+                  --AaB03x--";
+
+    match read_multipart(&mut &input[..], false) {
+        Err(Error::NestedMultipartMissingBoundary) => (),
+        other => panic!("Unexpected result: {:?}", other.map(|nodes| nodes.len())),
+    }
+}
+
+#[test]
+fn nested_missing_boundary_best_effort() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"files\"\r\n\
+                  Content-Type: multipart/mixed\r\n\
+                  \r\n\
+                  --BbC04y\r\n\
+                  \r\n\
+                  ... contents of file1.txt ...\r\n\
+                  --BbC04y--\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    // The malformed nested multipart is skipped, and the parts after it kept
+    let options = ParseOptions::default();
+    let (nodes, result) = read_multipart_body_best_effort(&mut &input[..], &headers, &options);
+    assert!(matches!(result, Err(Error::NestedMultipartMissingBoundary)));
+    assert_eq!(nodes.len(), 1);
+    if let Node::Part(ref part) = nodes[0] {
+        assert_eq!(part.body, b"Larry");
+    } else {
+        panic!("1st node of wrong type");
+    }
+
+    // A context keeps its scratch buffer when the parse fails there
+    let mut context = MultipartContext::new();
+    let mut stream = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\r\n".to_vec();
+    stream.extend_from_slice(input);
+    assert!(matches!(read_multipart_with_context(&mut &stream[..], &mut context, false),
+                     Err(Error::NestedMultipartMissingBoundary)));
+    assert!(context.buf.capacity() > 0);
+}
+
+#[cfg(feature = "hyper-server")]
+#[test]
+fn from_request() {
+    let input = b"POST / HTTP/1.1\r\n\
+                  Host: example.domain\r\n\
+                  Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  Content-Length: 1000\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x--";
+
+    let mut mock = MockStream::with_input(input);
+
+    let mock: &mut dyn NetworkStream = &mut mock;
+    let mut stream = BufReader::new(mock);
+    let sock: SocketAddr = "127.0.0.1:80".parse().unwrap();
+    let req = HyperRequest::new(&mut stream, sock).unwrap();
+
+    let nodes = read_multipart_from_request(req, false).unwrap();
+    assert_eq!(nodes.len(), 1);
+}
+
+#[test]
+fn canonical_header_names() {
+    let input = b"content-type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  content-disposition: form-data; name=\"submit-name\"\r\n\
+                  X-CUSTOM-header: 1\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x--";
+
+    // By default, names are kept as received
+    let nodes = read_multipart(&mut &input[..], false).unwrap();
+    if let Node::Part(ref part) = nodes[0] {
+        let names: Vec<&str> = part.headers.iter().map(|h| h.name()).collect();
+        assert!(names.contains(&"content-disposition"));
+        assert!(names.contains(&"X-CUSTOM-header"));
+    } else {
+        panic!("1st node of wrong type");
+    }
+
+    let options = ParseOptions {
+        canonicalize_header_names: true,
+        ..Default::default()
+    };
+    let nodes = read_multipart_with_options(&mut &input[..], &options).unwrap();
+    if let Node::Part(ref part) = nodes[0] {
+        let names: Vec<&str> = part.headers.iter().map(|h| h.name()).collect();
+        assert!(names.contains(&"Content-Disposition"));
+        assert!(names.contains(&"X-Custom-Header"));
+    } else {
+        panic!("1st node of wrong type");
+    }
+}
+
+#[test]
+fn disposition_storage() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename=\"file.txt\"\r\n\
+                  \r\n\
+                  This is a file\r\n\
+                  --AaB03x\r\n\
+                  Content-Type: text/plain\r\n\
+                  \r\n\
+                  No disposition\r\n\
+                  --AaB03x--";
+
+    let mut options = ParseOptions::default();
+    options.disposition_storage.set_ext("FORM-DATA", PartStorage::File);
+    options.disposition_storage.attachment = PartStorage::Memory;
+    options.disposition_storage.missing = PartStorage::FileIfFilename;
+
+    let nodes = read_multipart_with_options(&mut &input[..], &options).unwrap();
+    assert_eq!(nodes.len(), 3);
+    assert!(matches!(nodes[0], Node::File(_)));
+    assert!(matches!(nodes[1], Node::Part(_)));
+    assert!(matches!(nodes[2], Node::Part(_)));
+}
+
+#[test]
+fn eof_after_boundary() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x";
+    match read_multipart(&mut &input[..], false) {
+        Err(Error::EofAfterBoundary) => (),
+        other => panic!("Unexpected result: {:?}", other.map(|nodes| nodes.len())),
+    }
+
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x";
+    match read_multipart(&mut &input[..], false) {
+        Err(Error::EofAfterBoundary) => (),
+        other => panic!("Unexpected result: {:?}", other.map(|nodes| nodes.len())),
+    }
+}
+
+#[test]
+fn boundary_whitespace() {
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary= AaB03x ".to_vec()]);
+    assert_eq!(get_multipart_boundary(&headers).unwrap(), b"--AaB03x");
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=\" AaB03x\"".to_vec()]);
+    assert_eq!(get_multipart_boundary(&headers).unwrap(), b"--AaB03x");
+
+    let input = b"Content-Type: multipart/form-data; boundary= AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x--";
+    let nodes = read_multipart(&mut &input[..], false).unwrap();
+    assert_eq!(nodes.len(), 1);
+}
+
+#[test]
+fn header_injection() {
+    let mut output: Vec<u8> = Vec::new();
+    let boundary = generate_boundary().unwrap();
+
+    let part = Part {
+        headers: {
+            let mut h = Headers::new();
+            h.set(ContentDisposition {
+                disposition: DispositionType::Ext("form-data".to_owned()),
+                parameters: vec![DispositionParam::Ext("name".to_owned(),
+                                                       "x\r\nX-Injected: 1".to_owned())],
+            });
+            h
+        },
+        body: b"Michael".to_vec(),
+    };
+    let nodes: Vec<Node> = vec![Node::Part(part)];
+
+    match write_multipart(&mut output, &boundary, &nodes) {
+        Err(Error::HeaderInjection(ref name)) => assert_eq!(name, "Content-Disposition"),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    match write_multipart_chunked(&mut output, &boundary, &nodes) {
+        Err(Error::HeaderInjection(_)) => (),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    let part = Part {
+        headers: {
+            let mut h = Headers::new();
+            h.set_raw("X-Custom\r\nX-Injected", vec![b"1".to_vec()]);
+            h
+        },
+        body: b"Michael".to_vec(),
+    };
+    let nodes: Vec<Node> = vec![Node::Part(part)];
+    match write_multipart(&mut output, &boundary, &nodes) {
+        Err(Error::HeaderInjection(_)) => (),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn preserve_raw_headers() {
+    let input = b"--AaB03x\r\n\
+                  X-Tag: one\r\n\
+                  content-disposition: form-data; name=\"submit-name\"\r\n\
+                  X-Tag: two\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x\r\n\
+                  Content-Type: multipart/mixed; boundary=BbC04y\r\n\
+                  \r\n\
+                  --BbC04y\r\n\
+                  X-Inner: 1\r\n\
+                  \r\n\
+                  inner\r\n\
+                  --BbC04y--\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let (nodes, raw_headers) = read_multipart_body_with_raw_headers(
+        &mut &input[..], &headers, &ParseOptions::default()).unwrap();
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(raw_headers.len(), 3);
+    let raw = &raw_headers[0];
+    assert_eq!(raw.len(), 3);
+    assert_eq!(raw[0], ("X-Tag".to_owned(), b"one".to_vec()));
+    assert_eq!(raw[1].0, "content-disposition");
+    assert_eq!(raw[2], ("X-Tag".to_owned(), b"two".to_vec()));
+
+    // The nested multipart comes before the part within it
+    assert_eq!(raw_headers[1][0].0, "Content-Type");
+    assert_eq!(raw_headers[2], vec![("X-Inner".to_owned(), b"1".to_vec())]);
+}
+
+#[test]
+fn tee_writer() {
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Disposition", vec![b"form-data; name=\"field\"".to_vec()]);
+    let nodes = vec![Node::Part(Part::new(headers, b"value".to_vec()))];
+    let boundary = b"boundary";
+
+    let mut single: Vec<u8> = Vec::new();
+    let single_count = write_multipart(&mut single, boundary, &nodes).unwrap();
+
+    let mut tee = TeeWriter::new(Vec::new(), Vec::new());
+    let count = write_multipart(&mut tee, boundary, &nodes).unwrap();
+    assert_eq!(count, single_count);
+    assert_eq!(tee.bytes_written(), count as u64);
+    let (first, second) = tee.into_inner();
+    assert_eq!(first, single);
+    assert_eq!(second, single);
+
+    // Writing through a trait object also works
+    let mut out: Vec<u8> = Vec::new();
+    let dyn_out: &mut dyn Write = &mut out;
+    assert_eq!(write_multipart(dyn_out, boundary, &nodes).unwrap(), single_count);
+}
+
+#[test]
+fn parsed_form() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"avatar\"; filename=\"a.png\"\r\n\
+                  Content-Type: image/png\r\n\
+                  \r\n\
+                  PNG\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"docs\"\r\n\
+                  Content-Type: multipart/mixed; boundary=BbC04y\r\n\
+                  \r\n\
+                  --BbC04y\r\n\
+                  Content-Disposition: file; filename=\"file1.txt\"\r\n\
+                  \r\n\
+                  one\r\n\
+                  --BbC04y\r\n\
+                  Content-Disposition: file; filename=\"file2.txt\"\r\n\
+                  \r\n\
+                  two\r\n\
+                  --BbC04y--\r\n\
+                  --AaB03x--";
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let form = parse_form(&mut &input[..], &headers, &ParseOptions::default()).unwrap();
+    assert_eq!(form.fields.len(), 1);
+    assert_eq!(form.field("submit-name").unwrap().body, b"Larry");
+    assert_eq!(form.files.len(), 3);
+    assert_eq!(form.file("avatar").unwrap().filename().unwrap(), Some("a.png".to_owned()));
+    let docs: Vec<String> = form.files.iter()
+        .filter(|(name, _)| name == "docs")
+        .map(|(_, f)| f.filename().unwrap().unwrap())
+        .collect();
+    assert_eq!(docs, vec!["file1.txt".to_owned(), "file2.txt".to_owned()]);
+}
+
+#[test]
+fn default_filename_charset() {
+    let mut input: Vec<u8> = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename=\"caf".to_vec();
+    input.push(0xE9); // e-acute in ISO-8859-1
+    input.extend_from_slice(b".txt\"\r\n\
+                  \r\n\
+                  contents\r\n\
+                  --AaB03x--");
+
+    let options = ParseOptions {
+        always_use_files: true,
+        default_filename_charset: Charset::Iso_8859_1,
+        ..Default::default()
+    };
+    let nodes = read_multipart_with_options(&mut &input[..], &options).unwrap();
+    if let Node::File(ref filepart) = nodes[0] {
+        assert_eq!(filepart.filename().unwrap().unwrap(), "caf\u{e9}.txt");
+        // Not valid UTF-8
+        assert!(filepart.filename_with_default_charset(&Charset::Ext("UTF-8".to_owned()))
+                .is_err());
+    } else {
+        panic!("1st node of wrong type");
+    }
+
+    // A declared charset is honoured regardless of the default
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename*=UTF-8''caf%C3%A9.txt\r\n\
+                  \r\n\
+                  contents\r\n\
+                  --AaB03x--";
+    let nodes = read_multipart_with_options(&mut &input[..], &options).unwrap();
+    if let Node::File(ref filepart) = nodes[0] {
+        assert_eq!(filepart.filename().unwrap().unwrap(), "caf\u{e9}.txt");
+    } else {
+        panic!("1st node of wrong type");
+    }
+}
+
+// Returns its data with an `Interrupted` failure before each chunk, and then
+// (optionally) fails with `Interrupted` forever.
+struct StutteringReader {
+    data: Vec<u8>,
+    pos: usize,
+    stutter: bool,
+    interrupt_at_end: bool,
+}
+
+impl Read for StutteringReader {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        if self.pos == self.data.len() && self.interrupt_at_end {
+            return Err(::std::io::ErrorKind::Interrupted.into());
+        }
+        self.stutter = ! self.stutter;
+        if self.stutter {
+            return Err(::std::io::ErrorKind::Interrupted.into());
+        }
+        let n = ::std::cmp::min(buf.len(), self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[test]
+fn no_progress() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x--";
+
+    // Occasional interruptions are tolerated
+    let mut reader = StutteringReader {
+        data: input.to_vec(), pos: 0, stutter: false, interrupt_at_end: false,
+    };
+    let nodes = read_multipart(&mut reader, false).unwrap();
+    assert_eq!(nodes.len(), 1);
+
+    // Endless interruptions are reported rather than mistaken for the end of the stream
+    let truncated = &input[..input.len() - 12];
+    let mut reader = StutteringReader {
+        data: truncated.to_vec(), pos: 0, stutter: false, interrupt_at_end: true,
+    };
+    match read_multipart(&mut reader, false) {
+        Err(Error::NoProgress) => {},
+        other => panic!("expected NoProgress, got {:?}", other.map(|n| n.len())),
+    }
+}
+
+// Returns its data, then `Ok(0)` once, and panics if it is read again after that
+struct EndOnceReader {
+    data: Vec<u8>,
+    pos: usize,
+    ended: bool,
+}
+
+impl Read for EndOnceReader {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        assert!(! self.ended, "read again after the end of the stream");
+        let n = ::std::cmp::min(buf.len(), self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        self.ended = n == 0;
+        Ok(n)
+    }
+}
+
+#[test]
+fn end_of_stream_read_once() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"submit-name\"\r\n\
+                  \r\n\
+                  Larry\r\n\
+                  --AaB03x--";
+
+    let mut reader = EndOnceReader { data: input.to_vec(), pos: 0, ended: false };
+    assert_eq!(read_multipart(&mut reader, false).unwrap().len(), 1);
+
+    // A truncated body ends at the first `Ok(0)`
+    let truncated = &input[..input.len() - 12];
+    let mut reader = EndOnceReader { data: truncated.to_vec(), pos: 0, ended: false };
+    assert!(matches!(read_multipart(&mut reader, false), Err(Error::EofInPart)));
+}
+
+#[test]
+fn streaming_writer() {
+    let make_node = |i: usize| -> Result<Node, Error> {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Disposition",
+                        vec![format!("form-data; name=\"field{}\"", i).into_bytes()]);
+        Ok(Node::Part(Part::new(headers, format!("value{}", i).into_bytes())))
+    };
+    let boundary = b"boundary";
+
+    let nodes: Vec<Node> = (0..3).map(|i| make_node(i).unwrap()).collect();
+    let mut expected: Vec<u8> = Vec::new();
+    let expected_count = write_multipart(&mut expected, boundary, &nodes).unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    let count = write_multipart_streaming(&mut output, boundary, (0..3).map(make_node)).unwrap();
+    assert_eq!(count, expected_count);
+    assert_eq!(output, expected);
+
+    // The first error stops the output
+    let mut output: Vec<u8> = Vec::new();
+    let failing = (0..3).map(|i| if i == 1 { Err(Error::NoProgress) } else { make_node(i) });
+    match write_multipart_streaming(&mut output, boundary, failing) {
+        Err(Error::NoProgress) => {},
+        other => panic!("expected the iterator's error, got {:?}", other),
+    }
+    assert!(! String::from_utf8(output).unwrap().contains("field2"));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_part() {
+    let mut headers = Headers::new();
+    headers.set(ContentType(mime!(Application/Json)));
+    let part = Part::new(headers, br#"{"name": "Larry", "age": 42}"#.to_vec());
+    let value: ::std::collections::HashMap<String, serde_json::Value> = part.json().unwrap();
+    assert_eq!(value["name"], "Larry");
+    assert_eq!(value["age"], 42);
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"application/vnd.api+json".to_vec()]);
+    let part = Part::new(headers, b"[1, 2]".to_vec());
+    assert_eq!(part.json::<Vec<u32>>().unwrap(), vec![1, 2]);
+
+    let mut headers = Headers::new();
+    headers.set(ContentType(mime!(Text/Plain)));
+    let part = Part::new(headers, b"[1, 2]".to_vec());
+    assert!(matches!(part.json::<Vec<u32>>(), Err(Error::NotJson)));
+
+    let mut headers = Headers::new();
+    headers.set(ContentType(mime!(Application/Json)));
+    let part = Part::new(headers, b"[1, 2".to_vec());
+    assert!(matches!(part.json::<Vec<u32>>(), Err(Error::Json(_))));
+}
+
+#[test]
+fn many_part_headers() {
+    let mut input: Vec<u8> = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                               \r\n\
+                               --AaB03x\r\n\
+                               Content-Disposition: form-data; name=\"field\"\r\n".to_vec();
+    for i in 0..40 {
+        input.extend(format!("X-Header-{}: {}\r\n", i, i).into_bytes());
+    }
+    input.extend(b"\r\nvalue\r\n--AaB03x--".iter());
+
+    let nodes = read_multipart(&mut &input[..], false).unwrap();
+    if let Node::Part(ref part) = nodes[0] {
+        assert_eq!(part.headers.len(), 41);
+        assert_eq!(part.headers.get_raw("X-Header-39").unwrap()[0], b"39");
+        assert_eq!(part.body, b"value");
+    } else {
+        panic!("1st node of wrong type");
+    }
+}
+
+#[test]
+fn grouped_by_name() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"colour\"\r\n\
+                  \r\n\
+                  red\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"size\"\r\n\
+                  \r\n\
+                  large\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"colour\"\r\n\
+                  \r\n\
+                  blue\r\n\
+                  --AaB03x--";
+
+    let nodes = read_multipart(&mut &input[..], false).unwrap();
+    let groups = group_by_name(nodes);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups["size"].len(), 1);
+    let colours: Vec<&[u8]> = groups["colour"].iter().map(|node| match *node {
+        Node::Part(ref part) => &part.body[..],
+        _ => panic!("node of wrong type"),
+    }).collect();
+    assert_eq!(colours, vec![&b"red"[..], &b"blue"[..]]);
+}
+
+#[test]
+fn standalone_part() {
+    let input = b"Content-Type: text/plain\r\n\
+                  Content-Disposition: inline\r\n\
+                  \r\n\
+                  Hello\r\nWorld";
+    match read_part(&mut &input[..], false).unwrap() {
+        Node::Part(part) => {
+            assert_eq!(part.content_type(), Some(mime!(Text/Plain)));
+            assert_eq!(part.body, b"Hello\r\nWorld");
+        },
+        _ => panic!("node of wrong type"),
+    }
+
+    let input = b"Content-Disposition: attachment; filename=\"file.txt\"\r\n\
+                  \r\n\
+                  contents";
+    match read_part(&mut &input[..], false).unwrap() {
+        Node::File(filepart) => {
+            assert_eq!(filepart.filename().unwrap().unwrap(), "file.txt");
+            assert_eq!(filepart.size, Some(8));
+            assert_eq!(::std::fs::read(&filepart.path).unwrap(), b"contents");
+        },
+        _ => panic!("node of wrong type"),
+    }
+}
+
+#[test]
+fn max_part_bytes() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"field\"\r\n\
+                  \r\n\
+                  0123456789\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename=\"file.txt\"\r\n\
+                  \r\n\
+                  0123456789ABCDEF\r\n\
+                  --AaB03x--";
+
+    let mut options = ParseOptions {
+        max_part_bytes: Some(16),
+        ..Default::default()
+    };
+    assert_eq!(read_multipart_with_options(&mut &input[..], &options).unwrap().len(), 2);
+
+    options.max_part_bytes = Some(15);
+    match read_multipart_with_options(&mut &input[..], &options) {
+        Err(Error::SizeLimitExceeded) => {},
+        other => panic!("expected SizeLimitExceeded, got {:?}", other.map(|n| n.len())),
+    }
+
+    options.max_part_bytes = Some(9);
+    match read_multipart_with_options(&mut &input[..], &options) {
+        Err(Error::SizeLimitExceeded) => {},
+        other => panic!("expected SizeLimitExceeded, got {:?}", other.map(|n| n.len())),
+    }
+}
+
+#[test]
+fn part_file_conversion() {
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Disposition", vec![b"attachment; filename=\"file.txt\"".to_vec()]);
+    let part = Part::new(headers, b"contents".to_vec());
+
+    let filepart = part.clone().into_file().unwrap();
+    let path = filepart.path.clone();
+    assert_eq!(filepart.size, Some(8));
+    assert_eq!(filepart.filename().unwrap().unwrap(), "file.txt");
+    assert_eq!(::std::fs::read(&path).unwrap(), b"contents");
+
+    let back = filepart.into_part().unwrap();
+    assert_eq!(back, part);
+    assert!(! path.exists());
+}
+
+#[test]
+fn escaped_quotes_in_params() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"a \\\"b\\\"\"; filename=\"my \\\"file\\\"; \\\\.txt\"\r\n\
+                  \r\n\
+                  contents\r\n\
+                  --AaB03x--";
+
+    let nodes = read_multipart(&mut &input[..], false).unwrap();
+    if let Node::File(ref filepart) = nodes[0] {
+        assert_eq!(filepart.filename().unwrap().unwrap(), "my \"file\"; \\.txt");
+    } else {
+        panic!("1st node of wrong type");
+    }
+    let form = ParsedForm::from_nodes(nodes);
+    assert!(form.file("a \"b\"").is_some());
+}
+
+// Sets the cancel flag once `after` bytes have been read
+struct CancellingReader<'a> {
+    data: &'a [u8],
+    after: usize,
+    cancel: ::std::sync::Arc<::std::sync::atomic::AtomicBool>,
+}
+
+impl<'a> Read for CancellingReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        let n = ::std::cmp::min(::std::cmp::min(buf.len(), 8), self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        self.after = self.after.saturating_sub(n);
+        if self.after == 0 {
+            self.cancel.store(true, ::std::sync::atomic::Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+}
+
+#[test]
+fn cancellation() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename=\"file.txt\"\r\n\
+                  \r\n\
+                  0123456789012345678901234567890123456789\r\n\
+                  --AaB03x--";
+
+    let cancel = ::std::sync::Arc::new(::std::sync::atomic::AtomicBool::new(false));
+    let options = ParseOptions {
+        cancel: Some(cancel.clone()),
+        ..Default::default()
+    };
+    assert_eq!(read_multipart_with_options(&mut &input[..], &options).unwrap().len(), 1);
+
+    // Cancel in the middle of the file
+    let mut reader = CancellingReader { data: &input[..], after: 140, cancel: cancel.clone() };
+    match read_multipart_with_options(&mut reader, &options) {
+        Err(Error::Cancelled) => {},
+        other => panic!("expected Cancelled, got {:?}", other.map(|n| n.len())),
+    }
+}
+
+#[test]
+fn base64_on_write() {
+    let body: Vec<u8> = (0..100u8).collect();
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Transfer-Encoding", vec![b"base64".to_vec()]);
+    let nodes = vec![Node::Part(Part::new(headers, body))];
+
+    // Written verbatim unless asked for
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart(&mut output, b"b", &nodes).unwrap();
+    assert!(output.windows(3).any(|w| w == [0u8, 1, 2]));
+
+    let encoded = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8gISIjJCUmJygpKissLS4vMDEyMzQ1\
+                   Njc4OTo7PD0+P0BBQkNERUZHSElKS0xNTk9QUVJTVFVWV1hZWltcXV5fYGFiYw==";
+    let mut options = WriteOptions {
+        apply_transfer_encoding: true,
+        ..Default::default()
+    };
+    let mut output: Vec<u8> = Vec::new();
+    let count = write_multipart_with_options(&mut output, b"b", &nodes, &options).unwrap();
+    assert_eq!(count, output.len());
+    let expected = format!("--b\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n{}\r\n--b--",
+                           &encoded[..76], &encoded[76..]);
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+    // Without wrapping, and with LF line endings
+    options.base64_line_length = 0;
+    options.line_ending = LineEnding::Lf;
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart_with_options(&mut output, b"b", &nodes, &options).unwrap();
+    let expected = format!("--b\nContent-Transfer-Encoding: base64\n\n{}\n--b--", encoded);
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+    // Files are encoded the same way
+    options.base64_line_length = 20;
+    let part = match nodes[0] { Node::Part(ref part) => part.clone(), _ => unreachable!() };
+    let file_nodes = vec![Node::File(part.into_file().unwrap())];
+    let mut from_part: Vec<u8> = Vec::new();
+    write_multipart_with_options(&mut from_part, b"b", &nodes, &options).unwrap();
+    let mut from_file: Vec<u8> = Vec::new();
+    write_multipart_with_options(&mut from_file, b"b", &file_nodes, &options).unwrap();
+    assert_eq!(from_part, from_file);
+}
+
+#[test]
+fn content_disposition_accessors() {
+    let mut part = Part::new(Headers::new(), b"value".to_vec());
+    assert!(part.content_disposition().is_none());
+    part.set_content_disposition(ContentDisposition {
+        disposition: DispositionType::Ext("form-data".to_owned()),
+        parameters: vec![DispositionParam::Ext("name".to_owned(), "field".to_owned())],
+    });
+    assert_eq!(part.content_disposition().unwrap().disposition,
+               DispositionType::Ext("form-data".to_owned()));
+
+    let mut filepart = part.into_file().unwrap();
+    filepart.set_content_disposition(ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(
+            Charset::Ext("UTF-8".to_owned()), None, b"file.txt".to_vec())],
+    });
+    assert_eq!(filepart.content_disposition().unwrap().disposition, DispositionType::Attachment);
+    assert_eq!(filepart.filename().unwrap().unwrap(), "file.txt");
+}
+
+#[test]
+fn preamble_boundary_not_at_line_start() {
+    let input = b"Content-Type: multipart/mixed; boundary=AaB03x\r\n\
+                  \r\n\
+                  binary preamble x--AaB03x\r\nnot a part\r\n\
+                  --AaB03x\r\n\
+                  Content-Type: text/plain\r\n\
+                  \r\n\
+                  Hello\r\n\
+                  --AaB03x--";
+
+    let nodes = read_multipart(&mut &input[..], false).unwrap();
+    assert_eq!(nodes.len(), 1);
+    if let Node::Part(ref part) = nodes[0] {
+        assert_eq!(part.body, b"Hello");
+    } else {
+        panic!("1st node of wrong type");
+    }
+}
+
+#[test]
+fn best_effort() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"big\"\r\n\
+                  \r\n\
+                  0123456789ABCDEF\r\n\
+                  --AaB03x\r\n\
+                  Bad Header\r\n\
+                  \r\n\
+                  ignored\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"good\"\r\n\
+                  \r\n\
+                  fine\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let options = ParseOptions {
+        max_part_bytes: Some(8),
+        ..Default::default()
+    };
+
+    // A normal parse stops at the first error
+    assert!(read_multipart_body_with_options(&mut &input[..], &headers, &options).is_err());
+
+    let (nodes, result) = read_multipart_body_best_effort(&mut &input[..], &headers, &options);
+    assert_eq!(nodes.len(), 1);
+    if let Node::Part(ref part) = nodes[0] {
+        assert_eq!(part.body, b"fine");
+    } else {
+        panic!("1st node of wrong type");
+    }
+    match result {
+        Err(Error::Multiple(ref errors)) => {
+            assert_eq!(errors.len(), 2);
+            assert!(matches!(errors[0], Error::SizeLimitExceeded));
+            assert!(matches!(errors[1], Error::Httparse(_)));
+        },
+        ref other => panic!("expected Multiple, got {:?}", other),
+    }
+    assert!(format!("{}", result.unwrap_err()).starts_with("2 errors: "));
+}
+
+#[test]
+fn in_memory_file_part() {
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Disposition", vec![b"attachment; filename=\"blob.bin\"".to_vec()]);
+    let in_memory = vec![Node::File(FilePart::from_bytes(headers.clone(), b"blob".to_vec()))];
+    let on_disk = vec![Node::File(Part::new(headers, b"blob".to_vec()).into_file().unwrap())];
+
+    let mut expected: Vec<u8> = Vec::new();
+    write_multipart(&mut expected, b"b", &on_disk).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart(&mut output, b"b", &in_memory).unwrap();
+    assert_eq!(output, expected);
+
+    let mut expected: Vec<u8> = Vec::new();
+    write_multipart_chunked(&mut expected, b"b", &on_disk).unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart_chunked(&mut output, b"b", &in_memory).unwrap();
+    assert_eq!(output, expected);
+
+    if let Node::File(ref filepart) = in_memory[0] {
+        assert_eq!(filepart.clone().into_part().unwrap().body, b"blob");
+    }
+}
+
+#[test]
+fn read_until_predicate() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: attachment; name=\"skipped\"; filename=\"skip.txt\"\r\n\
+                  \r\n\
+                  not stored\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"wanted\"\r\n\
+                  \r\n\
+                  found\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"after\"\r\n\
+                  \r\n\
+                  not reached\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let is_named = |name: &'static str| move |headers: &Headers| {
+        form::disposition_name(headers).is_some_and(|n| n == name)
+    };
+
+    let node = read_until(&mut &input[..], &headers, is_named("wanted")).unwrap();
+    match node {
+        Some(Node::Part(ref part)) => assert_eq!(part.body, b"found"),
+        _ => panic!("wrong node"),
+    }
+
+    let node = read_until(&mut &input[..], &headers, is_named("missing")).unwrap();
+    assert!(node.is_none());
+}
+
+#[test]
+fn content_type_quoting() {
+    let write_part = |headers: Headers| -> String {
+        let mut output: Vec<u8> = Vec::new();
+        write_multipart(&mut output, b"b", &[Node::Part(Part::new(headers, vec![]))]).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    let mut headers = Headers::new();
+    headers.set(ContentType(Mime(TopLevel::Multipart, SubLevel::Ext("mixed".to_owned()),
+                                 vec![(Attr::Boundary, Value::Ext("a b:c".to_owned()))])));
+    assert!(write_part(headers).contains("Content-Type: multipart/mixed; boundary=\"a b:c\"\r\n"));
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type",
+                    vec![b"text/plain; charset=utf-8; name=\"my \\\"file\\\".txt\"".to_vec()]);
+    assert!(write_part(headers).contains(
+        "Content-Type: text/plain; charset=utf-8; name=\"my \\\"file\\\".txt\"\r\n"));
+}
+
+#[test]
+fn node_headers() {
+    let mut headers = Headers::new();
+    headers.set_raw("X-Which", vec![b"one".to_vec()]);
+    let nodes = vec![
+        Node::Part(Part::new(headers.clone(), vec![])),
+        Node::File(FilePart::from_bytes(headers.clone(), vec![])),
+        Node::Multipart((headers, vec![])),
+    ];
+    for node in &nodes {
+        assert_eq!(node.headers().get_raw("X-Which").unwrap()[0], b"one");
+    }
+}
+
+#[test]
+fn lf_header_blocks() {
+    let input = b"Content-Type: multipart/form-data; boundary=ABCDEFG\r\n\
+                  \r\n\
+                  --ABCDEFG\n\
+                  Content-Disposition: form-data; name=\"note\"\n\
+                  X-Mixed: crlf\r\n\
+                  \n\
+                  Check out this file about genomes!\n\
+                  --ABCDEFG\n\
+                  \n\
+                  no headers at all\n\
+                  --ABCDEFG--";
+
+    let nodes = read_multipart(&mut &input[..], false).unwrap();
+    assert_eq!(nodes.len(), 2);
+    if let Node::Part(ref part) = nodes[0] {
+        assert_eq!(part.headers.get_raw("X-Mixed").unwrap()[0], b"crlf");
+        assert_eq!(part.body, b"Check out this file about genomes!");
+    } else {
+        panic!("1st node of wrong type");
+    }
+    if let Node::Part(ref part) = nodes[1] {
+        assert_eq!(part.headers.len(), 0);
+        assert_eq!(part.body, b"no headers at all");
+    } else {
+        panic!("2nd node of wrong type");
+    }
+}
+
+#[test]
+fn boundary_lengths() {
+    assert_eq!(generate_boundary().unwrap().len(), 68);
+    for &(len, expected) in &[(8, 8), (20, 20), (70, 70), (100, 70)] {
+        let boundary = generate_boundary_with_len(len).unwrap();
+        assert_eq!(boundary.len(), expected);
+        assert!(boundary.iter().all(|&b| b.is_ascii_alphanumeric() || b"+-.".contains(&b)));
+    }
+    assert_ne!(generate_boundary_with_len(8).unwrap(), generate_boundary_with_len(8).unwrap());
+    assert!(matches!(generate_boundary_with_len(7), Err(Error::BoundaryTooShort)));
+}
+
+#[test]
+fn external_boundary() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"field\"\r\n\
+                  \r\n\
+                  value\r\n\
+                  --AaB03x--";
+
+    let nodes = read_multipart_body_with_boundary(&mut &input[..], b"AaB03x", false).unwrap();
+    assert_eq!(nodes.len(), 1);
+    if let Node::Part(ref part) = nodes[0] {
+        assert_eq!(part.body, b"value");
+    } else {
+        panic!("1st node of wrong type");
+    }
+    assert!(matches!(read_multipart_body_with_boundary(&mut &input[..], b"", false),
+                     Err(Error::BoundaryNotSpecified)));
+}
+
+#[test]
+fn part_as_buffer() {
+    let mut part = Part::new(Headers::new(), b"Hello".to_vec());
+    let name = "World";
+    write!(part, ", {}!", name).unwrap();
+    assert_eq!(part.body, b"Hello, World!");
+
+    let mut upper = Part::new(Headers::new(), Vec::new());
+    let mut contents = String::new();
+    part.body_reader().read_to_string(&mut contents).unwrap();
+    upper.write_all(contents.to_uppercase().as_bytes()).unwrap();
+    assert_eq!(upper.body, b"HELLO, WORLD!");
+}
+
+#[test]
+fn alternatives() {
+    let input = b"--AaB03x\r\n\
+                  \r\n\
+                  plain\r\n\
+                  --AaB03x\r\n\
+                  Content-Type: text/html\r\n\
+                  \r\n\
+                  <p>html</p>\r\n\
+                  --AaB03x\r\n\
+                  Content-Type: text/enriched\r\n\
+                  \r\n\
+                  enriched\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/alternative; boundary=AaB03x".to_vec()]);
+    let nodes = read_multipart_body(&mut &input[..], &headers, false).unwrap();
+    let multipart = Node::Multipart((headers, nodes));
+
+    let body = |node: Option<&Node>| match node {
+        Some(Node::Part(part)) => String::from_utf8(part.body.clone()).unwrap(),
+        _ => panic!("no part selected"),
+    };
+    assert_eq!(body(select_alternative(&multipart, &[mime!(Text/Html), mime!(Text/Plain)])),
+               "<p>html</p>");
+    assert_eq!(body(select_alternative(&multipart, &[mime!(Image/Png), mime!(Text/Plain)])),
+               "plain");
+    assert_eq!(body(select_alternative(&multipart, &[mime!(Text/Star)])), "enriched");
+    assert!(select_alternative(&multipart, &[mime!(Image/Png)]).is_none());
+}
+
+#[test]
+fn alternatives_sorted() {
+    let part = |ct: Option<Mime>, body: &str| {
+        let mut headers = Headers::new();
+        if let Some(ct) = ct {
+            headers.set(ContentType(ct));
+        }
+        Node::Part(Part::new(headers, body.as_bytes().to_vec()))
+    };
+    let mut nodes = vec![
+        part(Some(mime!(Image/Png)), "png"),
+        part(Some(mime!(Text/Html)), "html"),
+        part(Some(mime!(Text/Plain)), "plain"),
+        part(Some(Mime(TopLevel::Text, SubLevel::Ext("enriched".to_owned()), vec![])),
+             "enriched"),
+        part(None, "untyped"),
+    ];
+    sort_alternatives(&mut nodes);
+    let bodies: Vec<String> = nodes.iter().map(|node| match *node {
+        Node::Part(ref part) => String::from_utf8(part.body.clone()).unwrap(),
+        _ => unreachable!(),
+    }).collect();
+    assert_eq!(bodies, vec!["plain", "untyped", "enriched", "html", "png"]);
+}
+
+#[test]
+fn part_stats() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"field\"\r\n\
+                  \r\n\
+                  value\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename=\"file.txt\"\r\n\
+                  \r\n\
+                  file contents\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let (nodes, stats) = read_multipart_body_with_stats(
+        &mut &input[..], &headers, &ParseOptions::default()).unwrap();
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(stats, vec![
+        PartStats { header_bytes: 48, body_bytes: 5, to_disk: false },
+        PartStats { header_bytes: 56, body_bytes: 13, to_disk: true },
+    ]);
+}
+
+#[test]
+fn to_channel() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"field\"\r\n\
+                  \r\n\
+                  value\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                  \r\n\
+                  file contents\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    let consumer = ::std::thread::spawn(move || {
+        rx.iter().map(|node| match node {
+            Node::Part(part) => String::from_utf8(part.body).unwrap(),
+            Node::File(filepart) => {
+                let mut contents = String::new();
+                filepart.reader().unwrap().read_to_string(&mut contents).unwrap();
+                contents
+            },
+            Node::Multipart(_) => panic!("unexpected multipart"),
+        }).collect::<Vec<String>>()
+    });
+    read_multipart_to_channel(&mut &input[..], &headers, false, tx).unwrap();
+    assert_eq!(consumer.join().unwrap(), vec!["value", "file contents"]);
+
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    drop(rx);
+    assert!(matches!(read_multipart_to_channel(&mut &input[..], &headers, false, tx),
+                     Err(Error::Disconnected)));
+}
+
+#[test]
+fn detect_base64() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"multiline\"\r\n\
+                  \r\n\
+                  VGhlIHF1aWNrIGJyb3du\r\n\
+                  IGZveCBqdW1wcw==\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"padded\"\r\n\
+                  \r\n\
+                  aGk=\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"unpadded\"\r\n\
+                  \r\n\
+                  HelloWorld123456\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"ragged\"\r\n\
+                  \r\n\
+                  abcd\r\n\
+                  abcdefgh\r\n\
+                  abcd\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"labelled\"\r\n\
+                  Content-Transfer-Encoding: 8bit\r\n\
+                  \r\n\
+                  aGk=\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                  \r\n\
+                  aGVsbG8sIGZpbGU=\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let bodies = |options: &ParseOptions| -> Vec<String> {
+        read_multipart_body_with_options(&mut &input[..], &headers, options).unwrap()
+            .into_iter()
+            .map(|node| match node {
+                Node::Part(part) => String::from_utf8(part.body).unwrap(),
+                Node::File(filepart) => {
+                    let mut contents = String::new();
+                    filepart.reader().unwrap().read_to_string(&mut contents).unwrap();
+                    assert_eq!(filepart.size, Some(contents.len() as u64));
+                    contents
+                },
+                Node::Multipart(_) => panic!("unexpected multipart"),
+            })
+            .collect()
+    };
+
+    // Off by default
+    assert_eq!(bodies(&ParseOptions::default())[1], "aGk=");
+
+    let options = ParseOptions {
+        detect_base64: true,
+        ..Default::default()
+    };
+    assert_eq!(bodies(&options), vec![
+        "The quick brown fox jumps",
+        "hi",
+        "HelloWorld123456",
+        "abcd\r\nabcdefgh\r\nabcd",
+        "aGk=",
+        "hello, file",
+    ]);
+}
+
+#[test]
+fn max_filename_chars() {
+    // A combining acute accent after the 'e', which NFC composes into one character
+    let input = "Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                 \r\n\
+                 --AaB03x\r\n\
+                 Content-Disposition: attachment; filename=\"cafe\u{301}.txt\"\r\n\
+                 \r\n\
+                 contents\r\n\
+                 --AaB03x--";
+    let filename = |options: &ParseOptions| match read_multipart_with_options(
+        &mut input.as_bytes(), options).unwrap().remove(0)
+    {
+        Node::File(filepart) => filepart.filename(),
+        _ => panic!("1st node of wrong type"),
+    };
+
+    let mut options = ParseOptions {
+        max_filename_chars: Some(9),
+        ..Default::default()
+    };
+    assert_eq!(filename(&options).unwrap().unwrap(), "cafe\u{301}.txt");
+    options.max_filename_chars = Some(8);
+    assert!(matches!(filename(&options), Err(Error::InvalidFilename(_))));
+
+    #[cfg(feature = "unicode-normalization")]
+    {
+        options.normalize_filenames = true;
+        assert_eq!(filename(&options).unwrap().unwrap(), "caf\u{e9}.txt");
+        options.max_filename_chars = Some(7);
+        assert!(matches!(filename(&options), Err(Error::InvalidFilename(_))));
+    }
+}
+
+#[test]
+fn part_headers_only() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"field\"\r\n\
+                  \r\n\
+                  value\r\n\
+                  --AaB03x\r\n\
+                  Content-Type: multipart/mixed; boundary=BbC04y\r\n\
+                  \r\n\
+                  --BbC04y\r\n\
+                  Content-Type: text/plain\r\n\
+                  \r\n\
+                  nested\r\n\
+                  --BbC04y--\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                  \r\n\
+                  file contents\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let all_headers = read_part_headers(&mut &input[..], &headers).unwrap();
+    assert_eq!(all_headers.len(), 3);
+    assert_eq!(all_headers[0].get_raw("Content-Disposition").unwrap()[0],
+               b"form-data; name=\"field\"");
+    assert!(all_headers[1].get::<ContentType>().is_some());
+    assert_eq!(all_headers[2].get_raw("Content-Disposition").unwrap()[0],
+               b"form-data; name=\"file\"; filename=\"a.txt\"");
+}
+
+#[test]
+fn multipart_writer() {
+    let boundary = b"AaB03x";
+    let mut json_headers = Headers::new();
+    json_headers.set(ContentType(mime!(Application/Json)));
+    let mut base64_headers = Headers::new();
+    base64_headers.set_raw("Content-Transfer-Encoding", vec![b"base64".to_vec()]);
+
+    let mut output: Vec<u8> = Vec::new();
+    let count = {
+        let options = WriteOptions {
+            apply_transfer_encoding: true,
+            ..Default::default()
+        };
+        let mut writer = MultipartWriter::with_options(&mut output, boundary, options);
+        writer.write_node(&Node::Part(Part::new(Headers::new(), b"first".to_vec()))).unwrap();
+        writer.write_part_with(&json_headers, |out| {
+            write!(out, "{{\"n\": {}}}", 42)?;
+            Ok(9)
+        }).unwrap();
+        writer.write_part_with(&base64_headers, |out| {
+            out.write_all(b"hi")?;
+            Ok(2)
+        }).unwrap();
+        writer.finish().unwrap()
+    };
+    assert_eq!(count, output.len());
+    assert_eq!(output, &b"--AaB03x\r\n\
+                          \r\n\
+                          first\r\n\
+                          --AaB03x\r\n\
+                          Content-Type: application/json\r\n\
+                          \r\n\
+                          {\"n\": 42}\r\n\
+                          --AaB03x\r\n\
+                          Content-Transfer-Encoding: base64\r\n\
+                          \r\n\
+                          aGk=\r\n\
+                          --AaB03x--"[..]);
+}
+
+#[test]
+fn error_kind() {
+    let input = b"--AaB03x\r\n\
+                  \r\n\
+                  truncated";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let err = read_multipart_body(&mut &input[..], &headers, false).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Truncation);
+
+    assert_eq!(Error::SizeLimitExceeded.kind(), ErrorKind::Limit);
+    assert_eq!(Error::Multiple(vec![Error::EofInPart, Error::EofInFile]).kind(),
+               ErrorKind::Truncation);
+    assert_eq!(Error::Multiple(vec![Error::EofInPart, Error::SizeLimitExceeded]).kind(),
+               ErrorKind::Other);
+}
+
+#[test]
+fn spill_policy() {
+    let input = b"--AaB03x\r\n\
+                  \r\n\
+                  12345\r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  abcdefgh\r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  xyz\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+
+    let stored = |options: &ParseOptions| -> Vec<(bool, String)> {
+        read_multipart_body_with_options(&mut &input[..], &headers, options).unwrap()
+            .into_iter()
+            .map(|node| match node {
+                Node::Part(part) => (false, String::from_utf8(part.body).unwrap()),
+                Node::File(filepart) => {
+                    let mut contents = String::new();
+                    filepart.reader().unwrap().read_to_string(&mut contents).unwrap();
+                    assert_eq!(filepart.size, Some(contents.len() as u64));
+                    (true, contents)
+                },
+                Node::Multipart(_) => panic!("unexpected multipart"),
+            })
+            .collect()
+    };
+
+    let mut options = ParseOptions {
+        spill: Some(SpillPolicy { memory_budget: 10, max_part_in_memory: None }),
+        ..Default::default()
+    };
+    assert_eq!(stored(&options), vec![
+        (false, "12345".to_owned()),
+        (true, "abcdefgh".to_owned()),
+        (false, "xyz".to_owned()),
+    ]);
+
+    options.spill = Some(SpillPolicy { memory_budget: 10, max_part_in_memory: Some(2) });
+    assert_eq!(stored(&options), vec![
+        (true, "12345".to_owned()),
+        (true, "abcdefgh".to_owned()),
+        (true, "xyz".to_owned()),
+    ]);
+}
+
+#[test]
+fn concatenated_bodies() {
+    let input = b"--AaB03x\r\n\
+                  \r\n\
+                  first\r\n\
+                  --AaB03x--\r\n\
+                  an epilogue\r\n\
+                  -- \r\n\
+                  --BbC04y\r\n\
+                  \r\n\
+                  second\r\n\
+                  --BbC04y\r\n\
+                  \r\n\
+                  third\r\n\
+                  --BbC04y--\r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  fourth\r\n\
+                  --AaB03x--\r\n";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+
+    let bodies = read_multipart_bodies(&mut &input[..], &headers, &ParseOptions::default())
+        .unwrap();
+    let bodies: Vec<Vec<String>> = bodies.into_iter().map(|nodes| {
+        nodes.into_iter().map(|node| match node {
+            Node::Part(part) => String::from_utf8(part.body).unwrap(),
+            _ => panic!("unexpected node"),
+        }).collect()
+    }).collect();
+    assert_eq!(bodies, vec![vec!["first"], vec!["second", "third"], vec!["fourth"]]);
+}
+
+#[test]
+fn normalize_text_crlf() {
+    let mut text_headers = Headers::new();
+    text_headers.set(ContentType(mime!(Text/Plain)));
+    let mut binary_headers = Headers::new();
+    binary_headers.set(ContentType(mime!(Application/OctetStream)));
+    let nodes = vec![
+        Node::Part(Part::new(text_headers, b"one\ntwo\r\nthree\n".to_vec())),
+        Node::Part(Part::new(binary_headers, b"\n\r\n".to_vec())),
+    ];
+    let options = WriteOptions {
+        normalize_text_crlf: true,
+        ..Default::default()
+    };
+
+    let mut output: Vec<u8> = Vec::new();
+    let count = write_multipart_with_options(&mut output, b"AaB03x", &nodes, &options).unwrap();
+    assert_eq!(count, output.len());
+    assert_eq!(output, &b"--AaB03x\r\n\
+                          Content-Type: text/plain\r\n\
+                          \r\n\
+                          one\r\ntwo\r\nthree\r\n\r\n\
+                          --AaB03x\r\n\
+                          Content-Type: application/octet-stream\r\n\
+                          \r\n\
+                          \n\r\n\r\n\
+                          --AaB03x--"[..]);
+}
+
+#[test]
+fn top_level_headers() {
+    let input = b"Content-Type: multipart/mixed; boundary=\"AaB03x\"\r\n\
+                  X-Proxy: yes\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  body\r\n\
+                  --AaB03x--";
+    let (headers, nodes) = read_multipart_with_headers(&mut &input[..], &ParseOptions::default())
+        .unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(headers.get_raw("Content-Type").unwrap()[0],
+               b"multipart/mixed; boundary=\"AaB03x\"");
+    assert_eq!(headers.get_raw("X-Proxy").unwrap()[0], b"yes");
+}
+
+#[test]
+fn disposition_param_order() {
+    let dispositions: &[&[u8]] = &[
+        b"form-data; name=\"upload\"; filename=\"plain.txt\"; filename*=UTF-8''caf%C3%A9.txt",
+        b"form-data; filename*=UTF-8''caf%C3%A9.txt; filename=\"plain.txt\"; name=\"upload\"",
+        b"form-data; filename=\"plain.txt\"; x-unknown=1; name=\"upload\"; \
+          filename*=UTF-8''caf%C3%A9.txt",
+    ];
+    for &disposition in dispositions {
+        let mut input: Vec<u8> = b"--AaB03x\r\nContent-Disposition: ".to_vec();
+        input.extend_from_slice(disposition);
+        input.extend_from_slice(b"\r\n\r\ncontents\r\n--AaB03x--");
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+        let form = parse_form(&mut &input[..], &headers, &ParseOptions::default()).unwrap();
+        let filepart = form.file("upload").expect("no file named upload");
+        assert_eq!(filepart.filename().unwrap().unwrap(), "caf\u{e9}.txt");
+    }
+
+    // Without filename*, filename is used wherever it appears
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; filename=\"a.txt\"; x-unknown=1; name=\"f\"\r\n\
+                  \r\n\
+                  contents\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let form = parse_form(&mut &input[..], &headers, &ParseOptions::default()).unwrap();
+    assert_eq!(form.file("f").unwrap().filename().unwrap().unwrap(), "a.txt");
+}
+
+#[test]
+fn filepart_metadata_setters() {
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Disposition",
+                    vec![b"form-data; name=\"upload\"; filename=old.txt".to_vec()]);
+    let mut filepart = FilePart::from_bytes(headers, b"contents".to_vec());
+
+    filepart.set_content_type(mime!(Image/Png));
+    filepart.set_filename("caf\u{e9} menu.png");
+    assert_eq!(filepart.content_type(), Some(mime!(Image/Png)));
+    assert_eq!(filepart.headers.get_raw("Content-Disposition").unwrap()[0],
+               &b"form-data; name=upload; filename=\"caf_ menu.png\"; \
+                  filename*=UTF-8''caf%C3%A9%20menu.png"[..]);
+    assert_eq!(filepart.filename().unwrap().unwrap(), "caf\u{e9} menu.png");
+    assert_eq!(form::disposition_name(&filepart.headers).unwrap(), "upload");
+
+    // Round trip through writing and parsing
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart(&mut output, b"AaB03x", &[Node::File(filepart)]).unwrap();
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let form = parse_form(&mut &output[..], &headers, &ParseOptions::default()).unwrap();
+    let filepart = form.file("upload").unwrap();
+    assert_eq!(filepart.filename().unwrap().unwrap(), "caf\u{e9} menu.png");
+    assert_eq!(filepart.content_type(), Some(mime!(Image/Png)));
+
+    // Without a Content-Disposition, one is added
+    let mut filepart = FilePart::from_bytes(Headers::new(), Vec::new());
+    filepart.set_filename("a.txt");
+    assert_eq!(filepart.headers.get_raw("Content-Disposition").unwrap()[0],
+               b"attachment; filename=a.txt");
+}
+
+#[test]
+fn max_header_line_len() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"field\"\r\n\
+                  \r\n\
+                  value\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    // The header line is 44 bytes long
+    let mut options = ParseOptions {
+        max_header_line_len: Some(44),
+        ..Default::default()
+    };
+    assert!(read_multipart_body_with_options(&mut &input[..], &headers, &options).is_ok());
+    options.max_header_line_len = Some(43);
+    let err = read_multipart_body_with_options(&mut &input[..], &headers, &options).unwrap_err();
+    assert!(matches!(err, Error::HeaderLineTooLong));
+    assert_eq!(err.kind(), ErrorKind::Limit);
+}
+
+#[test]
+fn detect_boundary() {
+    let input = b"a preamble\r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  first\r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  second\r\n\
+                  --AaB03x--";
+    let headers = Headers::new();
+    assert!(matches!(read_multipart_body(&mut &input[..], &headers, false),
+                     Err(Error::NoRequestContentType)));
+
+    let options = ParseOptions {
+        detect_boundary: true,
+        ..Default::default()
+    };
+    let nodes = read_multipart_body_with_options(&mut &input[..], &headers, &options).unwrap();
+    assert_eq!(nodes.len(), 2);
+    if let Node::Part(ref part) = nodes[1] {
+        assert_eq!(part.body, b"second");
+    } else {
+        panic!("2nd node of wrong type");
+    }
+
+    // A boundary in the headers is still used if there is one
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=Other".to_vec()]);
+    assert!(matches!(read_multipart_body_with_options(&mut &input[..], &headers, &options),
+                     Err(Error::EofBeforeFirstBoundary)));
+}
+
+#[test]
+fn detect_boundary_in_variants() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"a\"\r\n\
+                  \r\n\
+                  first\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"b\"\r\n\
+                  \r\n\
+                  second\r\n\
+                  --AaB03x--";
+    let headers = Headers::new();
+    let options = ParseOptions {
+        detect_boundary: true,
+        ..Default::default()
+    };
+
+    let (nodes, stats) = read_multipart_body_with_stats(&mut &input[..], &headers, &options)
+        .unwrap();
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(stats.len(), 2);
+
+    let (nodes, result) = read_multipart_body_best_effort(&mut &input[..], &headers, &options);
+    assert!(result.is_ok());
+    assert_eq!(nodes.len(), 2);
+
+    let is_b = |h: &Headers| {
+        params::find_header_param(h, "Content-Disposition", "name") == Some(b"b".to_vec())
+    };
+    let found = read_until_with_options(&mut &input[..], &headers, is_b, &options).unwrap();
+    match found {
+        Some(Node::Part(ref part)) => assert_eq!(part.body, b"second"),
+        _ => panic!("part not found"),
+    }
+}
+
+#[test]
+fn boundary_scanner() {
+    let input = b"preamble\r\n\
+                  --AaB03x\r\n\
+                  Content-Type: text/plain\r\n\
+                  \r\n\
+                  first\r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  second\r\n\
+                  --AaB03x--\r\n\
+                  epilogue";
+    let scanner = BoundaryScanner::new(&input[..], b"AaB03x");
+    let parts: Vec<(Vec<u8>, bool)> = scanner.map(Result::unwrap).collect();
+    assert_eq!(parts, vec![
+        (b"Content-Type: text/plain\r\n\r\nfirst".to_vec(), false),
+        (b"\r\nsecond".to_vec(), true),
+    ]);
+
+    let mut scanner = BoundaryScanner::new(&input[..], b"AaB03x");
+    let mut sink: Vec<u8> = Vec::new();
+    assert_eq!(scanner.next_part(&mut sink).unwrap(), Some((33, false)));
+    assert_eq!(sink.len(), 33);
+    assert_eq!(scanner.next_part(&mut ::std::io::sink()).unwrap(), Some((8, true)));
+    assert_eq!(scanner.next_part(&mut ::std::io::sink()).unwrap(), None);
+
+    let mut scanner = BoundaryScanner::new(&b"--AaB03x\r\n\r\ntruncated"[..], b"AaB03x");
+    assert!(matches!(scanner.next(), Some(Err(Error::EofInPart))));
+    assert!(scanner.next().is_none());
+}
+
+#[test]
+fn max_total_file_bytes() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: attachment; filename=a.txt\r\n\
+                  \r\n\
+                  12345\r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  abcdefgh\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename=c.txt\r\n\
+                  \r\n\
+                  xyz\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+
+    // The part in memory does not count
+    let mut options = ParseOptions {
+        max_total_file_bytes: Some(8),
+        ..Default::default()
+    };
+    assert!(read_multipart_body_with_options(&mut &input[..], &headers, &options).is_ok());
+    options.max_total_file_bytes = Some(7);
+    assert!(matches!(read_multipart_body_with_options(&mut &input[..], &headers, &options),
+                     Err(Error::SizeLimitExceeded)));
+
+    // A part spilled to a file does count
+    options.spill = Some(SpillPolicy { memory_budget: 0, max_part_in_memory: None });
+    options.max_total_file_bytes = Some(16);
+    assert!(read_multipart_body_with_options(&mut &input[..], &headers, &options).is_ok());
+    options.max_total_file_bytes = Some(15);
+    let (nodes, result) = read_multipart_body_best_effort(&mut &input[..], &headers, &options);
+    assert!(matches!(result, Err(Error::SizeLimitExceeded)));
+    assert_eq!(nodes.len(), 2);
+    options.max_total_file_bytes = Some(12);
+    let (nodes, result) = read_multipart_body_best_effort(&mut &input[..], &headers, &options);
+    assert!(matches!(result, Err(Error::SizeLimitExceeded)));
+    assert_eq!(nodes.len(), 2);
+    if let Node::File(ref filepart) = nodes[1] {
+        assert_eq!(filepart.size, Some(3));
+    } else {
+        panic!("2nd node of wrong type");
+    }
+}
+
+#[test]
+fn max_total_file_bytes_buffered() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: attachment; filename=a.txt\r\n\
+                  \r\n\
+                  12345\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename=b.txt\r\n\
+                  \r\n\
+                  67890\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: attachment; filename=c.txt\r\n\
+                  \r\n\
+                  xyz\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+
+    // File parts held in memory count as files, as they are bound for disk
+    let mut options = ParseOptions {
+        buffer_files_up_to: Some(64),
+        max_total_file_bytes: Some(13),
+        ..Default::default()
+    };
+    let nodes = read_multipart_body_with_options(&mut &input[..], &headers, &options).unwrap();
+    assert!(nodes.iter().all(|node| matches!(*node, Node::File(ref f) if f.content().is_some())));
+    options.max_total_file_bytes = Some(12);
+    assert!(matches!(read_multipart_body_with_options(&mut &input[..], &headers, &options),
+                     Err(Error::SizeLimitExceeded)));
+    let (nodes, result) = read_multipart_body_best_effort(&mut &input[..], &headers, &options);
+    assert!(matches!(result, Err(Error::SizeLimitExceeded)));
+    assert_eq!(nodes.len(), 2);
+}
+
+#[test]
+fn semantically_eq() {
+    let mut a = Headers::new();
+    a.set(ContentType(mime!(Text/Plain)));
+    a.set_raw("X-First", vec![b"1".to_vec()]);
+    let mut b = Headers::new();
+    b.set_raw("x-first", vec![b"1".to_vec()]);
+    b.set(ContentType(mime!(Text/Plain)));
+
+    let part_a = Part::new(a.clone(), b"body".to_vec());
+    let part_b = Part::new(b.clone(), b"body".to_vec());
+    assert!(part_a.semantically_eq(&part_b));
+    assert!(! part_a.semantically_eq(&Part::new(b.clone(), b"other".to_vec())));
+    assert!(! part_a.semantically_eq(&Part::new(Headers::new(), b"body".to_vec())));
+
+    // One in memory and one in a file
+    let file_a = FilePart::from_bytes(a.clone(), b"contents".to_vec());
+    let file_b = Part::new(b.clone(), b"contents".to_vec()).into_file().unwrap();
+    assert!(file_a.semantically_eq(&file_b).unwrap());
+    let file_c = FilePart::from_bytes(b, b"contentz".to_vec());
+    assert!(! file_a.semantically_eq(&file_c).unwrap());
+}
+
+#[cfg(feature = "flate2")]
+#[test]
+fn gzip_body() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let input = b"Content-Type: multipart/mixed; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  compressed\r\n\
+                  --AaB03x--";
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(input).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let nodes = read_multipart_gzip(&mut &compressed[..], false).unwrap();
+    assert_eq!(nodes.len(), 1);
+    if let Node::Part(ref part) = nodes[0] {
+        assert_eq!(part.body, b"compressed");
+    } else {
+        panic!("1st node of wrong type");
+    }
+
+    // Not gzip
+    assert!(matches!(read_multipart_gzip(&mut &input[..], false), Err(Error::Io(_))));
+}
+
+#[test]
+fn not_multipart_fails_fast() {
+    let input = b"Content-Type: text/plain\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  body\r\n\
+                  --AaB03x--";
+    let options = ParseOptions {
+        detect_boundary: true,
+        ..ParseOptions::default()
+    };
+    match read_multipart_with_options(&mut &input[..], &options) {
+        Err(Error::NotMultipart) => {},
+        other => panic!("expected NotMultipart, got {:?}", other),
+    }
+}
+
+#[test]
+fn write_quoted_printable() {
+    let mut headers = Headers::new();
+    headers.set(ContentType(mime!(Text/Plain; Charset=Utf8)));
+    headers.set_raw("Content-Transfer-Encoding", vec![b"quoted-printable".to_vec()]);
+    let mut body: Vec<u8> = "café = ok \r\n".as_bytes().to_vec();
+    body.extend_from_slice(&[b'a'; 80]);
+    body.push(b' ');
+    let nodes = vec![Node::Part(Part::new(headers, body))];
+    let options = WriteOptions {
+        apply_transfer_encoding: true,
+        ..Default::default()
+    };
+
+    let mut output: Vec<u8> = Vec::new();
+    let count = write_multipart_with_options(&mut output, b"AaB03x", &nodes, &options).unwrap();
+    assert_eq!(count, output.len());
+    let mut expected: Vec<u8> = b"--AaB03x\r\n\
+                                  Content-Type: text/plain; charset=utf-8\r\n\
+                                  Content-Transfer-Encoding: quoted-printable\r\n\
+                                  \r\n\
+                                  caf=C3=A9 =3D ok=20\r\n".to_vec();
+    expected.extend_from_slice(&[b'a'; 75]);
+    expected.extend_from_slice(b"=\r\naaaaa=20\r\n--AaB03x--");
+    assert_eq!(String::from_utf8_lossy(&output), String::from_utf8_lossy(&expected));
+}
+
+#[test]
+fn disposition_policy() {
+    let mut named = Headers::new();
+    named.set_raw("Content-Disposition", vec![b"form-data; name=\"field\"".to_vec()]);
+    let mut inner = Headers::new();
+    inner.set(ContentType(mime!(Text/Plain)));
+    let mut mixed = Headers::new();
+    mixed.set_raw("Content-Type", vec![b"multipart/mixed; boundary=inner".to_vec()]);
+    mixed.set_raw("Content-Disposition", vec![b"form-data; name=\"files\"".to_vec()]);
+
+    // Parts of a nested multipart/mixed need no disposition
+    let nodes = vec![
+        Node::Part(Part::new(named.clone(), b"value".to_vec())),
+        Node::Multipart((mixed, vec![Node::Part(Part::new(inner.clone(), b"a".to_vec()))])),
+    ];
+    let mut output: Vec<u8> = Vec::new();
+    write_form_data(&mut output, b"AaB03x", &nodes).unwrap();
+
+    // But top-level form-data parts do
+    let nodes = vec![Node::Part(Part::new(inner.clone(), b"a".to_vec()))];
+    match write_form_data(&mut Vec::new(), b"AaB03x", &nodes) {
+        Err(Error::MissingDisposition) => {},
+        other => panic!("expected MissingDisposition, got {:?}", other),
+    }
+
+    // And generic multipart writing checks nothing
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart(&mut output, b"AaB03x", &nodes).unwrap();
+}
+
+#[test]
+fn anonymous_files() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+                  \r\n\
+                  contents\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"encoded\"\r\n\
+                  \r\n\
+                  aGVsbG8=\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let options = ParseOptions {
+        always_use_files: true,
+        detect_base64: true,
+        anonymous_files: true,
+        ..Default::default()
+    };
+    let nodes = read_multipart_body_with_options(&mut &input[..], &headers, &options).unwrap();
+    assert_eq!(nodes.len(), 2);
+    let (first, second) = match (&nodes[0], &nodes[1]) {
+        (Node::File(first), Node::File(second)) => (first, second),
+        _ => panic!("expected two files"),
+    };
+    assert!(first.is_anonymous());
+    assert_eq!(first.path, PathBuf::new());
+    assert_eq!(second.size, Some(5));
+
+    // The content can be read repeatedly, and written out
+    assert_eq!(first.clone().into_part().unwrap().body, b"contents");
+    assert_eq!(second.clone().into_part().unwrap().body, b"hello");
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart(&mut output, b"AaB03x", &nodes).unwrap();
+    let reparsed = read_multipart_body(&mut &output[..], &headers, true).unwrap();
+    match reparsed[0] {
+        Node::File(ref filepart) => assert!(filepart.semantically_eq(first).unwrap()),
+        _ => panic!("expected a file"),
+    }
+}
+
+#[test]
+fn content_length_framing() {
+    let input = b"--AaB03x\r\n\
+                  Content-Length: 17\r\n\
+                  \r\n\
+                  a\r\n--AaB03x\r\nb\r\nc\r\n\
+                  --AaB03x\r\n\
+                  \r\n\
+                  scanned\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+    let options = ParseOptions {
+        content_length_framing: true,
+        ..Default::default()
+    };
+    let nodes = read_multipart_body_with_options(&mut &input[..], &headers, &options).unwrap();
+    assert_eq!(nodes.len(), 2);
+    match (&nodes[0], &nodes[1]) {
+        (Node::Part(first), Node::Part(second)) => {
+            assert_eq!(first.body, b"a\r\n--AaB03x\r\nb\r\nc");
+            assert_eq!(second.body, b"scanned");
+        },
+        _ => panic!("expected two parts"),
+    }
+
+    // Without the option, the body is cut at the boundary within it, and the rest fails to
+    // parse as a part
+    assert!(read_multipart_body(&mut &input[..], &headers, false).is_err());
+
+    // A wrong length is an error rather than a truncated body
+    let input = b"--AaB03x\r\nContent-Length: 3\r\n\r\nabcd\r\n--AaB03x--";
+    match read_multipart_body_with_options(&mut &input[..], &headers, &options) {
+        Err(Error::ContentLengthMismatch) => {},
+        other => panic!("expected ContentLengthMismatch, got {:?}", other),
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn write_async() {
+    let mut text = Headers::new();
+    text.set(ContentType(mime!(Text/Plain)));
+    let mut nested = Headers::new();
+    nested.set_raw("Content-Type", vec![b"multipart/mixed; boundary=inner".to_vec()]);
+    let mut file = Headers::new();
+    file.set(ContentType(mime!(Application/OctetStream)));
+    let filepart = Part::new(file.clone(), b"file contents".to_vec()).into_file().unwrap();
+    let nodes = vec![
+        Node::Part(Part::new(text.clone(), b"hello".to_vec())),
+        Node::File(filepart),
+        Node::Multipart((nested, vec![Node::Part(Part::new(text, b"inner".to_vec()))])),
+    ];
+
+    let mut expected: Vec<u8> = Vec::new();
+    let expected_count = write_multipart(&mut expected, b"AaB03x", &nodes).unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    let count = runtime.block_on(write_multipart_async(&mut output, b"AaB03x", &nodes)).unwrap();
+    assert_eq!(count, expected_count);
+    assert_eq!(output, expected);
+
+    // A part may also be streamed from any AsyncRead
+    let mut output: Vec<u8> = Vec::new();
+    let count = runtime.block_on(async {
+        let mut writer = AsyncMultipartWriter::new(&mut output, b"AaB03x");
+        writer.write_part_from(&file, &mut &b"streamed"[..]).await?;
+        writer.finish().await
+    }).unwrap();
+    assert_eq!(count, output.len());
+    assert_eq!(output, &b"--AaB03x\r\n\
+                          Content-Type: application/octet-stream\r\n\
+                          \r\n\
+                          streamed\r\n\
+                          --AaB03x--"[..]);
+}
+
+#[test]
+fn validate_form_schema() {
+    let input = b"--AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"title\"\r\n\
+                  \r\n\
+                  Holiday\r\n\
+                  --AaB03x\r\n\
+                  Content-Disposition: form-data; name=\"photo\"; filename=\"a.png\"\r\n\
+                  Content-Type: image/png\r\n\
+                  \r\n\
+                  PNG\r\n\
+                  --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let nodes = read_multipart_body(&mut &input[..], &headers, false).unwrap();
+
+    let schema = FormSchema {
+        required_fields: vec!["title".to_owned()],
+        required_files: vec!["photo".to_owned()],
+        optional: vec!["caption".to_owned()],
+        content_types: vec![("photo".to_owned(), vec![mime!(Image/Star)])],
+        ..Default::default()
+    };
+    validate_form(&nodes, &schema).unwrap();
+
+    let check = |schema: FormSchema| validate_form(&nodes, &schema).unwrap_err().to_string();
+    assert_eq!(check(FormSchema { required_fields: vec!["caption".to_owned()], ..schema.clone() }),
+               "Unexpected form field \"title\"");
+    assert_eq!(check(FormSchema { optional: vec!["title".to_owned(), "photo".to_owned()],
+                                  required_fields: vec!["body".to_owned()],
+                                  ..schema.clone() }),
+               "Missing form field \"body\"");
+    assert_eq!(check(FormSchema { required_files: vec!["title".to_owned(), "photo".to_owned()],
+                                  ..schema.clone() }),
+               "Wrong type of form field \"title\"");
+    assert_eq!(check(FormSchema { content_types: vec![("photo".to_owned(), vec![mime!(Text/Star)])],
+                                  ..schema }),
+               "Wrong type of form field \"photo\"");
+}
+
+#[test]
+fn transport_padding() {
+    let input = b"--AaB03x \t\r\n\
+                  \r\n\
+                  first\r\n\
+                  --AaB03x  \r\n\
+                  \r\n\
+                  second\r\n\
+                  --AaB03x \t--  \r\n\
+                  epilogue";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+    let nodes = read_multipart_body(&mut &input[..], &headers, false).unwrap();
+    let bodies: Vec<&[u8]> = nodes.iter().map(|node| match *node {
+        Node::Part(ref part) => &part.body[..],
+        _ => panic!("expected a part"),
+    }).collect();
+    assert_eq!(bodies, vec![&b"first"[..], &b"second"[..]]);
+
+    let parts: Vec<(Vec<u8>, bool)> = BoundaryScanner::new(&input[..], b"AaB03x")
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(parts.len(), 2);
+    assert!(parts[1].1);
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn write_hashed() {
+    use sha2::{Digest, Sha256};
+
+    let mut headers = Headers::new();
+    headers.set(ContentType(mime!(Text/Plain)));
+    let nodes = vec![Node::Part(Part::new(headers, b"hello".to_vec()))];
+
+    let mut output: Vec<u8> = Vec::new();
+    let (count, digest) = write_multipart_hashed(&mut output, b"AaB03x", &nodes).unwrap();
+    assert_eq!(count, output.len());
+    assert_eq!(digest[..], Sha256::digest(&output)[..]);
+}
+
+#[test]
+fn content_language() {
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Language", vec![b"en-GB, mi (Maori)".to_vec(), b"fr".to_vec()]);
+    let part = Part::new(headers.clone(), Vec::new());
+    assert_eq!(part.content_language().unwrap(), vec!["en-GB", "mi", "fr"]);
+    let filepart = FilePart::from_bytes(headers, Vec::new());
+    assert_eq!(filepart.content_language().unwrap(), vec!["en-GB", "mi", "fr"]);
+    assert_eq!(Part::new(Headers::new(), Vec::new()).content_language(), None);
+}
+
+#[test]
+fn reuse_context() {
+    let input = b"Content-Type: multipart/mixed; boundary=AaB03x\r\n\
+                  \r\n\
+                  --AaB03x\r\n\
+                  X-Header: value\r\n\
+                  \r\n\
+                  body\r\n\
+                  --AaB03x--";
+    let mut context = MultipartContext::new();
+    for _ in 0..3 {
+        let nodes = read_multipart_with_context(&mut &input[..], &mut context, false).unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert!(context.buf.capacity() > 0);
+        assert!(context.buf.is_empty());
+    }
+}
+
+#[test]
+fn dot_stuff() {
+    let mut headers = Headers::new();
+    headers.set(ContentType(mime!(Text/Plain)));
+    let nodes = vec![Node::Part(Part::new(headers.clone(), b".hidden\r\nline\r\n.".to_vec()))];
+    let options = WriteOptions {
+        dot_stuff: true,
+        ..Default::default()
+    };
+
+    let mut output: Vec<u8> = Vec::new();
+    let count = write_multipart_with_options(&mut output, b"AaB03x", &nodes, &options).unwrap();
+    assert_eq!(count, output.len());
+    assert_eq!(output, &b"--AaB03x\r\n\
+                          Content-Type: text/plain\r\n\
+                          \r\n\
+                          ..hidden\r\nline\r\n..\r\n\
+                          --AaB03x--"[..]);
+
+    let mut streamed: Vec<u8> = Vec::new();
+    let mut writer = MultipartWriter::with_options(&mut streamed, b"AaB03x", options);
+    writer.write_part_with(&headers, |out| {
+        out.write_all(b".hidden\r\nline\r\n.")?;
+        Ok(17)
+    }).unwrap();
+    assert_eq!(writer.finish().unwrap(), output.len());
+    assert_eq!(streamed, output);
+}
+
+#[test]
+fn quoted_charset() {
+    let charset = |value: &[u8]| {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", vec![value.to_vec()]);
+        Part::new(headers, Vec::new()).charset()
+    };
+    assert_eq!(charset(b"text/plain; charset=\"utf-8\""), Some(Charset::Ext("UTF-8".to_owned())));
+    assert_eq!(charset(b"text/plain; charset=UTF-8"), Some(Charset::Ext("UTF-8".to_owned())));
+    assert_eq!(charset(b"text/plain; charset=\"ISO-8859-1\""), Some(Charset::Iso_8859_1));
+    assert_eq!(charset(b"text/plain; charset=\" iso-8859-1 \""), Some(Charset::Iso_8859_1));
+    assert_eq!(charset(b"text/plain"), None);
+}
+
+#[test]
+fn content_type_check() {
+    let part = |content_type: &str, body: &[u8]| {
+        let mut input: Vec<u8> = b"--AaB03x\r\nContent-Type: ".to_vec();
+        input.extend_from_slice(content_type.as_bytes());
+        input.extend_from_slice(b"\r\n\r\n");
+        input.extend_from_slice(body);
+        input.extend_from_slice(b"\r\n--AaB03x--");
+        input
+    };
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+    let options = ParseOptions {
+        content_type_check: Some(ContentTypeCheck::default()),
+        ..Default::default()
+    };
+    let parse = |input: &[u8], options: &ParseOptions| {
+        read_multipart_body_with_options(&mut &input[..], &headers, options)
+    };
+
+    // Matching, unrecognised, generic and allowed content passes
+    parse(&part("image/png", b"\x89PNG\r\n\x1a\nrest"), &options).unwrap();
+    parse(&part("text/plain", b"just text"), &options).unwrap();
+    parse(&part("application/octet-stream", b"%PDF-1.4"), &options).unwrap();
+    parse(&part("application/vnd.oasis.opendocument.text", b"PK\x03\x04"), &options).unwrap();
+
+    // Scripts disguised as images do not, whether in memory or in files
+    let disguised = part("image/png", b"  <!DOCTYPE html><script>alert(1)</script>");
+    match parse(&disguised, &options) {
+        Err(Error::ContentTypeMismatch(declared, sniffed)) => {
+            assert_eq!(declared, "image/png");
+            assert_eq!(sniffed, "text/html");
+        },
+        other => panic!("expected ContentTypeMismatch, got {:?}", other),
+    }
+    let files = ParseOptions { always_use_files: true, ..options.clone() };
+    let svg = part("image/png", b"<?xml version=\"1.0\"?><svg onload=\"alert(1)\"/>");
+    assert!(matches!(parse(&svg, &files), Err(Error::ContentTypeMismatch(..))));
+
+    // Nothing is checked by default
+    parse(&disguised, &ParseOptions::default()).unwrap();
+}
+
+#[test]
+fn iterate_files_and_parts() {
+    let mut nested = Headers::new();
+    nested.set_raw("Content-Type", vec![b"multipart/mixed; boundary=inner".to_vec()]);
+    let file = |content: &[u8]| Node::File(FilePart::from_bytes(Headers::new(), content.to_vec()));
+    let part = |body: &[u8]| Node::Part(Part::new(Headers::new(), body.to_vec()));
+    let nodes = vec![
+        part(b"one"),
+        file(b"a"),
+        Node::Multipart((nested.clone(), vec![
+            file(b"b"),
+            Node::Multipart((nested, vec![part(b"two"), file(b"c")])),
+        ])),
+        file(b"d"),
+    ];
+    let contents: Vec<Vec<u8>> = files(&nodes)
+        .map(|filepart| filepart.clone().into_part().unwrap().body)
+        .collect();
+    assert_eq!(contents, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+    let bodies: Vec<&[u8]> = parts(&nodes).map(|part| &part.body[..]).collect();
+    assert_eq!(bodies, vec![&b"one"[..], &b"two"[..]]);
+    assert_eq!(files(&[]).count(), 0);
+}
+
+#[test]
+fn strict_form_data() {
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let parse = |part_headers: &[u8]| {
+        let mut input: Vec<u8> = b"--AaB03x\r\n".to_vec();
+        input.extend_from_slice(part_headers);
+        input.extend_from_slice(b"\r\n\r\nvalue\r\n--AaB03x--");
+        parse_form_data_strict(&mut &input[..], &headers, &ParseOptions::default())
+    };
+
+    let form = parse(b"Content-Disposition: Form-Data; name=\"field\"").unwrap();
+    assert_eq!(form.field("field").unwrap().body, b"value");
+    assert!(matches!(parse(b"Content-Disposition: form-data"), Err(Error::MissingName)));
+    assert!(matches!(parse(b"Content-Disposition: attachment; name=\"field\""),
+                     Err(Error::MissingName)));
+    assert!(matches!(parse(b"Content-Type: text/plain"), Err(Error::MissingName)));
+    match parse(b"Content-Disposition: form-data; name=\"field\"\r\n\
+                  Content-Transfer-Encoding: base64") {
+        Err(Error::UnexpectedTransferEncoding(name)) => assert_eq!(name, "field"),
+        other => panic!("expected UnexpectedTransferEncoding, got {:?}", other),
+    }
+}
+
+#[test]
+fn take_path() {
+    let filepart = Part::new(Headers::new(), b"kept".to_vec()).into_file().unwrap();
+    let path = filepart.take_path().unwrap();
+    assert_eq!(::std::fs::read(&path).unwrap(), b"kept");
+    ::std::fs::remove_file(&path).unwrap();
+    ::std::fs::remove_dir(path.parent().unwrap()).unwrap();
+
+    // Content in memory or in an anonymous file has no path to give
+    assert_eq!(FilePart::from_bytes(Headers::new(), b"kept".to_vec()).take_path(), None);
+    assert_eq!(FilePart::create_anonymous(Headers::new()).unwrap().take_path(), None);
+}
+
+#[test]
+fn file_size_counts_written_bytes() {
+    // Larger than the read buffer, so the body arrives in several writes
+    let contents = vec![b'x'; 100_000];
+    let mut input: Vec<u8> = b"--AaB03x\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"big.txt\"\r\n\r\n".to_vec();
+    input.extend_from_slice(&contents);
+    input.extend_from_slice(b"\r\n--AaB03x--");
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let nodes = read_multipart_body(&mut &input[..], &headers, false).unwrap();
+    match nodes[0] {
+        Node::File(ref filepart) => {
+            let on_disk = ::std::fs::metadata(&filepart.path).unwrap().len();
+            assert_eq!(filepart.size, Some(on_disk));
+            assert_eq!(filepart.size, Some(contents.len() as u64));
+        },
+        ref other => panic!("expected a file, got {:?}", other),
+    }
+}
+
+#[test]
+fn write_single_parts() {
+    let nodes = vec![
+        Node::Part(Part::new(Headers::new(), b"one".to_vec())),
+        Node::Part(Part::new(Headers::new(), b"two".to_vec())),
+    ];
+    let mut expected: Vec<u8> = Vec::new();
+    write_multipart(&mut expected, b"AaB03x", &nodes).unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut count = 0;
+    for node in &nodes {
+        count += write_single_part(&mut output, b"AaB03x", node).unwrap();
+    }
+    assert_eq!(output, b"--AaB03x\r\n\r\none\r\n--AaB03x\r\n\r\ntwo\r\n");
+    count += write_final_boundary(&mut output, b"AaB03x").unwrap();
+    assert_eq!(output, expected);
+    assert_eq!(count, expected.len());
+}
+
+#[test]
+fn content_type_params_round_trip() {
+    let input = b"--AaB03x\r\n\
+        Content-Disposition: form-data; name=\"pic\"\r\n\
+        Content-Type: image/png; name=\"my pic.png\"; type=thumbnail; x-scale=2\r\n\r\n\
+        data\r\n--AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let mut nodes = read_multipart_body(&mut &input[..], &headers, false).unwrap();
+
+    let mime = match nodes[0] {
+        Node::Part(ref part) => part.content_type().unwrap(),
+        ref other => panic!("expected a part, got {:?}", other),
+    };
+    let params: Vec<String> = mime.2.iter().map(|(a, v)| format!("{}={}", a, v)).collect();
+    assert_eq!(params, vec!["name=my pic.png", "type=thumbnail", "x-scale=2"]);
+
+    let expected = "Content-Type: image/png; name=\"my pic.png\"; type=thumbnail; x-scale=2\r\n";
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart(&mut output, b"AaB03x", &nodes).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains(expected));
+
+    // Setting the parsed type back keeps the parameters, quoted where necessary
+    if let Node::Part(ref mut part) = nodes[0] {
+        part.headers.set(ContentType(mime));
+    }
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart(&mut output, b"AaB03x", &nodes).unwrap();
+    assert!(String::from_utf8(output).unwrap().contains(expected));
+}
+
+#[test]
+fn buffer_small_files() {
+    let input = b"--AaB03x\r\n\
+        Content-Disposition: form-data; name=\"small\"; filename=\"small.txt\"\r\n\r\n\
+        tiny\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"large\"; filename=\"large.txt\"\r\n\r\n\
+        rather larger\r\n--AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let options = ParseOptions { buffer_files_up_to: Some(8), ..ParseOptions::default() };
+    let mut nodes = read_multipart_body_with_options(&mut &input[..], &headers, &options)
+        .unwrap();
+    assert_eq!(nodes.len(), 2);
+
+    match nodes[1] {
+        Node::File(ref filepart) => {
+            assert_eq!(filepart.content(), None);
+            assert_eq!(::std::fs::read(&filepart.path).unwrap(), b"rather larger");
+        },
+        ref other => panic!("expected a file, got {:?}", other),
+    }
+    match nodes[0] {
+        Node::File(ref mut filepart) => {
+            assert_eq!(filepart.content(), Some(&b"tiny"[..]));
+            assert_eq!(filepart.path, PathBuf::new());
+            assert_eq!(filepart.filename().unwrap(), Some("small.txt".to_owned()));
+            filepart.commit_to_disk().unwrap();
+            assert_eq!(filepart.content(), None);
+            assert_eq!(::std::fs::read(&filepart.path).unwrap(), b"tiny");
+        },
+        ref other => panic!("expected a file, got {:?}", other),
+    }
+    let path = match nodes[0] {
+        Node::File(ref filepart) => filepart.path.clone(),
+        _ => unreachable!(),
+    };
+    drop(nodes);
+    assert!(! path.exists());
+}
+
+#[test]
+fn parse_budget() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+        one\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+        two\r\n--AaB03x--";
+    let parse = |budget: ParseBudget| read_multipart_with_budget(&mut &input[..], false, budget);
+
+    assert_eq!(parse(ParseBudget::default()).unwrap().len(), 2);
+    let exact = ParseBudget { max_bytes: Some(input.len() as u64), max_parts: Some(2),
+                              ..ParseBudget::default() };
+    assert_eq!(parse(exact).unwrap().len(), 2);
+
+    let bytes = ParseBudget { max_bytes: Some(input.len() as u64 - 1), ..ParseBudget::default() };
+    assert!(matches!(parse(bytes), Err(Error::BodyTooLarge)));
+    let parts = ParseBudget { max_parts: Some(1), ..ParseBudget::default() };
+    assert!(matches!(parse(parts), Err(Error::TooManyParts)));
+    let duration = ParseBudget { max_duration: Some(::std::time::Duration::from_secs(0)),
+                                 ..ParseBudget::default() };
+    assert!(matches!(parse(duration), Err(Error::TimedOut)));
+    assert_eq!(Error::TimedOut.kind(), ErrorKind::Limit);
+}
+
+#[test]
+fn boundary_within_file_content() {
+    // The file content contains the final boundary, after a line terminator
+    let input = b"--AaB03x\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"data.bin\"\r\n\
+        Content-Length: 20\r\n\r\n\
+        abc\r\n--AaB03x--\r\nxyz\r\n\
+        --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let content = |nodes: &[Node]| match nodes[0] {
+        Node::File(ref filepart) => ::std::fs::read(&filepart.path).unwrap(),
+        ref other => panic!("expected a file, got {:?}", other),
+    };
+
+    // Scanning for the boundary silently truncates the file
+    let nodes = read_multipart_body(&mut &input[..], &headers, false).unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(content(&nodes), b"abc");
+
+    // Reading by Content-Length keeps it whole
+    let options = ParseOptions { content_length_framing: true, ..ParseOptions::default() };
+    let nodes = read_multipart_body_with_options(&mut &input[..], &headers, &options).unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(content(&nodes), b"abc\r\n--AaB03x--\r\nxyz");
+}
+
+#[test]
+fn read_borrowed_slices() {
+    let input = b"preamble\r\n--AaB03x\r\n\
+        Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+        one\r\n\
+        --AaB03x\r\n\
+        Content-Type: multipart/mixed; boundary=BbC04y\r\n\r\n\
+        --BbC04y\r\n\r\nnested\r\n--BbC04y--\r\n\
+        --AaB03x--\r\nepilogue";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let parts = read_multipart_slice(&input[..], &headers).unwrap();
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].body, b"one");
+    assert!(! parts[0].is_multipart());
+    let range = input.as_ptr_range();
+    assert!(range.contains(&parts[0].body.as_ptr()));
+
+    assert!(parts[1].is_multipart());
+    let nested = read_multipart_slice(parts[1].body, &parts[1].headers).unwrap();
+    assert_eq!(nested.len(), 1);
+    assert_eq!(nested[0].body, b"nested");
+
+    let cut = input.windows(3).position(|w| w == b"one").unwrap() + 2;
+    assert!(matches!(read_multipart_slice(&input[..cut], &headers), Err(Error::EofInPart)));
+}
+
+#[test]
+fn reexported_types() {
+    let mime = crate::mime::Mime(crate::TopLevel::Text, crate::SubLevel::Plain, vec![]);
+    let mut headers = crate::hyper::header::Headers::new();
+    headers.set(crate::ContentType(mime.clone()));
+    headers.set(crate::ContentDisposition {
+        disposition: crate::DispositionType::Inline,
+        parameters: vec![],
+    });
+    let part = Part::new(headers, b"text".to_vec());
+    assert_eq!(part.content_type(), Some(mime));
+}
+
+#[test]
+fn consume_files_once() {
+    let filepart = Part::new(Headers::new(), b"once".to_vec()).into_file().unwrap();
+    let anonymous = FilePart::create_anonymous(Headers::new()).unwrap();
+    let in_memory = FilePart::from_bytes(Headers::new(), b"again".to_vec());
+    let options = WriteOptions { consume_once: true, ..WriteOptions::default() };
+    let write = |nodes: &[Node]| {
+        let mut output: Vec<u8> = Vec::new();
+        let result = write_multipart_with_options(&mut output, b"AaB03x", nodes, &options);
+        (result, output)
+    };
+
+    let nodes = vec![Node::File(filepart.clone()), Node::File(anonymous.clone()),
+                     Node::File(in_memory.clone()), Node::File(in_memory)];
+    assert!(write(&nodes).0.is_ok());
+
+    let mut nested = Headers::new();
+    nested.set_raw("Content-Type", vec![b"multipart/mixed; boundary=BbC04y".to_vec()]);
+    let nodes = vec![Node::File(filepart.clone()),
+                     Node::Multipart((nested, vec![Node::File(filepart.clone())]))];
+    let (result, output) = write(&nodes);
+    assert!(matches!(result, Err(Error::SourceReused)));
+    assert!(output.is_empty());
+    let nodes = vec![Node::File(anonymous.clone()), Node::File(anonymous.clone())];
+    assert!(matches!(write(&nodes).0, Err(Error::SourceReused)));
+
+    // Without the option, the file is simply read twice
+    let mut output: Vec<u8> = Vec::new();
+    write_multipart(&mut output, b"AaB03x", &[Node::File(filepart.clone()),
+                                              Node::File(filepart.clone())]).unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut writer = MultipartWriter::with_options(&mut output, b"AaB03x", options.clone());
+    writer.write_node(&Node::File(anonymous.clone())).unwrap();
+    assert!(matches!(writer.write_node(&Node::File(anonymous)), Err(Error::SourceReused)));
+}
+
+#[test]
+fn rfc2231_continuations() {
+    let filepart = |disposition: &[u8]| {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Disposition", vec![disposition.to_vec()]);
+        FilePart::new(headers, Path::new("/dev/null"))
+    };
+
+    let encoded = filepart(b"attachment; filename*0*=UTF-8''long%20; filename*1=\"name part\"; \
+                             filename*2*=%C3%A9.txt");
+    assert_eq!(encoded.filename().unwrap(), Some("long name part\u{e9}.txt".to_owned()));
+
+    // Sections may come out of order, and are unencoded unless marked
+    let plain = filepart(b"form-data; filename*1=\"part.txt\"; name*0=fi; \
+                           filename*0=\"a long file\"; name*1=eld");
+    assert_eq!(plain.filename().unwrap(), Some("a long filepart.txt".to_owned()));
+    assert_eq!(form::disposition_name(&plain.headers), Some("field".to_owned()));
+
+    let name = filepart(b"form-data; name*0*=UTF-8''caf%C3%A9; name*1=s");
+    assert_eq!(form::disposition_name(&name.headers), Some("caf\u{e9}s".to_owned()));
+
+    // An escape must be two hex digits, without a sign; others are left as they are
+    let signed = filepart(b"attachment; filename*0=\"a\"; filename*1*=%+Fb; filename*2*=%4");
+    assert_eq!(signed.filename().unwrap(), Some("a%+Fb%4".to_owned()));
+}
+
+#[test]
+fn file_callback() {
+    let input = b"--AaB03x\r\n\
+        Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+        value\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"a\"; filename=\"a.txt\"\r\n\r\n\
+        first\r\n\
+        --AaB03x\r\n\
+        Content-Type: multipart/mixed; boundary=BbC04y\r\n\r\n\
+        --BbC04y\r\n\
+        Content-Disposition: attachment; filename=\"b.txt\"\r\n\r\n\
+        second\r\n\
+        --BbC04y--\r\n\
+        --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let options = ParseOptions::default();
+
+    let mut seen: Vec<(String, Vec<u8>)> = Vec::new();
+    let nodes = read_multipart_body_with_file_callback(&mut &input[..], &headers, &options,
+        |filepart| {
+            let mut content = Vec::new();
+            filepart.reader()?.read_to_end(&mut content)?;
+            seen.push((filepart.filename()?.unwrap(), content));
+            Ok(())
+        }).unwrap();
+    assert_eq!(nodes.len(), 3);
+    assert_eq!(seen, vec![("a.txt".to_owned(), b"first".to_vec()),
+                          ("b.txt".to_owned(), b"second".to_vec())]);
+
+    let result = read_multipart_body_with_file_callback(&mut &input[..], &headers, &options,
+        |_| Err(Error::Cancelled));
+    assert!(matches!(result, Err(Error::Cancelled)));
+}
+
+#[test]
+fn body_text_byte_order_marks() {
+    let part = |content_type: Option<&[u8]>, body: &[u8]| {
+        let mut headers = Headers::new();
+        if let Some(content_type) = content_type {
+            headers.set_raw("Content-Type", vec![content_type.to_vec()]);
+        }
+        Part::new(headers, body.to_vec())
+    };
+
+    assert_eq!(part(None, b"plain").body_text().unwrap(), "plain");
+    assert_eq!(part(None, b"\xef\xbb\xbfcaf\xc3\xa9").body_text().unwrap(), "caf\u{e9}");
+    assert_eq!(part(Some(b"text/plain; charset=iso-8859-1"), b"caf\xe9").body_text().unwrap(),
+               "caf\u{e9}");
+
+    // UTF-16 byte order marks override the declared charset
+    assert_eq!(part(Some(b"text/plain; charset=utf-8"), b"\xff\xfeh\0i\0").body_text().unwrap(),
+               "hi");
+    assert_eq!(part(None, b"\xfe\xff\0h\0i").body_text().unwrap(), "hi");
+
+    assert!(matches!(part(None, b"\xff").body_text(), Err(Error::Decoding(_))));
+}
+
+#[test]
+fn files_named_from_nested_mixed() {
+    let input = b"--AaB03x\r\n\
+        Content-Disposition: form-data; name=\"submit-name\"\r\n\r\n\
+        Larry\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"files\"\r\n\
+        Content-Type: multipart/mixed; boundary=BbC04y\r\n\r\n\
+        --BbC04y\r\n\
+        Content-Disposition: file; filename=\"file1.txt\"\r\n\r\n\
+        ... contents of file1.txt ...\r\n\
+        --BbC04y\r\n\
+        Content-Disposition: file; filename=\"awesome_image.gif\"\r\n\
+        Content-Type: image/gif\r\n\r\n\
+        ... contents of awesome_image.gif ...\r\n\
+        --BbC04y--\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"avatar\"; filename=\"me.png\"\r\n\r\n\
+        png\r\n\
+        --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let form = parse_form(&mut &input[..], &headers, &ParseOptions::default()).unwrap();
+
+    let filenames: Vec<String> = form.files_named("files")
+        .map(|filepart| filepart.filename().unwrap().unwrap())
+        .collect();
+    assert_eq!(filenames, vec!["file1.txt", "awesome_image.gif"]);
+    assert_eq!(form.files_named("avatar").count(), 1);
+    assert_eq!(form.files_named("submit-name").count(), 0);
+}
+
+#[test]
+fn nodes_outlive_borrowed_reader() {
+    // The input and the reader borrowing it live only within this function, while the
+    // nodes are returned from it
+    fn parse(always_use_files: bool) -> Vec<Node> {
+        let input: Vec<u8> = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\r\n\
+            --AaB03x\r\n\
+            Content-Disposition: form-data; name=\"field\"\r\n\r\n\
+            value\r\n\
+            --AaB03x--".to_vec();
+        let mut reader: &[u8] = &input;
+        let mut borrowing = (&mut reader).take(input.len() as u64);
+        read_multipart(&mut borrowing, always_use_files).unwrap()
+    }
+
+    match parse(false)[0] {
+        Node::Part(ref part) => assert_eq!(part.body, b"value"),
+        ref other => panic!("expected a part, got {:?}", other),
+    }
+    match parse(true)[0] {
+        Node::File(ref filepart) => assert_eq!(::std::fs::read(&filepart.path).unwrap(), b"value"),
+        ref other => panic!("expected a file, got {:?}", other),
+    }
+}
+
+#[test]
+fn invalid_content_type_policy() {
+    let input = b"--AaB03x\r\n\
+        Content-Type: not a type\r\n\r\n\
+        value\r\n\
+        --AaB03x\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        fine\r\n--AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+    let parse = |policy: InvalidContentType| {
+        let options = ParseOptions { invalid_content_type: policy, ..ParseOptions::default() };
+        read_multipart_body_with_options(&mut &input[..], &headers, &options)
+    };
+    let content_type = |node: &Node| match *node {
+        Node::Part(ref part) => part.content_type(),
+        ref other => panic!("expected a part, got {:?}", other),
+    };
+
+    let nodes = parse(InvalidContentType::Keep).unwrap();
+    assert_eq!(content_type(&nodes[0]), None);
+    assert_eq!(nodes[0].headers().get_raw("Content-Type").unwrap()[0], b"not a type");
+
+    let nodes = parse(InvalidContentType::OctetStream).unwrap();
+    assert_eq!(content_type(&nodes[0]).unwrap().to_string(), "application/octet-stream");
+    assert_eq!(content_type(&nodes[1]).unwrap().to_string(), "text/plain");
+
+    match parse(InvalidContentType::Reject) {
+        Err(Error::InvalidContentType(value)) => assert_eq!(value, "not a type"),
+        other => panic!("expected InvalidContentType, got {:?}", other),
+    }
+}
+
+#[test]
+fn rewrite_preserves_structure() {
+    let input: &[u8] = b"Content-Type: multipart/mixed; boundary=AaB03x\r\n\
+                         \r\n\
+                         --AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"field\"\r\n\
+                         \r\n\
+                         value\r\n\
+                         --AaB03x\r\n\
+                         Content-Type: multipart/mixed; boundary=BbC04y\r\n\
+                         \r\n\
+                         --BbC04y\r\n\
+                         Content-Type: text/plain\r\n\
+                         \r\n\
+                         inner\r\n\
+                         --BbC04y--\r\n\
+                         --AaB03x--";
+    let (headers, nodes) = read_multipart_with_headers(&mut &input[..], &ParseOptions::default())
+        .unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    let count = rewrite_multipart(&mut output, &nodes, &headers).unwrap();
+    assert_eq!(count, output.len());
+    assert_eq!(String::from_utf8_lossy(&output), String::from_utf8_lossy(input));
+}
+
+#[test]
+fn boundary_collision_warnings() {
+    let input: &[u8] = b"--AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"first\"\r\n\
+                         \r\n\
+                         quoted --AaB03x within\r\n\
+                         --AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"second\"\r\n\
+                         \r\n\
+                         clean\r\n\
+                         --AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+                         \r\n\
+                         file--AaB03x\r\n\
+                         --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+
+    let (nodes, warnings) = read_multipart_body_with_warnings(
+        &mut &input[..], &headers, &ParseOptions::default()).unwrap();
+    assert_eq!(nodes.len(), 3);
+    assert_eq!(warnings, vec![ParseWarning::BoundaryInBody { part: 0 },
+                              ParseWarning::BoundaryInBody { part: 2 }]);
+}
+
+#[test]
+fn parse_options_builder() {
+    let options = ParseOptions::default()
+        .always_use_files(true)
+        .max_part_bytes(1024)
+        .max_header_line_len(256)
+        .detect_base64(true)
+        .invalid_content_type(InvalidContentType::Reject);
+    assert!(options.always_use_files);
+    assert_eq!(options.max_part_bytes, Some(1024));
+    assert_eq!(options.max_header_line_len, Some(256));
+    assert!(options.detect_base64);
+    assert_eq!(options.invalid_content_type, InvalidContentType::Reject);
+    assert_eq!(options.max_total_file_bytes, None);
+
+    let input: &[u8] = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                         \r\n\
+                         --AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"field\"\r\n\
+                         \r\n\
+                         value\r\n\
+                         --AaB03x--";
+    let nodes = read_multipart_with_options(&mut &input[..], &options).unwrap();
+    assert!(matches!(nodes[0], Node::File(_)));
+}
+
+#[test]
+fn boundary_with_colon_and_equals() {
+    // Quoted as they must be, and unquoted as some clients send them anyway
+    let raws: &[&[u8]] = &[
+        b"multipart/mixed; boundary=\"a=b:c\"",
+        b"multipart/mixed; boundary=\"a=b:c\"; charset=utf-8",
+        b"multipart/mixed; boundary=a=b:c",
+        b"multipart/mixed; boundary=a=b:c; charset=utf-8",
+    ];
+    for raw in raws {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", vec![raw.to_vec()]);
+        assert_eq!(get_multipart_boundary(&headers).unwrap(), b"--a=b:c");
+    }
+
+    // A typed header is written with the boundary quoted, and round-trips through a parse
+    let mut headers = Headers::new();
+    headers.set(ContentType(Mime(TopLevel::Multipart, SubLevel::Ext("mixed".to_owned()),
+                                 vec![(Attr::Boundary, Value::Ext("a=b:c".to_owned()))])));
+    let nodes = vec![Node::Part(Part::new(Headers::new(), b"x=y: z".to_vec()))];
+    let mut output: Vec<u8> = Vec::new();
+    rewrite_multipart(&mut output, &nodes, &headers).unwrap();
+    assert!(output.starts_with(b"Content-Type: multipart/mixed; boundary=\"a=b:c\"\r\n"));
+
+    let (headers, parsed) = read_multipart_with_headers(&mut &output[..], &ParseOptions::default())
+        .unwrap();
+    assert_eq!(get_multipart_boundary(&headers).unwrap(), b"--a=b:c");
+    match parsed[..] {
+        [Node::Part(ref part)] => assert_eq!(part.body, b"x=y: z"),
+        ref other => panic!("expected one part, got {:?}", other),
+    }
+}
+
+#[test]
+fn size_report_discrepancies() {
+    let input: &[u8] = b"--AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"exact\"\r\n\
+                         Content-Length: 5\r\n\
+                         \r\n\
+                         hello\r\n\
+                         --AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"undeclared\"\r\n\
+                         \r\n\
+                         free\r\n\
+                         --AaB03x\r\n\
+                         Content-Disposition: attachment; filename=\"a.txt\"; size=10\r\n\
+                         \r\n\
+                         short\r\n\
+                         --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let nodes = read_multipart_body(&mut &input[..], &headers, false).unwrap();
+
+    let report = size_report(&nodes).unwrap();
+    assert_eq!(report.parts, vec![
+        PartSize { index: 0, declared: Some(5), actual: 5 },
+        PartSize { index: 1, declared: None, actual: 4 },
+        PartSize { index: 2, declared: Some(10), actual: 5 },
+    ]);
+    assert_eq!(report.declared_total, 15);
+    assert_eq!(report.actual_total, 14);
+    let discrepant: Vec<usize> = report.discrepancies().map(|part| part.index).collect();
+    assert_eq!(discrepant, vec![2]);
+}
+
+#[test]
+fn debug_dump_tree() {
+    let mut field = Headers::new();
+    field.set_raw("Content-Disposition", vec![b"form-data; name=\"field\"".to_vec()]);
+    let mut upload = Headers::new();
+    upload.set_raw("Content-Disposition", vec![b"attachment; filename=\"a.txt\"".to_vec()]);
+    let mut nested = Headers::new();
+    nested.set_raw("Content-Type", vec![b"multipart/mixed; boundary=BbC04y".to_vec()]);
+    let nodes = vec![
+        Node::Part(Part::new(field, b"line one\nline two".to_vec())),
+        Node::Multipart((nested, vec![
+            Node::File(FilePart::from_bytes(upload, b"content".to_vec())),
+            Node::Part(Part::new(Headers::new(), vec![b'x'; 50])),
+        ])),
+    ];
+
+    let mut output: Vec<u8> = Vec::new();
+    debug_dump(&nodes, &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), format!("\
+part
+  Content-Disposition: form-data; name=\"field\"
+  body: 17 bytes \"line one\\nline two\"
+multipart (boundary \"BbC04y\")
+  Content-Type: multipart/mixed; boundary=BbC04y
+  file (in memory)
+    Content-Disposition: attachment; filename=\"a.txt\"
+    size: 7 bytes
+  part
+    body: 50 bytes \"{}\"...
+", "x".repeat(40)));
+}
+
+#[test]
+fn multipart_subtypes() {
+    let input: &[u8] = b"Content-Type: multipart/Parallel; boundary=AaB03x\r\n\
+                         \r\n\
+                         --AaB03x\r\n\
+                         Content-Type: multipart/alternative; boundary=BbC04y\r\n\
+                         \r\n\
+                         --BbC04y\r\n\
+                         \r\n\
+                         plain\r\n\
+                         --BbC04y--\r\n\
+                         --AaB03x\r\n\
+                         Content-Type: text/plain\r\n\
+                         \r\n\
+                         text\r\n\
+                         --AaB03x--";
+    let (headers, nodes) = read_multipart_with_headers(&mut &input[..], &ParseOptions::default())
+        .unwrap();
+    assert_eq!(multipart_subtype(&headers).as_deref(), Some("parallel"));
+    assert_eq!(nodes[0].multipart_subtype().as_deref(), Some("alternative"));
+    assert_eq!(nodes[1].multipart_subtype(), None);
+    assert_eq!(multipart_subtype(nodes[1].headers()), None);
+}
+
+#[test]
+fn disallowed_headers() {
+    let input: &[u8] = b"--AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"plain\"\r\n\
+                         \r\n\
+                         value\r\n\
+                         --AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"sneaky\"\r\n\
+                         content-transfer-encoding: base64\r\n\
+                         \r\n\
+                         dmFsdWU=\r\n\
+                         --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let parse = |policy: HeaderPolicy| {
+        let options = ParseOptions::default().header_policy(policy);
+        read_multipart_body_with_options(&mut &input[..], &headers, &options)
+    };
+
+    let denied = parse(HeaderPolicy::Deny(vec!["Content-Transfer-Encoding".to_owned()]));
+    match denied {
+        Err(Error::DisallowedHeader(name)) =>
+            assert!(name.eq_ignore_ascii_case("Content-Transfer-Encoding")),
+        other => panic!("expected DisallowedHeader, got {:?}", other),
+    }
+    let allowed = parse(HeaderPolicy::Allow(vec!["content-disposition".to_owned()]));
+    assert!(matches!(allowed, Err(Error::DisallowedHeader(_))));
+    let allowed = parse(HeaderPolicy::Allow(vec!["Content-Disposition".to_owned(),
+                                                 "Content-Transfer-Encoding".to_owned()]));
+    assert_eq!(allowed.unwrap().len(), 2);
+
+    // A best-effort parse skips just the offending part
+    let options = ParseOptions::default()
+        .header_policy(HeaderPolicy::Deny(vec!["Content-Transfer-Encoding".to_owned()]));
+    let (nodes, result) = read_multipart_body_best_effort(&mut &input[..], &headers, &options);
+    assert_eq!(nodes.len(), 1);
+    assert!(matches!(result, Err(Error::DisallowedHeader(_))));
+}
+
+#[test]
+fn decoded_readers() {
+    // A base64 body larger than the decoder reads at once
+    let data: Vec<u8> = (0..20000u32).map(|i| (i * 7 % 256) as u8).collect();
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Transfer-Encoding", vec![b"base64".to_vec()]);
+    let nodes = vec![Node::Part(Part::new(headers, data.clone()))];
+    let options = WriteOptions { apply_transfer_encoding: true, ..WriteOptions::default() };
+    let mut body: Vec<u8> = Vec::new();
+    write_multipart_with_options(&mut body, b"AaB03x", &nodes, &options).unwrap();
+
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/mixed; boundary=AaB03x".to_vec()]);
+    let parsed = read_multipart_body(&mut &body[..], &headers, false).unwrap();
+    let part = match parsed[0] {
+        Node::Part(ref part) => part,
+        ref other => panic!("expected a part, got {:?}", other),
+    };
+    assert_ne!(part.body, data);
+    let mut decoded: Vec<u8> = Vec::new();
+    part.decoded_reader().unwrap().read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, data);
+
+    // Quoted-printable, with a soft line break and an `=` which begins no escape
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Transfer-Encoding", vec![b"Quoted-Printable".to_vec()]);
+    let filepart = FilePart::from_bytes(headers.clone(),
+                                        b"caf=C3=A9 =\r\nsoft = literal=".to_vec());
+    let mut decoded = String::new();
+    filepart.decoded_reader().unwrap().read_to_string(&mut decoded).unwrap();
+    assert_eq!(decoded, "café soft = literal=");
+
+    // An escape must be two hex digits, without a sign
+    let filepart = FilePart::from_bytes(headers, b"a=+Fb=-1".to_vec());
+    let mut decoded = String::new();
+    filepart.decoded_reader().unwrap().read_to_string(&mut decoded).unwrap();
+    assert_eq!(decoded, "a=+Fb=-1");
+
+    // Unencoded bodies are read as they are, and unknown encodings are refused
+    let part = Part::new(Headers::new(), b"plain".to_vec());
+    let mut decoded: Vec<u8> = Vec::new();
+    part.decoded_reader().unwrap().read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, b"plain");
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Transfer-Encoding", vec![b"x-uuencode".to_vec()]);
+    assert!(matches!(Part::new(headers, Vec::new()).decoded_reader(), Err(Error::Decoding(_))));
+}
+
+#[test]
+fn commit_files_all_or_nothing() {
+    let input: &[u8] = b"--AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"a\"; filename=\"a.txt\"\r\n\
+                         \r\n\
+                         first\r\n\
+                         --AaB03x\r\n\
+                         Content-Disposition: form-data; name=\"b\"; filename=\"b.txt\"\r\n\
+                         \r\n\
+                         second\r\n\
+                         --AaB03x--";
+    let mut headers = Headers::new();
+    headers.set_raw("Content-Type", vec![b"multipart/form-data; boundary=AaB03x".to_vec()]);
+    let mut nodes = read_multipart_body(&mut &input[..], &headers, false).unwrap();
+    nodes.push(Node::File(FilePart::from_bytes(Headers::new(), b"third".to_vec())));
+    let dest = tempfile::tempdir().unwrap();
+    let name = |filepart: &FilePart| -> PathBuf {
+        filepart.filename().unwrap().unwrap_or_else(|| "c.txt".to_owned()).into()
+    };
+
+    // A name which is taken fails the commit, undoing the files committed before it
+    ::std::fs::write(dest.path().join("b.txt"), b"existing").unwrap();
+    assert!(commit_files(&mut nodes, dest.path(), name).is_err());
+    assert!(! dest.path().join("a.txt").exists());
+    assert_eq!(::std::fs::read(dest.path().join("b.txt")).unwrap(), b"existing");
+    let temporary: Vec<PathBuf> = files(&nodes).map(|filepart| filepart.path.clone()).collect();
+    assert_eq!(::std::fs::read(&temporary[0]).unwrap(), b"first");
+
+    // As does a name which is not a plain file name
+    assert!(commit_files(&mut nodes, dest.path(), |_: &FilePart| PathBuf::from("../x")).is_err());
+
+    ::std::fs::remove_file(dest.path().join("b.txt")).unwrap();
+    let committed = commit_files(&mut nodes, dest.path(), name).unwrap();
+    assert_eq!(committed, vec![dest.path().join("a.txt"), dest.path().join("b.txt"),
+                               dest.path().join("c.txt")]);
+    assert!(! temporary[0].exists());
+    drop(nodes);
+    assert_eq!(::std::fs::read(&committed[0]).unwrap(), b"first");
+    assert_eq!(::std::fs::read(&committed[1]).unwrap(), b"second");
+    assert_eq!(::std::fs::read(&committed[2]).unwrap(), b"third");
+}
+
+#[test]
+fn field_and_file_budgets() {
+    let input = b"Content-Type: multipart/form-data; boundary=AaB03x\r\n\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+        one\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+        two\r\n\
+        --AaB03x\r\n\
+        Content-Type: multipart/mixed; boundary=BbC04y\r\n\r\n\
+        --BbC04y\r\n\
+        Content-Disposition: attachment; filename=\"c.txt\"\r\n\r\n\
+        three\r\n\
+        --BbC04y--\r\n\
+        --AaB03x--";
+    let parse = |budget: ParseBudget| read_multipart_with_budget(&mut &input[..], false, budget);
+
+    let exact = ParseBudget { max_memory_parts: Some(2), max_file_parts: Some(1),
+                              ..ParseBudget::default() };
+    assert_eq!(parse(exact).unwrap().len(), 3);
+
+    let fields = ParseBudget { max_memory_parts: Some(1), max_file_parts: Some(10),
+                               ..ParseBudget::default() };
+    assert!(matches!(parse(fields), Err(Error::TooManyFields)));
+    let files = ParseBudget { max_memory_parts: Some(10), max_file_parts: Some(0),
+                              ..ParseBudget::default() };
+    assert!(matches!(parse(files), Err(Error::TooManyFiles)));
+    assert_eq!(Error::TooManyFiles.kind(), ErrorKind::Limit);
+}
@@ -0,0 +1,699 @@
+// Copyright 2016-2020 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Content-Transfer-Encoding support.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use hyper::header::Headers;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// The Content-Transfer-Encoding of a part, lowercased, if it has one
+pub(crate) fn transfer_encoding(headers: &Headers) -> Option<String> {
+    let raw = headers.get_raw("Content-Transfer-Encoding")?.first()?;
+    let value = String::from_utf8_lossy(super::params::trim(raw)).to_ascii_lowercase();
+    Some(value)
+}
+
+// Base64 encodes everything written to it into the inner writer, breaking the output into
+// lines of at most `line_length` characters (or not at all if it is 0).  `finish()` must be
+// called to write out the final characters and padding.
+pub(crate) struct Base64Writer<'a, W: ?Sized> {
+    inner: &'a mut W,
+    line_length: usize,
+    line_ending: &'a [u8],
+    pending: [u8; 3],
+    pending_len: usize,
+    column: usize,
+    // The number of bytes written to the inner writer
+    pub(crate) count: usize,
+}
+
+impl<'a, W: Write + ?Sized> Base64Writer<'a, W> {
+    pub(crate) fn new(inner: &'a mut W, line_length: usize, line_ending: &'a [u8])
+        -> Base64Writer<'a, W>
+    {
+        Base64Writer {
+            inner,
+            line_length,
+            line_ending,
+            pending: [0; 3],
+            pending_len: 0,
+            column: 0,
+            count: 0,
+        }
+    }
+
+    // Write out any final partial group with padding.  Returns the number of bytes written
+    // to the inner writer in total.
+    pub(crate) fn finish(mut self) -> io::Result<usize> {
+        if self.pending_len > 0 {
+            let mut group = [0u8; 3];
+            group[..self.pending_len].copy_from_slice(&self.pending[..self.pending_len]);
+            let mut chars = encode_group(&group);
+            for c in chars.iter_mut().skip(self.pending_len + 1) {
+                *c = b'=';
+            }
+            self.write_chars(&chars)?;
+        }
+        Ok(self.count)
+    }
+
+    fn write_chars(&mut self, chars: &[u8]) -> io::Result<()> {
+        let mut out: Vec<u8> = Vec::with_capacity(chars.len() * 2);
+        for &c in chars {
+            if self.line_length > 0 && self.column == self.line_length {
+                out.extend_from_slice(self.line_ending);
+                self.column = 0;
+            }
+            out.push(c);
+            self.column += 1;
+        }
+        self.inner.write_all(&out)?;
+        self.count += out.len();
+        Ok(())
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for Base64Writer<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = buf;
+
+        // Complete any partial group from the last write
+        while self.pending_len > 0 && self.pending_len < 3 && ! input.is_empty() {
+            self.pending[self.pending_len] = input[0];
+            self.pending_len += 1;
+            input = &input[1..];
+        }
+        if self.pending_len == 3 {
+            let chars = encode_group(&self.pending);
+            self.pending_len = 0;
+            self.write_chars(&chars)?;
+        }
+
+        // Encode all the whole groups
+        let whole = input.len() / 3 * 3;
+        let mut chars: Vec<u8> = Vec::with_capacity(whole / 3 * 4);
+        for group in input[..whole].chunks(3) {
+            chars.extend_from_slice(&encode_group(&[group[0], group[1], group[2]]));
+        }
+        self.write_chars(&chars)?;
+
+        // Keep the remainder for next time
+        let rest = &input[whole..];
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len += rest.len();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Converts bare LFs written to it into CRLFs on their way to the inner writer, leaving
+// existing CRLFs alone, to put text into the canonical form MIME requires.
+pub(crate) struct CrlfWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    after_cr: bool,
+    // The number of bytes written to the inner writer
+    pub(crate) count: usize,
+}
+
+impl<'a, W: Write + ?Sized> CrlfWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> CrlfWriter<'a, W> {
+        CrlfWriter {
+            inner,
+            after_cr: false,
+            count: 0,
+        }
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for CrlfWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out: Vec<u8> = Vec::with_capacity(buf.len() + buf.len() / 16);
+        for &b in buf {
+            if b == b'\n' && ! self.after_cr {
+                out.push(b'\r');
+            }
+            out.push(b);
+            self.after_cr = b == b'\r';
+        }
+        self.inner.write_all(&out)?;
+        self.count += out.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Applies SMTP dot-stuffing (RFC 5321 section 4.5.2) to everything written to it, doubling
+// the `.` at the start of any line, so that the output can be sent as SMTP DATA.  Writing is
+// taken to start at the start of a line.
+pub(crate) struct DotStuffWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    at_line_start: bool,
+    // The number of bytes written to the inner writer
+    pub(crate) count: usize,
+}
+
+impl<'a, W: Write + ?Sized> DotStuffWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> DotStuffWriter<'a, W> {
+        DotStuffWriter {
+            inner,
+            at_line_start: true,
+            count: 0,
+        }
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for DotStuffWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out: Vec<u8> = Vec::with_capacity(buf.len() + 8);
+        for &b in buf {
+            if self.at_line_start && b == b'.' {
+                out.push(b'.');
+            }
+            out.push(b);
+            self.at_line_start = b == b'\n';
+        }
+        self.inner.write_all(&out)?;
+        self.count += out.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// The longest a quoted-printable line may be, not counting the line ending
+const QP_LINE_LENGTH: usize = 76;
+
+// Quoted-printable encodes everything written to it into the inner writer.  CRLFs are taken
+// to be line breaks and written as `line_ending`; all other control characters, `=` and
+// non-ASCII bytes are escaped, as is whitespace at the end of a line.  Lines longer than 76
+// characters are broken with soft line breaks.  `finish()` must be called to write out any
+// held back whitespace or CR.
+pub(crate) struct QpWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    line_ending: &'a [u8],
+    column: usize,
+    // Whitespace which is escaped only if the line ends straight after it
+    pending_ws: Option<u8>,
+    // A CR which is a line break only if an LF follows it
+    pending_cr: bool,
+    // The number of bytes written to the inner writer
+    pub(crate) count: usize,
+}
+
+impl<'a, W: Write + ?Sized> QpWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W, line_ending: &'a [u8]) -> QpWriter<'a, W> {
+        QpWriter {
+            inner,
+            line_ending,
+            column: 0,
+            pending_ws: None,
+            pending_cr: false,
+            count: 0,
+        }
+    }
+
+    // Write out any held back whitespace or CR.  Returns the number of bytes written to the
+    // inner writer in total.
+    pub(crate) fn finish(mut self) -> io::Result<usize> {
+        let mut out: Vec<u8> = Vec::new();
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.release_ws(&mut out, false);
+            self.push_token(&mut out, &qp_escape(b'\r'));
+        }
+        self.release_ws(&mut out, true);
+        self.inner.write_all(&out)?;
+        self.count += out.len();
+        Ok(self.count)
+    }
+
+    // Write out held back whitespace, escaped if it ends a line
+    fn release_ws(&mut self, out: &mut Vec<u8>, at_line_end: bool) {
+        if let Some(ws) = self.pending_ws.take() {
+            if at_line_end {
+                self.push_token(out, &qp_escape(ws));
+            } else {
+                self.push_token(out, &[ws]);
+            }
+        }
+    }
+
+    // Add a literal character or escape, first breaking the line if it would not fit with
+    // room left for the `=` of a soft line break.
+    fn push_token(&mut self, out: &mut Vec<u8>, token: &[u8]) {
+        if self.column + token.len() > QP_LINE_LENGTH - 1 {
+            out.push(b'=');
+            out.extend_from_slice(self.line_ending);
+            self.column = 0;
+        }
+        out.extend_from_slice(token);
+        self.column += token.len();
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for QpWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out: Vec<u8> = Vec::with_capacity(buf.len() + buf.len() / 8);
+        for &b in buf {
+            if self.pending_cr {
+                self.pending_cr = false;
+                if b == b'\n' {
+                    self.release_ws(&mut out, true);
+                    out.extend_from_slice(self.line_ending);
+                    self.column = 0;
+                    continue;
+                }
+                self.release_ws(&mut out, false);
+                self.push_token(&mut out, &qp_escape(b'\r'));
+            }
+            match b {
+                b'\r' => self.pending_cr = true,
+                b' ' | b'\t' => {
+                    self.release_ws(&mut out, false);
+                    self.pending_ws = Some(b);
+                },
+                33..=60 | 62..=126 => {
+                    self.release_ws(&mut out, false);
+                    self.push_token(&mut out, &[b]);
+                },
+                _ => {
+                    self.release_ws(&mut out, false);
+                    self.push_token(&mut out, &qp_escape(b));
+                },
+            }
+        }
+        self.inner.write_all(&out)?;
+        self.count += out.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn qp_escape(b: u8) -> [u8; 3] {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    [b'=', HEX[(b >> 4) as usize], HEX[(b & 15) as usize]]
+}
+
+fn encode_group(group: &[u8; 3]) -> [u8; 4] {
+    let n = (group[0] as usize) << 16 | (group[1] as usize) << 8 | group[2] as usize;
+    [
+        BASE64_ALPHABET[n >> 18 & 63],
+        BASE64_ALPHABET[n >> 12 & 63],
+        BASE64_ALPHABET[n >> 6 & 63],
+        BASE64_ALPHABET[n & 63],
+    ]
+}
+
+fn decode_char(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+// Decides, from everything written to it, whether a body which has no
+// Content-Transfer-Encoding is nonetheless base64 encoded.  This is deliberately strict, so
+// that text which merely happens to use only base64 characters is not mistaken for it: the
+// body must be lines of base64 characters, all but the last of the same length (a multiple
+// of 4, and at most 76 as MIME requires), with `=` padding only at the very end, and a
+// total length which is a multiple of 4.  A body of a single line qualifies only if it is
+// padded.  Blank lines are allowed only at the end.
+pub(crate) struct Base64Sniffer {
+    valid: bool,
+    after_cr: bool,
+    after_blank_line: bool,
+    line_len: usize,
+    first_line_len: usize,
+    last_line_len: usize,
+    lines: usize,
+    chars: usize,
+    padding: usize,
+}
+
+impl Base64Sniffer {
+    pub(crate) fn new() -> Base64Sniffer {
+        Base64Sniffer {
+            valid: true,
+            after_cr: false,
+            after_blank_line: false,
+            line_len: 0,
+            first_line_len: 0,
+            last_line_len: 0,
+            lines: 0,
+            chars: 0,
+            padding: 0,
+        }
+    }
+
+    fn end_line(&mut self) {
+        if self.line_len == 0 {
+            self.after_blank_line = true;
+            return;
+        }
+        if self.after_blank_line {
+            self.valid = false;
+        }
+        if self.lines == 0 {
+            self.first_line_len = self.line_len;
+        } else if self.last_line_len != self.first_line_len {
+            // The previous line was short, but was not the last
+            self.valid = false;
+        }
+        self.last_line_len = self.line_len;
+        self.lines += 1;
+        self.line_len = 0;
+    }
+
+    fn push(&mut self, c: u8) {
+        if self.after_cr && c != b'\n' {
+            self.valid = false;
+        }
+        self.after_cr = c == b'\r';
+        match c {
+            b'\r' => {},
+            b'\n' => self.end_line(),
+            b'=' => {
+                self.padding += 1;
+                self.line_len += 1;
+                self.chars += 1;
+            },
+            c if decode_char(c).is_some() && self.padding == 0 => {
+                self.line_len += 1;
+                self.chars += 1;
+            },
+            _ => self.valid = false,
+        }
+    }
+
+    // Whether everything written was base64, by the rules above.  (`is_multiple_of()` would
+    // need Rust 1.87.)
+    #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+    pub(crate) fn finish(mut self) -> bool {
+        if self.after_cr {
+            return false;
+        }
+        self.end_line();
+        self.valid &&
+            self.lines > 0 &&
+            self.first_line_len % 4 == 0 &&
+            self.first_line_len <= 76 &&
+            self.last_line_len <= self.first_line_len &&
+            self.chars % 4 == 0 &&
+            self.padding <= 2 &&
+            (self.lines > 1 || self.padding > 0)
+    }
+}
+
+impl Write for Base64Sniffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.valid {
+            for &c in buf {
+                self.push(c);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Base64 decodes everything written to it into the inner writer, ignoring line breaks.
+// `finish()` must be called to check that the input ended on a whole group.
+pub(crate) struct Base64Decoder<'a, W: ?Sized> {
+    inner: &'a mut W,
+    group: [u8; 4],
+    group_len: usize,
+    // The number of bytes written to the inner writer
+    count: u64,
+}
+
+impl<'a, W: Write + ?Sized> Base64Decoder<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> Base64Decoder<'a, W> {
+        Base64Decoder {
+            inner,
+            group: [0; 4],
+            group_len: 0,
+            count: 0,
+        }
+    }
+
+    // Returns the number of bytes written to the inner writer in total.
+    pub(crate) fn finish(self) -> io::Result<u64> {
+        if self.group_len != 0 {
+            return Err(invalid_base64());
+        }
+        Ok(self.count)
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for Base64Decoder<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut out: Vec<u8> = Vec::with_capacity(buf.len() / 4 * 3 + 3);
+        for &c in buf {
+            if c == b'\r' || c == b'\n' {
+                continue;
+            }
+            self.group[self.group_len] = c;
+            self.group_len += 1;
+            if self.group_len == 4 {
+                self.group_len = 0;
+                decode_group(&self.group, &mut out)?;
+            }
+        }
+        self.inner.write_all(&out)?;
+        self.count += out.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Decode a group of four base64 characters, which may end in `=` padding, onto `out`
+fn decode_group(group: &[u8; 4], out: &mut Vec<u8>) -> io::Result<()> {
+    let padding = group.iter().rev().take_while(|&&c| c == b'=').count();
+    if padding > 2 {
+        return Err(invalid_base64());
+    }
+    let mut n: usize = 0;
+    for &c in &group[..4 - padding] {
+        n = n << 6 | decode_char(c).ok_or_else(invalid_base64)? as usize;
+    }
+    n <<= 6 * padding;
+    let bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+    out.extend_from_slice(&bytes[..3 - padding]);
+    Ok(())
+}
+
+fn invalid_base64() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid base64")
+}
+
+// Decode an in-memory body if it appears to be base64 (see `Base64Sniffer`), otherwise
+// return it unchanged
+pub(crate) fn decode_if_base64(body: Vec<u8>) -> Vec<u8> {
+    let mut sniffer = Base64Sniffer::new();
+    let _ = sniffer.write_all(&body);
+    if ! sniffer.finish() {
+        return body;
+    }
+    let mut decoded: Vec<u8> = Vec::with_capacity(body.len() / 4 * 3);
+    let mut decoder = Base64Decoder::new(&mut decoded);
+    match decoder.write_all(&body).and_then(|_| decoder.finish()) {
+        Ok(_) => decoded,
+        Err(_) => body,
+    }
+}
+
+// Decode the file at `path` in place if it appears to be base64 (see `Base64Sniffer`).
+// Returns the decoded size, or `None` if the file was left unchanged.
+pub(crate) fn decode_file_if_base64(path: &Path) -> io::Result<Option<u64>> {
+    let mut sniffer = Base64Sniffer::new();
+    io::copy(&mut File::open(path)?, &mut sniffer)?;
+    if ! sniffer.finish() {
+        return Ok(None);
+    }
+    let mut decoded_path = path.as_os_str().to_owned();
+    decoded_path.push(".decoded");
+    let decoded_path = PathBuf::from(decoded_path);
+    // Never replace a file already there, which is not ours to remove
+    let mut file = OpenOptions::new().write(true).create_new(true).open(&decoded_path)?;
+    let decoded = {
+        let mut decoder = Base64Decoder::new(&mut file);
+        File::open(path).and_then(|mut source| io::copy(&mut source, &mut decoder))
+            .and_then(|_| decoder.finish())
+    };
+    drop(file);
+    let result = decoded.and_then(|size| fs::rename(&decoded_path, path).map(|_| size));
+    if result.is_err() {
+        let _ = fs::remove_file(&decoded_path);
+    }
+    result.map(Some)
+}
+
+// Decode an anonymous temporary file if it appears to be base64, as
+// `decode_file_if_base64()` does, into a new anonymous file.  Returns the new file and the
+// decoded size, or `None` if the file is to be left unchanged.
+pub(crate) fn decode_anonymous_if_base64(file: &File) -> io::Result<Option<(File, u64)>> {
+    let rewound = |file: &File| -> io::Result<File> {
+        let mut file = file.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    };
+    let mut sniffer = Base64Sniffer::new();
+    io::copy(&mut rewound(file)?, &mut sniffer)?;
+    if ! sniffer.finish() {
+        return Ok(None);
+    }
+    let mut decoded = tempfile::tempfile()?;
+    let mut decoder = Base64Decoder::new(&mut decoded);
+    io::copy(&mut rewound(file)?, &mut decoder)?;
+    let size = decoder.finish()?;
+    Ok(Some((decoded, size)))
+}
+
+// The encodings `DecodingReader` decodes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Decoding {
+    Base64,
+    QuotedPrintable,
+}
+
+impl Decoding {
+    // The decoding a part needs, from its `Content-Transfer-Encoding`: `Ok(None)` if the body
+    // is not encoded (it has none, or `7bit`, `8bit` or `binary`), or the unknown encoding
+    pub(crate) fn for_headers(headers: &Headers) -> Result<Option<Decoding>, String> {
+        match transfer_encoding(headers).as_deref() {
+            None | Some("7bit") | Some("8bit") | Some("binary") => Ok(None),
+            Some("base64") => Ok(Some(Decoding::Base64)),
+            Some("quoted-printable") => Ok(Some(Decoding::QuotedPrintable)),
+            Some(other) => Err(other.to_owned()),
+        }
+    }
+}
+
+// Decodes base64 or quoted-printable from the inner reader as it is read, a chunk at a time,
+// so that a large encoded body need not be decoded all at once.  Line breaks within base64
+// are ignored, and quoted-printable soft line breaks are removed.  A malformed body fails
+// the read with `io::ErrorKind::InvalidData`, except that an `=` in quoted-printable which
+// does not begin an escape is taken literally.
+pub(crate) struct DecodingReader<R> {
+    inner: R,
+    decoding: Decoding,
+    // Encoded input which cannot be decoded until more is read
+    pending: Vec<u8>,
+    // Decoded output which has not yet been read, from `pos`
+    output: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> DecodingReader<R> {
+    pub(crate) fn new(inner: R, decoding: Decoding) -> DecodingReader<R> {
+        DecodingReader {
+            inner,
+            decoding,
+            pending: Vec::new(),
+            output: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    // Decode as much of the pending input as can be, or all of it at the end of the input
+    fn decode(&mut self) -> io::Result<()> {
+        match self.decoding {
+            Decoding::Base64 => {
+                self.pending.retain(|&c| ! c.is_ascii_whitespace());
+                let whole = self.pending.len() / 4 * 4;
+                if self.eof && whole != self.pending.len() {
+                    return Err(invalid_base64());
+                }
+                for group in self.pending[..whole].chunks(4) {
+                    decode_group(&[group[0], group[1], group[2], group[3]], &mut self.output)?;
+                }
+                self.pending.drain(..whole);
+            },
+            Decoding::QuotedPrintable => {
+                let mut i = 0;
+                while i < self.pending.len() {
+                    let rest = &self.pending[i..];
+                    if rest[0] != b'=' {
+                        self.output.push(rest[0]);
+                        i += 1;
+                    } else if rest.starts_with(b"=\r\n") {
+                        i += 3;
+                    } else if rest.starts_with(b"=\n") {
+                        i += 2;
+                    } else if rest.len() < 3 && ! self.eof {
+                        break;
+                    } else if let Some(b) = rest.get(1..3).and_then(qp_unescape) {
+                        self.output.push(b);
+                        i += 3;
+                    } else {
+                        self.output.push(b'=');
+                        i += 1;
+                    }
+                }
+                self.pending.drain(..i);
+            },
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.output.len() && ! self.eof {
+            self.output.clear();
+            self.pos = 0;
+            let mut chunk = [0; 8192];
+            let n = self.inner.read(&mut chunk)?;
+            self.eof = n == 0;
+            self.pending.extend_from_slice(&chunk[..n]);
+            self.decode()?;
+        }
+        let n = ::std::cmp::min(buf.len(), self.output.len() - self.pos);
+        buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// The byte a quoted-printable escape's two hex digits give.  (`from_str_radix()` alone
+// would also take a sign, as in `=+F`.)
+fn qp_unescape(hex: &[u8]) -> Option<u8> {
+    if ! hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let hex = ::std::str::from_utf8(hex).ok()?;
+    u8::from_str_radix(hex, 16).ok()
+}